@@ -1,9 +1,11 @@
+use crate::errors::GenericError;
 use crate::test;
 use chrono::Local;
 use log::error;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::env;
+use std::error::Error;
 use std::path::Path;
 
 #[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
@@ -11,6 +13,8 @@ use std::path::Path;
 pub struct Config {
     pub settings: Settings,
     pub globals: BTreeMap<String, String>,
+    // base urls by environment name, for `jk compare`
+    pub environments: BTreeMap<String, String>,
 }
 
 #[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
@@ -20,6 +24,18 @@ pub struct Settings {
     pub environment: Option<String>,
     #[serde(skip_serializing)]
     pub api_key: Option<String>,
+    pub cleanup_failure: CleanupFailure,
+}
+
+// how a cleanup request which doesn't get its expected response is reported
+#[derive(PartialEq, Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CleanupFailure {
+    // logged as a warning, the test keeps its result
+    #[default]
+    Warn,
+    // fails the test
+    Fail,
 }
 
 #[derive(Deserialize)]
@@ -27,6 +43,7 @@ pub struct Settings {
 struct File {
     pub settings: Option<FileSettings>,
     pub globals: Option<BTreeMap<String, String>>,
+    pub environments: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Deserialize)]
@@ -35,6 +52,7 @@ struct FileSettings {
     pub continue_on_failure: Option<bool>,
     pub api_key: Option<String>,
     pub environment: Option<String>,
+    pub cleanup_failure: Option<CleanupFailure>,
 }
 
 impl Config {
@@ -68,8 +86,10 @@ impl Default for Config {
                 continue_on_failure: false,
                 api_key: None,
                 environment: None,
+                cleanup_failure: CleanupFailure::Warn,
             },
             globals: BTreeMap::new(),
+            environments: BTreeMap::new(),
         }
     }
 }
@@ -81,41 +101,66 @@ pub async fn get_config() -> Config {
         Some(load_config_from_environment_variables_as_file()),
     ];
 
-    return get_config_impl(config_sources_ascending_priority);
+    get_config_impl(config_sources_ascending_priority)
+}
+
+// like `get_config`, but a config file that exists and can't be loaded is an error rather
+// than being skipped, so a run doesn't quietly use the wrong settings
+pub async fn try_get_config() -> Result<Config, Box<dyn Error + Send + Sync>> {
+    let mut config_sources_ascending_priority = Vec::new();
+
+    if let Some(home_file) = home_file_path() {
+        config_sources_ascending_priority.push(read_config_file(&home_file).await?);
+    }
+
+    config_sources_ascending_priority.push(read_config_file(".jikken").await?);
+    config_sources_ascending_priority.push(Some(load_config_from_environment_variables_as_file()));
+
+    Ok(get_config_impl(config_sources_ascending_priority))
 }
 
 fn get_config_impl(config_sources_ascending_priority: Vec<Option<File>>) -> Config {
-    return config_sources_ascending_priority
+    config_sources_ascending_priority
         .into_iter()
-        .fold(Config::default(), apply_config_file);
+        .fold(Config::default(), apply_config_file)
 }
 
-async fn load_config_file(file: &str) -> Option<File> {
+async fn read_config_file(file: &str) -> Result<Option<File>, Box<dyn Error + Send + Sync>> {
     if !Path::new(file).exists() {
-        return None;
+        return Ok(None);
     }
 
-    match tokio::fs::read_to_string(file).await {
-        Ok(data) => {
-            let config_result: Result<File, _> = toml::from_str(&data);
-            match config_result {
-                Ok(config) => Some(config),
-                Err(e) => {
-                    error!("unable to load config file ({}): {}", file, e);
-                    None
-                }
-            }
-        }
+    let config = tokio::fs::read_to_string(file)
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|data| toml::from_str::<File>(&data).map_err(|e| e.to_string()));
+
+    match config {
+        Ok(c) => Ok(Some(c)),
+        Err(e) => Err(Box::new(GenericError {
+            reason: format!("unable to load config file ({}): {}", file, e),
+        })),
+    }
+}
+
+async fn load_config_file(file: &str) -> Option<File> {
+    match read_config_file(file).await {
+        Ok(config) => config,
         Err(e) => {
-            error!("unable to load config file ({}): {}", file, e);
+            error!("{}", e);
             None
         }
     }
 }
 
+fn home_file_path() -> Option<String> {
+    dirs::home_dir()
+        .map(|pb| pb.join(".jikken"))
+        .and_then(|p| p.to_str().map(|s| s.to_string()))
+}
+
 async fn load_home_file() -> Option<File> {
-    let cfg_file = dirs::home_dir().map(|pb| pb.join(".jikken"));
-    return load_config_file(cfg_file?.as_path().to_str()?).await;
+    load_config_file(&home_file_path()?).await
 }
 
 fn load_config_from_environment_variables_as_file() -> File {
@@ -127,6 +172,15 @@ fn load_config_from_environment_variables_as_file() -> File {
 
     let envvar_env = env::var("JIKKEN_ENVIRONMENT").ok();
 
+    let envvar_cleanup =
+        env::var("JIKKEN_CLEANUP_FAILURE")
+            .ok()
+            .and_then(|cfg| match cfg.to_lowercase().as_str() {
+                "warn" => Some(CleanupFailure::Warn),
+                "fail" => Some(CleanupFailure::Fail),
+                _ => None,
+            });
+
     let mut global_variables = BTreeMap::new();
 
     for (key, value) in env::vars() {
@@ -135,14 +189,16 @@ fn load_config_from_environment_variables_as_file() -> File {
         }
     }
 
-    return File {
+    File {
         settings: Some(FileSettings {
             api_key: envvar_apikey,
             continue_on_failure: envvar_cof,
             environment: envvar_env,
+            cleanup_failure: envvar_cleanup,
         }),
         globals: Some(global_variables),
-    };
+        environments: None,
+    }
 }
 
 fn apply_config_file(config: Config, file_opt: Option<File>) -> Config {
@@ -152,6 +208,11 @@ fn apply_config_file(config: Config, file_opt: Option<File>) -> Config {
             .into_iter()
             .chain(file.globals.unwrap_or_default())
             .collect();
+        let merged_environments: BTreeMap<String, String> = config
+            .environments
+            .into_iter()
+            .chain(file.environments.unwrap_or_default())
+            .collect();
 
         if let Some(settings) = file.settings {
             return Config {
@@ -161,14 +222,19 @@ fn apply_config_file(config: Config, file_opt: Option<File>) -> Config {
                         .unwrap_or(config.settings.continue_on_failure),
                     api_key: settings.api_key.or(config.settings.api_key),
                     environment: settings.environment.or(config.settings.environment),
+                    cleanup_failure: settings
+                        .cleanup_failure
+                        .unwrap_or(config.settings.cleanup_failure),
                 },
                 globals: merged_globals,
+                environments: merged_environments,
             };
         }
 
         return Config {
             settings: config.settings,
             globals: merged_globals,
+            environments: merged_environments,
         };
     }
 
@@ -220,11 +286,13 @@ mod tests {
                     continue_on_failure: true,
                     api_key: None,
                     environment: None,
+                    cleanup_failure: CleanupFailure::Warn,
                 },
                 globals: BTreeMap::from([(
                     String::from("my_override_global"),
                     String::from("foo")
-                )])
+                )]),
+                environments: BTreeMap::new(),
             },
             actual
         );
@@ -253,6 +321,10 @@ mod tests {
             [globals]
             my_override_global="foo"
             my_override_global2="bar"
+
+            [environments]
+            prod="https://api.jikken.io"
+            staging="https://staging.jikken.io"
             "#
             .as_bytes(),
         )
@@ -268,10 +340,14 @@ mod tests {
             [settings]
             continueOnFailure=false
             environment="magic"
+            cleanupFailure="fail"
 
             [globals]
             my_override_global="bar"
             my_override_global3="car"
+
+            [environments]
+            staging="https://canary.jikken.io"
             "#
             .as_bytes(),
         )
@@ -288,12 +364,20 @@ mod tests {
                     continue_on_failure: false,
                     api_key: Some(String::from("key")),
                     environment: Some(String::from("magic")),
+                    cleanup_failure: CleanupFailure::Fail,
                 },
                 globals: BTreeMap::from([
                     (String::from("my_override_global"), String::from("bar")),
                     (String::from("my_override_global2"), String::from("bar")),
                     (String::from("my_override_global3"), String::from("car"))
                 ]),
+                environments: BTreeMap::from([
+                    (String::from("prod"), String::from("https://api.jikken.io")),
+                    (
+                        String::from("staging"),
+                        String::from("https://canary.jikken.io")
+                    )
+                ]),
             },
             actual
         );
@@ -1,33 +1,184 @@
+pub mod interrupt;
+pub mod load;
+pub mod record;
+pub mod select;
+pub mod shadow;
+pub mod stream;
+pub mod suite;
+pub mod summary;
+
 use crate::config;
-use crate::errors::TestFailure;
+use crate::errors::GenericError;
+use crate::export;
+use crate::json::diff;
 use crate::json::extractor::extract_json;
 use crate::json::filter::filter_json;
+use crate::logger;
 use crate::telemetry;
 use crate::test;
 use crate::test::definition::ResponseDescriptor;
 use crate::test::http;
 use crate::test::{definition, validation};
-use crate::TagMode;
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
 use hyper::header::HeaderValue;
 use hyper::{body, Body, Client, Request};
 use hyper_tls::HttpsConnector;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use serde::Serialize;
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::{self, Write};
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
 use url::Url;
 
+// process exit codes for `jk run`, when several apply the highest is used
+pub const EXIT_PASSED: i32 = 0;
+pub const EXIT_FAILED: i32 = 1;
+pub const EXIT_INVALID: i32 = 2;
+pub const EXIT_ERROR: i32 = 3;
+// the usual code for a process stopped by Ctrl-C
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+// once a run is interrupted, requests still waiting for a response are given up after this
+const INTERRUPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
 pub struct Report {
     pub run: u16,
     pub passed: u16,
     pub failed: u16,
+    // iterations that didn't run: marked `skip`, in a suite whose setup failed,
+    // or not reached because the run stopped early
+    pub skipped: u16,
+    // iterations that couldn't complete, e.g. a request couldn't be sent
+    pub errored: u16,
+    // iterations marked with `xfail` that failed or passed
+    pub xfailed: u16,
+    pub xpassed: u16,
+    // test files that couldn't be loaded or failed validation
+    pub invalid: u16,
+    // stopped by Ctrl-C or SIGTERM before every test ran
+    pub interrupted: bool,
+}
+
+impl Report {
+    pub fn exit_code(&self) -> i32 {
+        if self.interrupted {
+            EXIT_INTERRUPTED
+        } else if self.errored > 0 {
+            EXIT_ERROR
+        } else if self.invalid > 0 {
+            EXIT_INVALID
+        } else if self.failed > 0 {
+            EXIT_FAILED
+        } else {
+            EXIT_PASSED
+        }
+    }
 }
 
 struct State {
     variables: HashMap<String, String>,
+    base_url: Option<String>,
+    // whether an interrupt stops the remaining stages, teardown runs regardless
+    interruptible: bool,
+    cleanup_failure: config::CleanupFailure,
+    // spaces requests out to the rate given to `jk load`
+    pacer: Option<Arc<load::Pacer>>,
+    // set when the run is interrupted
+    interrupted: interrupt::Flag,
+    // how much longer a request may take once the run is interrupted
+    interrupt_timeout: Duration,
+    clients: Clients,
+}
+
+// clients keep their connections open between requests, so one set is shared by every
+// test of a run and every worker of `jk load`
+#[derive(Clone)]
+struct Clients {
+    http: Client<HttpsConnector<HttpConnector>>,
+    // gRPC is spoken over HTTP/2 from the start, without upgrading from HTTP/1.1
+    grpc: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl Clients {
+    fn new() -> Clients {
+        Clients {
+            http: Client::builder().build(HttpsConnector::new()),
+            grpc: Client::builder().http2_only(true).build(h2_connector()),
+        }
+    }
+}
+
+// servers only speak HTTP/2 over TLS when the client offers it through ALPN, which the
+// default connector doesn't
+fn h2_connector() -> HttpsConnector<HttpConnector> {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+
+    match native_tls::TlsConnector::builder()
+        .request_alpns(&["h2"])
+        .build()
+    {
+        Ok(tls) => HttpsConnector::from((http, tls.into())),
+        Err(e) => {
+            warn!(
+                "unable to offer HTTP/2 over TLS, gRPC calls to https urls may fail: {}",
+                e
+            );
+            HttpsConnector::new()
+        }
+    }
+}
+
+// settings for `jk run` that change what happens after each test
+#[derive(Default, Clone)]
+pub struct RunOptions {
+    pub show_curl: bool,
+    pub record: Option<record::Mode>,
+    pub record_headers: Vec<String>,
+    pub base_url: Option<String>,
+    pub progress: Option<UnboundedSender<Progress>>,
+    // overrides the `continueOnFailure` setting when given
+    pub continue_on_failure: Option<bool>,
+}
+
+#[derive(Clone)]
+pub struct PlannedTest {
+    pub id: String,
+    pub name: String,
+    pub filename: String,
+    pub requires: Option<String>,
+    pub iterations: u32,
+    // dataset row labels, one per iteration
+    pub labels: Vec<String>,
+}
+
+// sent while tests run so an interactive runner can follow along. `test` indexes the
+// planned tests, messages are the warnings and errors logged while the test ran
+#[derive(Clone)]
+pub enum Progress {
+    Planned(Vec<PlannedTest>),
+    Started {
+        test: usize,
+        iteration: u32,
+    },
+    Finished {
+        test: usize,
+        iteration: u32,
+        passed: bool,
+        results: Vec<StageResult>,
+        messages: Vec<String>,
+    },
+    // the test was marked `skip` or its suite's setup failed
+    Skipped {
+        test: usize,
+        reason: String,
+    },
 }
 
 #[derive(PartialEq, Eq, Clone)]
@@ -41,13 +192,30 @@ pub enum StageType {
 pub enum TestStatus {
     Passed = 1,
     Failed = 2,
+    Skipped = 3,
+    // failed while marked with `xfail`
+    ExpectedFailure = 4,
+    // passed while marked with `xfail`
+    UnexpectedPass = 5,
+    // a cleanup request failed while the `cleanupFailure` setting is `warn`
+    Warning = 6,
+}
+
+// how an iteration of a test ended
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Outcome {
+    Passed,
+    Failed,
+    Errored,
+    ExpectedFailure,
+    UnexpectedPass,
 }
 
 #[derive(Clone, Serialize)]
 pub struct ResultData {
-    headers: Vec<http::Header>,
-    status: u16,
-    body: serde_json::Value,
+    pub headers: Vec<http::Header>,
+    pub status: u16,
+    pub body: serde_json::Value,
 }
 
 impl ResultData {
@@ -71,7 +239,10 @@ impl ResultData {
         ResultData::default()
     }
 
-    pub async fn from_response(resp: hyper::Response<Body>) -> Option<ResultData> {
+    pub async fn from_response(
+        resp: hyper::Response<Body>,
+        grpc: Option<&test::grpc::Method>,
+    ) -> Option<ResultData> {
         let response_status = resp.status();
         // TODO: We'll have to revisit this to support non-ASCII headers
         let headers = resp
@@ -80,6 +251,12 @@ impl ResultData {
             .map(|h| http::Header::new(h.0.to_string(), h.1.to_str().unwrap_or("").to_string()))
             .collect();
         let (_, body) = resp.into_parts();
+
+        if let Some(method) = grpc {
+            return ResultData::from_grpc_response(response_status.as_u16(), headers, body, method)
+                .await;
+        }
+
         let response_bytes = body::to_bytes(body).await;
 
         match response_bytes {
@@ -106,23 +283,65 @@ impl ResultData {
             }
         }
     }
+
+    // a call's metadata is its headers and trailers together, and its status is the gRPC
+    // status rather than the HTTP one
+    async fn from_grpc_response(
+        http_status: u16,
+        mut headers: Vec<http::Header>,
+        mut body: Body,
+        method: &test::grpc::Method,
+    ) -> Option<ResultData> {
+        let mut frame = Vec::new();
+
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(c) => frame.extend_from_slice(&c),
+                Err(e) => {
+                    error!("unable to get response bytes: {}", e);
+                    return None;
+                }
+            }
+        }
+
+        match body.trailers().await {
+            Ok(trailers) => headers.extend(trailers.iter().flatten().map(|h| {
+                http::Header::new(h.0.to_string(), h.1.to_str().unwrap_or("").to_string())
+            })),
+            Err(e) => {
+                error!("unable to get response trailers: {}", e);
+                return None;
+            }
+        }
+
+        let body = method.decode(&frame).unwrap_or_else(|e| {
+            debug!("{}", e);
+            serde_json::Value::Null
+        });
+
+        Some(ResultData {
+            status: test::grpc::status(&headers, http_status),
+            headers,
+            body,
+        })
+    }
 }
 
 #[derive(Clone, Serialize)]
 pub struct RequestDetails {
-    headers: Vec<http::Header>,
-    url: String,
-    method: http::Method,
-    body: serde_json::Value,
+    pub headers: Vec<http::Header>,
+    pub url: String,
+    pub method: http::Method,
+    pub body: serde_json::Value,
 }
 
 #[derive(Clone, Serialize)]
 pub struct ResultDetails {
-    request: RequestDetails,
-    expected: ResultData,
-    actual: Option<ResultData>,
-    compare_request: Option<RequestDetails>,
-    compare_actual: Option<ResultData>,
+    pub request: RequestDetails,
+    pub expected: ResultData,
+    pub actual: Option<ResultData>,
+    pub compare_request: Option<RequestDetails>,
+    pub compare_actual: Option<ResultData>,
 }
 
 #[derive(Clone)]
@@ -132,19 +351,73 @@ pub struct StageResult {
     pub runtime: u32,
     pub status: TestStatus,
     pub details: ResultDetails,
+    pub failures: Vec<Failure>,
 }
 
-pub async fn execute_tests(
-    config: config::Config,
-    files: Vec<String>,
-    mode_dryrun: bool,
-    tags: Vec<String>,
-    tag_mode: TagMode,
-    cli_args: Box<serde_json::Value>,
-) -> Report {
-    let global_variables = config.generate_global_variables();
+impl StageResult {
+    pub fn label(&self) -> String {
+        match self.stage_type {
+            StageType::Setup => "setup".to_string(),
+            StageType::Normal => format!("stage {}", self.stage + 1),
+            StageType::Cleanup => "cleanup".to_string(),
+        }
+    }
+}
+
+// why a stage failed, kept for the summary printed at the end of a run
+#[derive(Clone)]
+pub enum Failure {
+    NoResponse,
+    Status {
+        expected: u16,
+        actual: u16,
+    },
+    HeaderMissing {
+        header: String,
+        expected: String,
+    },
+    Header {
+        header: String,
+        expected: String,
+        actual: String,
+    },
+    Body(Vec<diff::Difference>),
+    CompareStatus {
+        compare: u16,
+        actual: u16,
+    },
+    CompareBody(Vec<diff::Difference>),
+    // a missing value means the response didn't have the header
+    CompareHeader {
+        header: String,
+        compare: Option<String>,
+        actual: Option<String>,
+    },
+    // a message from the `errors` of a GraphQL response
+    Graphql(String),
+    GrpcStatus {
+        expected: u16,
+        actual: u16,
+        message: String,
+    },
+    // a stream message which didn't arrive, with how the last message received differed
+    Unreceived {
+        message: usize,
+        reason: String,
+        differences: Vec<diff::Difference>,
+    },
+    Error(String),
+}
+
+// loads and validates the test files, returning the selected tests in execution order along
+// with the number of files which couldn't be loaded or failed validation
+pub fn plan_tests(
+    files: &[String],
+    global_variables: &[test::Variable],
+    filter: &select::Filter,
+) -> (Vec<test::Definition>, u16) {
     let mut tests_to_ignore: Vec<test::Definition> = Vec::new();
-    let mut tests_to_run: Vec<test::Definition> = files
+    let loaded_files: Vec<test::File> = files
         .iter()
         .filter_map(|filename| {
             let result = test::file::load(filename);
@@ -156,48 +429,25 @@ pub async fn execute_tests(
                 }
             }
         })
+        .collect();
+    let mut invalid_count = (files.len() - loaded_files.len()) as u16;
+    let mut tests_to_run: Vec<test::Definition> = loaded_files
+        .into_iter()
         .filter_map(|f| {
             let name = f.name.clone().unwrap_or_else(|| f.filename.clone());
-            let result = validation::validate_file(f, &global_variables);
+            let result = validation::validate_file(f, global_variables);
             match result {
                 Ok(td) => {
-                    if !tags.is_empty() {
-                        let td_tags: HashSet<String> = HashSet::from_iter(td.clone().tags);
-                        match tag_mode {
-                            TagMode::OR => {
-                                for t in tags.iter() {
-                                    if td_tags.contains(t) {
-                                        return Some(td);
-                                    }
-                                }
-
-                                tests_to_ignore.push(td);
-
-                                debug!(
-                                    "test `{}` doesn't match any tags: {}",
-                                    name,
-                                    tags.join(", ")
-                                );
-
-                                return None;
-                            }
-                            TagMode::AND => {
-                                for t in tags.iter() {
-                                    if !td_tags.contains(t) {
-                                        tests_to_ignore.push(td);
-
-                                        debug!("test `{}` is missing tag: {}", name, t);
-                                        return None;
-                                    }
-                                }
-                            }
-                        }
+                    if filter.matches(&td) {
+                        Some(td)
+                    } else {
+                        tests_to_ignore.push(td);
+                        None
                     }
-
-                    Some(td)
                 }
                 Err(e) => {
                     error!("test ({}) failed validation: {}", name, e);
+                    invalid_count += 1;
                     None
                 }
             }
@@ -205,38 +455,69 @@ pub async fn execute_tests(
         .collect();
 
     if !tests_to_ignore.is_empty() {
-        trace!("filtering out tests which don't match the tag pattern")
+        trace!("filtering out tests which don't match the selection")
     }
 
-    let tests_by_id: HashMap<String, test::Definition> = tests_to_run
-        .clone()
-        .into_iter()
-        .chain(tests_to_ignore.into_iter())
-        .map(|td| (td.id.clone(), td))
-        .collect();
-
-    tests_to_run.sort_by(|a, b| a.name.partial_cmp(&b.name).unwrap());
-
-    let mut duplicate_filter: HashSet<String> = HashSet::new();
-    let mut tests_to_run_with_dependencies: Vec<test::Definition> = Vec::new();
+    if tests_to_run.iter().any(|td| td.only) {
+        trace!("filtering out tests which aren't focused with `only`");
+        let (focused, unfocused): (Vec<test::Definition>, Vec<test::Definition>) =
+            tests_to_run.into_iter().partition(|td| td.only);
+        tests_to_run = focused;
+        tests_to_ignore.extend(unfocused);
+    }
 
     trace!("determine test execution order based on dependency graph");
 
-    for td in tests_to_run.into_iter() {
-        if let Some(req) = &td.requires {
-            if tests_by_id.contains_key(req) && !duplicate_filter.contains(req) {
-                duplicate_filter.insert(req.clone());
-                tests_to_run_with_dependencies.push(tests_by_id.get(req).unwrap().to_owned());
-            }
-        }
+    (
+        select::with_dependencies(tests_to_run, tests_to_ignore),
+        invalid_count,
+    )
+}
 
-        if !duplicate_filter.contains(&td.id) {
-            duplicate_filter.insert(td.id.clone());
-            tests_to_run_with_dependencies.push(td);
-        }
+pub async fn execute_tests(
+    config: config::Config,
+    files: Vec<String>,
+    mode_dryrun: bool,
+    filter: select::Filter,
+    cli_args: Box<serde_json::Value>,
+    options: RunOptions,
+) -> Report {
+    let global_variables = config.generate_global_variables();
+    let (tests_to_run_with_dependencies, mut invalid_count) =
+        plan_tests(&files, &global_variables, &filter);
+    let mut suites = Vec::new();
+
+    if !mode_dryrun {
+        let (found, suite_invalid_count) =
+            suite::discover(&tests_to_run_with_dependencies, &global_variables);
+        suites = found;
+        invalid_count += suite_invalid_count;
     }
 
     let total_count = tests_to_run_with_dependencies.len();
+    let total_iterations: u16 = tests_to_run_with_dependencies
+        .iter()
+        .map(|td| td.iterate as u16)
+        .sum();
+    let continue_on_failure = options
+        .continue_on_failure
+        .unwrap_or(config.settings.continue_on_failure);
+
+    if let Some(progress) = &options.progress {
+        let planned = tests_to_run_with_dependencies
+            .iter()
+            .enumerate()
+            .map(|(i, td)| PlannedTest {
+                id: td.id.clone(),
+                name: td.name.clone().unwrap_or(format!("Test {}", i + 1)),
+                filename: td.filename.clone(),
+                requires: td.requires.clone(),
+                iterations: td.iterate,
+                labels: td.labels.clone(),
+            })
+            .collect();
+        _ = progress.send(Progress::Planned(planned));
+    }
     let mut session: Option<telemetry::Session> = None;
 
     if !mode_dryrun {
@@ -256,55 +537,105 @@ pub async fn execute_tests(
         }
     }
 
+    let listener = (!mode_dryrun).then(interrupt::listen);
     let mut state = State {
         variables: HashMap::new(),
+        base_url: options.base_url.clone(),
+        interruptible: true,
+        cleanup_failure: config.settings.cleanup_failure,
+        pacer: None,
+        interrupted: listener
+            .as_ref()
+            .map(interrupt::Listener::flag)
+            .unwrap_or_default(),
+        interrupt_timeout: INTERRUPT_TIMEOUT,
+        clients: Clients::new(),
     };
 
     let mut run_count: u16 = 0;
     let mut passed_count: u16 = 0;
     let mut failed_count: u16 = 0;
+    let mut errored_count: u16 = 0;
+    let mut xfailed_count: u16 = 0;
+    let mut xpassed_count: u16 = 0;
 
     let start_time = Instant::now();
     let mut break_early = false;
+    let mut failed_tests: Vec<summary::FailedTest> = suite::set_up(&mut state, &mut suites).await;
+    errored_count += failed_tests.len() as u16;
 
     for (i, td) in tests_to_run_with_dependencies.into_iter().enumerate() {
-        if break_early {
+        if break_early || state.interrupted.is_set() {
             break;
         }
 
+        let skip_reason = suite::failed_suite(&suites, &td)
+            .map(|suite| format!("suite setup failed (`{}`)", suite.filename))
+            .or_else(|| td.skip.clone());
+
+        if let Some(reason) = skip_reason {
+            info!(
+                "Skipping Test ({}\\{}) `{}`: {}\n",
+                i + 1,
+                total_count,
+                td.name.clone().unwrap_or(format!("Test {}", i + 1)),
+                reason
+            );
+
+            if let Some(s) = &session {
+                report_skipped(s, &td).await;
+            }
+
+            if let Some(progress) = &options.progress {
+                _ = progress.send(Progress::Skipped { test: i, reason });
+            }
+            continue;
+        }
+
         for iteration in 0..td.iterate {
+            if state.interrupted.is_set() {
+                break;
+            }
+
             run_count += 1;
 
-            let mut passed = true;
+            let mut outcome = Outcome::Passed;
 
             if mode_dryrun {
                 info!(
-                    "Dry Run Test ({}\\{}) `{}` Iteration({}\\{})\n",
+                    "Dry Run Test ({}\\{}) `{}` Iteration({}\\{}){}\n",
                     i + 1,
                     total_count,
                     td.name.clone().unwrap_or(format!("Test {}", i + 1)),
                     iteration + 1,
                     td.iterate,
+                    td.iteration_label(iteration),
                 );
 
                 let result = dry_run(&state, &td, iteration).await;
 
                 if let Err(e) = result {
-                    passed = false;
+                    outcome = Outcome::Failed;
                     error!("{}", e);
                 }
             } else {
                 info!(
-                    "Running Test ({}\\{}) `{}` Iteration ({}\\{})...",
+                    "Running Test ({}\\{}) `{}` Iteration ({}\\{}){}...",
                     i + 1,
                     total_count,
                     td.name.clone().unwrap_or(format!("Test {}", i + 1)),
                     iteration + 1,
                     td.iterate,
+                    td.iteration_label(iteration),
                 );
                 io::stdout().flush().unwrap();
                 debug!(""); // print a new line if we're in debug | trace mode
 
+                if let Some(progress) = &options.progress {
+                    logger::take_captured();
+                    _ = progress.send(Progress::Started { test: i, iteration });
+                }
+
                 let test = if let Some(s) = &session {
                     match telemetry::create_test(s, &td).await {
                         Ok(t) => Some(t),
@@ -318,51 +649,126 @@ pub async fn execute_tests(
                 };
 
                 let result = run(&mut state, &td, iteration, test).await;
+                let mut stage_results = Vec::new();
+                let mut run_error = None;
 
                 match result {
                     Ok(p) => {
-                        if p.0 {
-                            info!("\x1b[32mPASSED\x1b[0m\n");
-                        } else {
-                            info!("\x1b[31mFAILED\x1b[0m\n");
-                            passed = false;
+                        outcome = test_outcome(&td, p.0, &p.1);
+
+                        match outcome {
+                            Outcome::Passed => info!("\x1b[32mPASSED\x1b[0m\n"),
+                            Outcome::ExpectedFailure => info!(
+                                "\x1b[33mXFAIL\x1b[0m{}\n",
+                                td.xfail
+                                    .as_ref()
+                                    .map(|r| format!(" ({})", r))
+                                    .unwrap_or_default()
+                            ),
+                            Outcome::UnexpectedPass => info!(
+                                "\x1b[33mXPASS\x1b[0m{}\n",
+                                td.xfail
+                                    .as_ref()
+                                    .map(|r| format!(" ({})", r))
+                                    .unwrap_or_default()
+                            ),
+                            _ => {
+                                info!("\x1b[31mFAILED\x1b[0m\n");
+
+                                if options.show_curl {
+                                    let origin = test_origin(&state, &td, iteration);
+                                    print_failed_requests(&state, origin.as_ref(), &p.1);
+                                }
+                            }
+                        }
+
+                        // iterations share response blocks, so only the first is recorded
+                        if let (Some(mode), 0) = (options.record, iteration) {
+                            record_results(&td, &p.1, mode, &options.record_headers);
                         }
+
+                        stage_results = p.1;
                     }
                     Err(e) => {
                         info!("\x1b[31mFAILED\x1b[0m\n");
                         error!("{}", e);
-                        passed = false;
+                        outcome = Outcome::Errored;
+                        run_error = Some(e.to_string());
                     }
                 }
-            };
 
-            if passed {
-                passed_count += 1;
-            } else {
-                failed_count += 1;
-            }
+                if matches!(outcome, Outcome::Failed | Outcome::Errored) {
+                    failed_tests.push(summary::FailedTest {
+                        name: td.name.clone().unwrap_or(format!("Test {}", i + 1)),
+                        filename: td.filename.clone(),
+                        iteration,
+                        iterations: td.iterate,
+                        label: td.iteration_label(iteration),
+                        stages: stage_results
+                            .iter()
+                            .filter(|r| r.status == TestStatus::Failed)
+                            .cloned()
+                            .collect(),
+                        error: run_error,
+                    });
+                }
 
-            if !config.settings.continue_on_failure && !passed {
-                if let Some(s) = &session {
-                    let runtime = start_time.elapsed().as_millis() as u32;
-                    _ = telemetry::complete_session(s, runtime, 2).await;
+                if let Some(progress) = &options.progress {
+                    _ = progress.send(Progress::Finished {
+                        test: i,
+                        iteration,
+                        passed: !matches!(outcome, Outcome::Failed | Outcome::Errored),
+                        results: stage_results,
+                        messages: logger::take_captured(),
+                    });
                 }
+            };
 
+            match outcome {
+                Outcome::Passed => passed_count += 1,
+                Outcome::Failed => failed_count += 1,
+                Outcome::Errored => errored_count += 1,
+                Outcome::ExpectedFailure => xfailed_count += 1,
+                Outcome::UnexpectedPass => xpassed_count += 1,
+            }
+
+            if !continue_on_failure && matches!(outcome, Outcome::Failed | Outcome::Errored) {
                 break_early = true;
                 break;
             }
         }
     }
 
+    let teardown_failures = suite::tear_down(&mut state, &suites).await;
+    errored_count += teardown_failures.len() as u16;
+    failed_tests.extend(teardown_failures);
+
+    let interrupted = state.interrupted.is_set();
+
     if let Some(s) = &session {
         let runtime = start_time.elapsed().as_millis() as u32;
-        _ = telemetry::complete_session(s, runtime, 1).await;
+        let status = if interrupted {
+            telemetry::SESSION_ABORTED
+        } else if break_early {
+            telemetry::SESSION_STOPPED
+        } else {
+            telemetry::SESSION_COMPLETED
+        };
+        _ = telemetry::complete_session(s, runtime, status).await;
     }
 
+    summary::print(&failed_tests);
+
     Report {
         run: run_count,
         passed: passed_count,
         failed: failed_count,
+        skipped: total_iterations - run_count,
+        errored: errored_count,
+        xfailed: xfailed_count,
+        xpassed: xpassed_count,
+        invalid: invalid_count,
+        interrupted,
     }
 }
 
@@ -373,18 +779,28 @@ async fn run(
     test: Option<telemetry::Test>,
 ) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>> {
     let mut results = Vec::new();
-    let mut setup_result = validate_setup(state, td, iteration).await?;
-    if let Some(test_telemetry) = &test {
-        if !setup_result.1.is_empty() {
-            let telemetry_result =
-                telemetry::complete_stage(test_telemetry, iteration, &setup_result.1[0]).await;
-            if let Err(e) = telemetry_result {
-                debug!("telemetry stage completion failed: {}", e);
+    let mut success = false;
+    let mut td_error = None;
+
+    match validate_setup(state, td, iteration).await {
+        Ok(mut setup_result) => {
+            if let Some(test_telemetry) = &test {
+                for result in setup_result.1.iter() {
+                    let telemetry_result =
+                        telemetry::complete_stage(test_telemetry, iteration, result).await;
+                    if let Err(e) = telemetry_result {
+                        debug!("telemetry stage completion failed: {}", e);
+                    }
+                }
             }
+            results.append(&mut setup_result.1);
+            success = setup_result.0;
+        }
+        Err(e) => {
+            trace!("setup error: {}", e);
+            td_error = Some(e);
         }
     }
-    results.append(&mut setup_result.1);
-    let mut success = setup_result.0;
 
     if success {
         let td_results = validate_td(state, td, iteration, test.clone()).await;
@@ -397,34 +813,68 @@ async fn run(
             Err(e) => {
                 trace!("td validation error: {}", e);
                 success = false;
+                td_error = Some(e);
             }
         }
+    }
 
-        let cleanup_result = run_cleanup(state, td, iteration, success, results.len() as u32).await;
-        match cleanup_result {
-            Ok(mut r) => {
-                if let Some(test_telemetry) = &test {
-                    for result in r.1.iter() {
-                        let telemetry_result =
-                            telemetry::complete_stage(test_telemetry, iteration, result).await;
-                        if let Err(e) = telemetry_result {
-                            debug!("telemetry stage completion failed: {}", e);
-                        }
-                    }
-                }
-                results.append(&mut r.1);
-                success &= r.0;
-            }
-            Err(e) => {
-                trace!("cleanup validation error: {}", e);
-                success = false;
+    // a failed setup may have created some of what the test needs, so cleanup runs after
+    // it too, with the `onfailure` steps
+    let mut cleanup_result = run_cleanup(state, td, iteration, success, results.len() as u32).await;
+    if let Some(test_telemetry) = &test {
+        for result in cleanup_result.1.iter() {
+            let telemetry_result =
+                telemetry::complete_stage(test_telemetry, iteration, result).await;
+            if let Err(e) = telemetry_result {
+                debug!("telemetry stage completion failed: {}", e);
             }
         }
     }
+    results.append(&mut cleanup_result.1);
+    success &= cleanup_result.0;
+
+    // cleanup still runs when a stage couldn't complete, the error is reported afterwards
+    if let Some(e) = td_error {
+        return Err(e);
+    }
 
     Ok((success, results))
 }
 
+// stages or tests marked with `xfail` don't fail the run, but are reported separately
+fn test_outcome(td: &test::Definition, passed: bool, results: &[StageResult]) -> Outcome {
+    // a failed setup step means the test couldn't run, rather than that it failed
+    if results
+        .iter()
+        .any(|r| r.stage_type == StageType::Setup && r.status == TestStatus::Failed)
+    {
+        return Outcome::Errored;
+    }
+
+    if !passed {
+        return if td.xfail.is_some() {
+            Outcome::ExpectedFailure
+        } else {
+            Outcome::Failed
+        };
+    }
+
+    let expected_failure = results
+        .iter()
+        .any(|r| r.status == TestStatus::ExpectedFailure);
+    let unexpected_pass = results
+        .iter()
+        .any(|r| r.status == TestStatus::UnexpectedPass);
+
+    if expected_failure {
+        Outcome::ExpectedFailure
+    } else if td.xfail.is_some() || unexpected_pass {
+        Outcome::UnexpectedPass
+    } else {
+        Outcome::Passed
+    }
+}
+
 async fn dry_run(
     state: &State,
     td: &test::Definition,
@@ -437,7 +887,8 @@ fn validate_body(
     actual: &Value,
     expected: &Value,
     ignore: &[String],
-) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    rules: &diff::Rules,
+) -> Result<Vec<diff::Difference>, Box<dyn Error + Send + Sync>> {
     trace!("validating response body");
     let mut modified_actual = actual.clone();
     let mut modified_expected = expected.clone();
@@ -450,29 +901,99 @@ fn validate_body(
     }
 
     trace!("compare json");
-    let r = modified_actual == modified_expected;
+    if modified_actual == modified_expected {
+        return Ok(Vec::new());
+    }
 
-    if !r {
-        let result = assert_json_diff::assert_json_matches_no_panic(
-            &modified_actual,
-            &modified_expected,
-            assert_json_diff::Config::new(assert_json_diff::CompareMode::Strict),
-        );
-        match result {
-            Ok(_) => {
-                return Err(Box::from(TestFailure {
-                    reason: "response body doesn't match".to_string(),
-                }));
-            }
-            Err(msg) => {
-                return Err(Box::from(TestFailure {
-                    reason: format!("response body doesn't match\n{}", msg),
-                }));
+    let differences = diff::differences(&modified_expected, &modified_actual, rules);
+    if differences.is_empty() {
+        return Ok(differences);
+    }
+
+    let result = assert_json_diff::assert_json_matches_no_panic(
+        &modified_actual,
+        &modified_expected,
+        assert_json_diff::Config::new(assert_json_diff::CompareMode::Strict),
+    );
+    match result {
+        Ok(_) => error!("response body doesn't match"),
+        Err(msg) => error!("response body doesn't match\n{}", msg),
+    }
+
+    Ok(differences)
+}
+
+fn header_value(headers: &[http::Header], name: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|h| h.header.eq_ignore_ascii_case(name))
+        .map(|h| h.value.clone())
+}
+
+// checks the stage's response against the compare request's response, using the rules of
+// the compare block's `response` section
+fn validate_compare(
+    actual: &ResultData,
+    compare: &ResultData,
+    rules: &definition::CompareResponseDescriptor,
+    stage_ignore: &[String],
+) -> Vec<Failure> {
+    let mut failures = Vec::new();
+
+    trace!("validating compare status");
+    if compare.status != actual.status {
+        failures.push(Failure::CompareStatus {
+            compare: compare.status,
+            actual: actual.status,
+        });
+    }
+
+    if rules.compares_headers() {
+        trace!("validating compare headers");
+        let mut names: Vec<String> = if rules.headers.is_empty() {
+            compare
+                .headers
+                .iter()
+                .chain(actual.headers.iter())
+                .map(|h| h.header.to_lowercase())
+                .collect()
+        } else {
+            rules.headers.iter().map(|h| h.to_lowercase()).collect()
+        };
+        names.sort();
+        names.dedup();
+
+        for name in names.into_iter().filter(|n| {
+            !rules
+                .ignore_headers
+                .iter()
+                .any(|i| i.eq_ignore_ascii_case(n))
+        }) {
+            let compare_value = header_value(&compare.headers, &name);
+            let actual_value = header_value(&actual.headers, &name);
+
+            if compare_value != actual_value {
+                failures.push(Failure::CompareHeader {
+                    header: name,
+                    compare: compare_value,
+                    actual: actual_value,
+                });
             }
         }
     }
 
-    Ok(r)
+    trace!("validating compare body");
+    let ignore = rules.ignore.as_deref().unwrap_or(stage_ignore);
+    match validate_body(&actual.body, &compare.body, ignore, &rules.rules()) {
+        Ok(differences) if differences.is_empty() => {}
+        Ok(differences) => failures.push(Failure::CompareBody(differences)),
+        Err(e) => {
+            error!("{}", e);
+            failures.push(Failure::Error(e.to_string()));
+        }
+    }
+
+    failures
 }
 
 async fn validate_td(
@@ -482,117 +1003,306 @@ async fn validate_td(
     test: Option<telemetry::Test>,
 ) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>> {
     let mut results = Vec::new();
+    let focused = td.stages.iter().any(|s| s.only);
 
     for (stage_index, stage) in td.stages.iter().enumerate() {
-        let stage_result = validate_stage(state, td, stage, stage_index, iteration).await?;
+        if stage.skip.is_some() || (focused && !stage.only) {
+            debug!(
+                "skipping stage {}: {}",
+                stage_index + 1,
+                stage.skip.as_deref().unwrap_or("not focused")
+            );
+            results.push(skipped_stage(stage, stage_index));
+            continue;
+        }
 
-        if let Some(test_telemetry) = &test {
-            let telemetry_result =
-                telemetry::complete_stage(test_telemetry, iteration, &stage_result).await;
-            if let Err(e) = telemetry_result {
-                debug!("telemetry stage completion failed: {}", e);
+        if let Some(condition) = &stage.condition {
+            if !condition.evaluate(&state.variables) {
+                debug!("skipping stage {}: `if` didn't hold", stage_index + 1);
+                results.push(skipped_stage(stage, stage_index));
+                continue;
             }
         }
 
-        let failed = stage_result.status == TestStatus::Failed;
-        results.push(stage_result);
+        let items = match &stage.repeat {
+            Some(definition::Repeat::ForEach { variable, .. }) => for_each_items(state, variable)?,
+            _ => Vec::new(),
+        };
+        let mut runs: u32 = 0;
+
+        loop {
+            match &stage.repeat {
+                Some(definition::Repeat::While(c)) if !c.evaluate(&state.variables) => break,
+                Some(definition::Repeat::ForEach { item, .. }) => match items.get(runs as usize) {
+                    Some(value) => {
+                        state.variables.insert(item.clone(), value.clone());
+                    }
+                    None => break,
+                },
+                _ => {}
+            }
 
-        if failed {
-            return Ok((false, results));
+            // a repeated stage that would run again past its limit fails with its last run
+            if runs == stage.max_iterations {
+                if let Some(last) = results.last_mut() {
+                    let reason = format!(
+                        "stage {} stopped after {} iterations, the limit set by `maxIterations`",
+                        stage_index + 1,
+                        runs
+                    );
+                    last.status = TestStatus::Failed;
+                    last.failures.push(Failure::Error(reason));
+                }
+
+                return Ok((false, results));
+            }
+
+            // the in-flight request finishes, later stages are left so cleanup can run
+            if state.interruptible && state.interrupted.is_set() {
+                return Err(Box::from(GenericError {
+                    reason: format!("interrupted before stage {}", stage_index + 1),
+                }));
+            }
+
+            runs += 1;
+            let mut stage_result = validate_stage(state, td, stage, stage_index, iteration).await?;
+
+            if stage.xfail.is_some() {
+                stage_result.status = if stage_result.status == TestStatus::Failed {
+                    TestStatus::ExpectedFailure
+                } else {
+                    TestStatus::UnexpectedPass
+                };
+            }
+
+            if let Some(test_telemetry) = &test {
+                let telemetry_result =
+                    telemetry::complete_stage(test_telemetry, iteration, &stage_result).await;
+                if let Err(e) = telemetry_result {
+                    debug!("telemetry stage completion failed: {}", e);
+                }
+            }
+
+            let failed = stage_result.status == TestStatus::Failed;
+            results.push(stage_result);
+
+            if failed {
+                return Ok((false, results));
+            }
+
+            match &stage.repeat {
+                None => break,
+                Some(definition::Repeat::Until(c)) if c.evaluate(&state.variables) => break,
+                _ => {}
+            }
         }
     }
 
     Ok((true, results))
 }
 
+// the elements of a list extracted earlier, an unset variable has none
+fn for_each_items(state: &State, variable: &str) -> Result<Vec<String>, GenericError> {
+    let value = match state.variables.get(variable) {
+        Some(v) if !v.is_empty() => v,
+        _ => return Ok(Vec::new()),
+    };
+
+    match serde_json::from_str(value) {
+        Ok(serde_json::Value::Array(items)) => Ok(items.into_iter().map(state_value).collect()),
+        _ => Err(GenericError {
+            reason: format!("`forEach` variable `{}` is not a list: {}", variable, value),
+        }),
+    }
+}
+
+// extracted values are kept as text, lists and objects as JSON so `forEach` can use them
+fn state_value(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s,
+        serde_json::Value::Null => "".to_string(),
+        other => other.to_string(),
+    }
+}
+
+// skipped tests are still sent to telemetry, with every stage of every iteration skipped
+async fn report_skipped(session: &telemetry::Session, td: &test::Definition) {
+    let test = match telemetry::create_test(session, td).await {
+        Ok(t) => t,
+        Err(e) => {
+            debug!("telemetry failed: {}", e);
+            return;
+        }
+    };
+
+    for iteration in 0..td.iterate {
+        for (stage_index, stage) in td.stages.iter().enumerate() {
+            let result = skipped_stage(stage, stage_index);
+            if let Err(e) = telemetry::complete_stage(&test, iteration, &result).await {
+                debug!("telemetry stage completion failed: {}", e);
+            }
+        }
+    }
+}
+
+fn skipped_stage(stage: &definition::StageDescriptor, stage_index: usize) -> StageResult {
+    StageResult {
+        stage: stage_index as u32,
+        stage_type: StageType::Normal,
+        runtime: 0,
+        status: TestStatus::Skipped,
+        details: ResultDetails {
+            request: RequestDetails {
+                headers: Vec::new(),
+                url: stage.request.url.clone(),
+                method: stage.request.method.as_method(),
+                body: serde_json::Value::Null,
+            },
+            expected: ResultData::default(),
+            actual: None,
+            compare_request: None,
+            compare_actual: None,
+        },
+        failures: Vec::new(),
+    }
+}
+
+fn validate_headers(expected: &[http::Header], actual: &[http::Header]) -> Vec<Failure> {
+    trace!("validating headers");
+    expected
+        .iter()
+        .filter_map(|e| {
+            match actual
+                .iter()
+                .find(|a| a.header.eq_ignore_ascii_case(&e.header))
+            {
+                None => Some(Failure::HeaderMissing {
+                    header: e.header.clone(),
+                    expected: e.value.clone(),
+                }),
+                Some(a) if a.value != e.value => Some(Failure::Header {
+                    header: e.header.clone(),
+                    expected: e.value.clone(),
+                    actual: a.value.clone(),
+                }),
+                Some(_) => None,
+            }
+        })
+        .collect()
+}
+
 fn process_response(
     stage: u32,
     stage_type: StageType,
     runtime: u32,
-    details: ResultDetails,
+    mut details: ResultDetails,
     ignore_body: &[String],
+    request: &definition::RequestDescriptor,
 ) -> StageResult {
-    let mut result = StageResult {
-        stage,
-        stage_type,
-        runtime,
-        details: details.clone(),
-        status: TestStatus::Passed,
-    };
+    let mut failures = Vec::new();
+
+    if let (true, Some(resp)) = (request.graphql, details.actual.as_mut()) {
+        trace!("validating graphql errors");
+        failures.extend(
+            test::graphql::errors(&resp.body)
+                .into_iter()
+                .map(Failure::Graphql),
+        );
+        resp.body = test::graphql::data(&resp.body);
+    }
 
     if let Some(resp) = &details.actual {
-        let header_match = if !details.expected.headers.is_empty() {
-            // compare headers
-            trace!("validating headers");
-            true
-        } else {
-            true
-        };
-
-        let status_match = if details.expected.status > 0 {
+        // calls are expected to succeed, so an OK status is checked for when none is given
+        if request.grpc.is_some() && details.expected.status != resp.status {
+            trace!("validating grpc status");
+            failures.push(Failure::GrpcStatus {
+                expected: details.expected.status,
+                actual: resp.status,
+                message: test::grpc::message(&resp.headers),
+            });
+        } else if request.grpc.is_none()
+            && details.expected.status > 0
+            && details.expected.status != resp.status
+        {
             trace!("validating status codes");
-            details.expected.status == resp.status
-        } else {
-            true
-        };
+            failures.push(Failure::Status {
+                expected: details.expected.status,
+                actual: resp.status,
+            });
+        }
 
-        let body_match = if details.expected.body != serde_json::Value::Null {
+        failures.append(&mut validate_headers(
+            &details.expected.headers,
+            &resp.headers,
+        ));
+
+        if details.expected.body != serde_json::Value::Null {
             trace!("validating body");
-            let body_result = validate_body(&resp.body, &details.expected.body, ignore_body);
-            match body_result {
-                Ok(passed) => passed,
+            match validate_body(
+                &resp.body,
+                &details.expected.body,
+                ignore_body,
+                &diff::Rules::default(),
+            ) {
+                Ok(differences) if differences.is_empty() => {}
+                Ok(differences) => failures.push(Failure::Body(differences)),
                 Err(e) => {
                     error!("{}", e);
-                    false
+                    failures.push(Failure::Error(e.to_string()));
                 }
             }
-        } else {
-            true
-        };
-
-        let mut status_compare_match = true;
-        let mut body_compare_match = true;
-
-        if let Some(compare) = &details.compare_actual {
-            trace!("validating compare status");
-            status_compare_match = compare.status == resp.status;
-
-            trace!("validating compare body");
-            let body_result = validate_body(&resp.body, &compare.body, ignore_body);
-            body_compare_match = match body_result {
-                Ok(passed) => passed,
-                Err(e) => {
-                    error!("{}", e);
-                    false
-                }
-            };
-        }
-
-        if !header_match
-            || !status_match
-            || !body_match
-            || !status_compare_match
-            || !body_compare_match
-        {
-            result.status = TestStatus::Failed;
         }
     } else if !details.expected.headers.is_empty()
         || details.expected.status > 0
         || details.expected.body != serde_json::Value::Null
     {
-        // failed
-        result.status = TestStatus::Failed;
+        failures.push(Failure::NoResponse);
     }
 
-    result
+    StageResult {
+        stage,
+        stage_type,
+        runtime,
+        status: if failures.is_empty() {
+            TestStatus::Passed
+        } else {
+            TestStatus::Failed
+        },
+        details,
+        failures,
+    }
 }
 
+// the expected response, with variables in the expected headers resolved
+fn expected_result(
+    state: &State,
+    td: &test::Definition,
+    response: &Option<ResponseDescriptor>,
+    iteration: u32,
+) -> ResultData {
+    let mut expected = ResultData::from_request(response.clone());
+
+    if let Some(r) = response {
+        expected.headers = td
+            .get_headers(&r.headers, iteration)
+            .into_iter()
+            .map(|(header, value)| http::Header::new(header, resolve_state(state, &value)))
+            .collect();
+    }
+
+    expected
+}
+
+// runs the setup steps in order, stopping at the first which fails as later steps
+// usually depend on it
 async fn validate_setup(
     state: &mut State,
     td: &test::Definition,
     iteration: u32,
 ) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>> {
-    if let Some(setup) = &td.setup {
+    let mut results = Vec::new();
+
+    for (setup_index, setup) in td.setup.iter().enumerate() {
         let req_method = setup.request.method.as_method();
         let req_url = td.get_url(
             iteration,
@@ -600,7 +1310,7 @@ async fn validate_setup(
             &setup.request.params,
             &td.variables,
         );
-        let req_headers = td.get_setup_request_headers(iteration);
+        let req_headers = td.get_headers(&setup.request.headers, iteration);
         let req_body = td.get_body(&setup.request, &td.variables, iteration);
 
         let resolved_request = test::definition::ResolvedRequest::new(
@@ -612,18 +1322,26 @@ async fn validate_setup(
 
         debug!("executing setup stage: {}", req_url);
 
-        let expected = ResultData::from_request(setup.response.clone());
+        let expected = expected_result(state, td, &setup.response, iteration);
+        pace(state).await;
         let start_time = Instant::now();
-        let req_response = process_request(state, resolved_request).await?;
+        let origin = test_origin(state, td, iteration);
+        let req_response = process_request(
+            state,
+            origin.as_ref(),
+            resolved_request,
+            setup.request.grpc.as_ref(),
+        )
+        .await?;
         let runtime = start_time.elapsed().as_millis() as u32;
-        let actual = ResultData::from_response(req_response).await;
+        let actual = ResultData::from_response(req_response, setup.request.grpc.as_ref()).await;
 
         let request = RequestDetails {
             headers: req_headers
                 .iter()
                 .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
                 .collect(),
-            url: req_url.to_string(),
+            url: resolve_state(state, &req_url),
             method: req_method,
             body: req_body.unwrap_or(serde_json::Value::Null),
         };
@@ -637,188 +1355,154 @@ async fn validate_setup(
         };
 
         let result = process_response(
-            0,
+            setup_index as u32,
             StageType::Setup,
             runtime,
             details,
             &setup.response.clone().map_or(Vec::new(), |r| r.ignore),
+            &setup.request,
         );
 
-        // extract variables and add them to the state
-        if let Some(r) = &setup.response {
-            if let Some(a) = &result.details.actual {
-                for v in &r.extract {
-                    match extract_json(&v.field, 0, a.body.clone()) {
-                        Ok(result) => {
-                            let converted_result = match result {
-                                serde_json::Value::Bool(b) => b.to_string(),
-                                serde_json::Value::Number(n) => n.to_string(),
-                                serde_json::Value::String(s) => s.to_string(),
-                                _ => "".to_string(),
-                            };
-                            state.variables.insert(v.name.clone(), converted_result);
-                        }
-                        Err(error) => {
-                            error!("no json result found: {}", error);
-                        }
-                    }
-                }
-            }
-        }
+        extract_variables(state, &setup.response, &result);
 
-        return Ok((result.status == TestStatus::Passed, vec![result]));
+        let failed = result.status == TestStatus::Failed;
+        results.push(result);
+
+        if failed {
+            return Ok((false, results));
+        }
     }
 
-    Ok((true, Vec::new()))
+    Ok((true, results))
 }
 
+// the onsuccess or onfailure steps followed by the always steps. every step runs even when
+// an earlier one fails, failures are reported according to the `cleanupFailure` setting
 async fn run_cleanup(
     state: &mut State,
     td: &test::Definition,
     iteration: u32,
     succeeded: bool,
     stage_count: u32,
-) -> Result<(bool, Vec<StageResult>), Box<dyn Error + Send + Sync>> {
-    let mut results = Vec::new();
-    let mut counter = stage_count;
-
-    if td.cleanup.always.is_some()
-        || td.cleanup.onsuccess.is_some()
-        || td.cleanup.onfailure.is_some()
-    {
-        debug!("running test cleanup");
+) -> (bool, Vec<StageResult>) {
+    let outcome_steps = if succeeded {
+        &td.cleanup.onsuccess
     } else {
-        return Ok((true, results));
-    }
-
-    if succeeded {
-        if let Some(onsuccess) = &td.cleanup.onsuccess {
-            debug!("execute onsuccess request");
-            let success_method = onsuccess.method.as_method();
-            let success_url =
-                &td.get_url(iteration, &onsuccess.url, &onsuccess.params, &td.variables);
-            let success_headers = td.get_headers(&onsuccess.headers, iteration);
-            let success_body = td.get_body(onsuccess, &td.variables, iteration);
-            let resolved_request = test::definition::ResolvedRequest::new(
-                success_url.clone(),
-                success_method.clone(),
-                success_headers.clone(),
-                success_body.clone(),
-            );
-
-            let expected = ResultData::from_request(None);
-            let start_time = Instant::now();
-            let req_response = process_request(state, resolved_request).await?;
-            let runtime = start_time.elapsed().as_millis() as u32;
-            let actual = ResultData::from_response(req_response).await;
-
-            let request = RequestDetails {
-                headers: success_headers
-                    .iter()
-                    .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
-                    .collect(),
-                url: success_url.to_string(),
-                method: success_method,
-                body: success_body.unwrap_or(serde_json::Value::Null),
-            };
+        &td.cleanup.onfailure
+    };
+    let steps: Vec<&definition::RequestResponseDescriptor> = outcome_steps
+        .iter()
+        .chain(td.cleanup.always.iter())
+        .collect();
 
-            let details = ResultDetails {
-                request,
-                expected,
-                actual,
-                compare_request: None,
-                compare_actual: None,
-            };
+    if steps.is_empty() {
+        return (true, Vec::new());
+    }
 
-            let result =
-                process_response(counter, StageType::Cleanup, runtime, details, &Vec::new());
-            counter += 1;
-            results.push(result);
+    debug!("running test cleanup");
+    let mut results = Vec::new();
+    let mut passed = true;
+
+    for (i, step) in steps.into_iter().enumerate() {
+        let mut result =
+            validate_cleanup_step(state, td, step, stage_count + i as u32, iteration).await;
+
+        if result.status == TestStatus::Failed {
+            match state.cleanup_failure {
+                config::CleanupFailure::Fail => passed = false,
+                config::CleanupFailure::Warn => {
+                    let reasons: Vec<String> =
+                        result.failures.iter().flat_map(summary::describe).collect();
+                    warn!(
+                        "cleanup request failed ({} {}): {}",
+                        result.details.request.method,
+                        result.details.request.url,
+                        reasons.join(", ")
+                    );
+                    result.status = TestStatus::Warning;
+                }
+            }
         }
-    } else if let Some(onfailure) = &td.cleanup.onfailure {
-        debug!("execute onfailure request");
-        let failure_method = onfailure.method.as_method();
-        let failure_url = &td.get_url(iteration, &onfailure.url, &onfailure.params, &td.variables);
-        let failure_headers = td.get_headers(&onfailure.headers, iteration);
-        let failure_body = td.get_body(onfailure, &td.variables, iteration);
-        let resolved_request = test::definition::ResolvedRequest::new(
-            failure_url.clone(),
-            failure_method.clone(),
-            failure_headers.clone(),
-            failure_body.clone(),
-        );
 
-        let expected = ResultData::from_request(None);
-        let start_time = Instant::now();
-        let req_response = process_request(state, resolved_request).await?;
-        let runtime = start_time.elapsed().as_millis() as u32;
-        let actual = ResultData::from_response(req_response).await;
+        results.push(result);
+    }
 
-        let request = RequestDetails {
-            headers: failure_headers
-                .iter()
-                .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
-                .collect(),
-            url: failure_url.to_string(),
-            method: failure_method,
-            body: failure_body.unwrap_or(serde_json::Value::Null),
-        };
+    (passed, results)
+}
 
-        let details = ResultDetails {
-            request,
-            expected,
-            actual,
-            compare_request: None,
-            compare_actual: None,
-        };
+// a request which can't be sent fails the step rather than stopping the cleanup
+async fn validate_cleanup_step(
+    state: &mut State,
+    td: &test::Definition,
+    step: &definition::RequestResponseDescriptor,
+    stage: u32,
+    iteration: u32,
+) -> StageResult {
+    let req_method = step.request.method.as_method();
+    let req_url = td.get_url(
+        iteration,
+        &step.request.url,
+        &step.request.params,
+        &td.variables,
+    );
+    let req_headers = td.get_headers(&step.request.headers, iteration);
+    let req_body = td.get_body(&step.request, &td.variables, iteration);
 
-        let result = process_response(counter, StageType::Cleanup, runtime, details, &Vec::new());
-        counter += 1;
-        results.push(result);
-    }
+    let resolved_request = test::definition::ResolvedRequest::new(
+        req_url.clone(),
+        req_method.clone(),
+        req_headers.clone(),
+        req_body.clone(),
+    );
 
-    if let Some(request) = &td.cleanup.always {
-        debug!("execute cleanup request");
-        let req_method = request.method.as_method();
-        let req_url = &td.get_url(iteration, &request.url, &request.params, &td.variables);
-        let req_headers = td.get_cleanup_request_headers(iteration);
-        let req_body = td.get_body(request, &td.variables, iteration);
-        let resolved_request = test::definition::ResolvedRequest::new(
-            req_url.clone(),
-            req_method.clone(),
-            req_headers.clone(),
-            req_body.clone(),
-        );
+    debug!("executing cleanup request: {}", req_url);
 
-        let expected = ResultData::from_request(None);
-        let start_time = Instant::now();
-        let req_response = process_request(state, resolved_request).await?;
-        let runtime = start_time.elapsed().as_millis() as u32;
-        let actual = ResultData::from_response(req_response).await;
+    let expected = expected_result(state, td, &step.response, iteration);
+    let request = RequestDetails {
+        headers: req_headers
+            .iter()
+            .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
+            .collect(),
+        url: resolve_state(state, &req_url),
+        method: req_method,
+        body: req_body.unwrap_or(serde_json::Value::Null),
+    };
 
-        let request = RequestDetails {
-            headers: req_headers
-                .iter()
-                .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
-                .collect(),
-            url: req_url.to_string(),
-            method: req_method,
-            body: req_body.unwrap_or(serde_json::Value::Null),
+    pace(state).await;
+    let start_time = Instant::now();
+    let grpc = step.request.grpc.as_ref();
+    let origin = test_origin(state, td, iteration);
+    let (actual, request_error) =
+        match process_request(state, origin.as_ref(), resolved_request, grpc).await {
+            Ok(response) => (ResultData::from_response(response, grpc).await, None),
+            Err(e) => (None, Some(e.to_string())),
         };
+    let runtime = start_time.elapsed().as_millis() as u32;
 
-        let details = ResultDetails {
-            request,
-            expected,
-            actual,
-            compare_request: None,
-            compare_actual: None,
-        };
+    let details = ResultDetails {
+        request,
+        expected,
+        actual,
+        compare_request: None,
+        compare_actual: None,
+    };
 
-        let result = process_response(counter, StageType::Cleanup, runtime, details, &Vec::new());
-        results.push(result);
+    let mut result = process_response(
+        stage,
+        StageType::Cleanup,
+        runtime,
+        details,
+        &step.response.clone().map_or(Vec::new(), |r| r.ignore),
+        &step.request,
+    );
+
+    if let Some(e) = request_error {
+        result.status = TestStatus::Failed;
+        result.failures.push(Failure::Error(e));
     }
 
-    Ok((true, results))
+    extract_variables(state, &step.response, &result);
+    result
 }
 
 async fn validate_stage(
@@ -828,6 +1512,11 @@ async fn validate_stage(
     stage_index: usize,
     iteration: u32,
 ) -> Result<StageResult, Box<dyn Error + Send + Sync>> {
+    if let Some(stream) = &stage.stream {
+        debug!("execute stage stream");
+        return stream::validate_stream(state, td, stage, stream, stage_index, iteration).await;
+    }
+
     debug!("execute stage request");
 
     let req_method = stage.request.method.as_method();
@@ -851,21 +1540,29 @@ async fn validate_stage(
         req_body.clone(),
     );
     debug!("executing test stage: {}", req_url);
-    let expected = ResultData::from_request(stage.response.clone());
+    let expected = expected_result(state, td, &stage.response, iteration);
     let request = RequestDetails {
         headers: req_headers
             .iter()
             .map(|h| http::Header::new(h.0.clone(), h.1.clone()))
             .collect(),
-        url: req_url.to_string(),
+        url: resolve_state(state, req_url),
         method: req_method,
         body: req_body.unwrap_or(serde_json::Value::Null),
     };
     let mut compare_response_opt = None;
     let mut compare_request = None;
 
+    pace(state).await;
     let start_time = Instant::now();
-    let req_response = process_request(state, resolved_request).await?;
+    let origin = test_origin(state, td, iteration);
+    let req_response = process_request(
+        state,
+        origin.as_ref(),
+        resolved_request,
+        stage.request.grpc.as_ref(),
+    )
+    .await?;
 
     if let Some(compare) = &stage.compare {
         debug!("execute stage comparison");
@@ -902,15 +1599,16 @@ async fn validate_stage(
             body: compare_body.unwrap_or(serde_json::Value::Null),
         });
 
-        compare_response_opt = Some(process_request(state, resolved_compare_request).await?);
+        compare_response_opt =
+            Some(process_request(state, None, resolved_compare_request, None).await?);
     }
 
     let runtime = start_time.elapsed().as_millis() as u32;
-    let actual = ResultData::from_response(req_response).await;
+    let actual = ResultData::from_response(req_response, stage.request.grpc.as_ref()).await;
     let mut compare_actual = None;
 
     if let Some(compare_response) = compare_response_opt {
-        compare_actual = ResultData::from_response(compare_response).await;
+        compare_actual = ResultData::from_response(compare_response, None).await;
     }
 
     let details = ResultDetails {
@@ -921,80 +1619,261 @@ async fn validate_stage(
         compare_actual,
     };
 
-    let result = process_response(
+    let stage_ignore = stage.response.clone().map_or(Vec::new(), |r| r.ignore);
+    let mut result = process_response(
         stage_index as u32,
         StageType::Normal,
         runtime,
         details,
-        &stage.response.clone().map_or(Vec::new(), |r| r.ignore),
+        &stage_ignore,
+        &stage.request,
     );
 
-    // extract variables and add them to the state
-    if let Some(r) = &stage.response {
-        if let Some(a) = &result.details.actual {
-            for v in &r.extract {
-                match extract_json(&v.field, 0, a.body.clone()) {
-                    Ok(result) => {
-                        let converted_result = match result {
-                            serde_json::Value::Bool(b) => b.to_string(),
-                            serde_json::Value::Number(n) => n.to_string(),
-                            serde_json::Value::String(s) => s.to_string(),
-                            _ => "".to_string(),
-                        };
-                        state.variables.insert(v.name.clone(), converted_result);
-                    }
-                    Err(error) => {
-                        error!("no json result found: {}", error);
-                    }
+    if let (Some(compare), Some(actual), Some(compare_actual)) = (
+        &stage.compare,
+        &result.details.actual,
+        &result.details.compare_actual,
+    ) {
+        let mut failures =
+            validate_compare(actual, compare_actual, &compare.response, &stage_ignore);
+
+        if !failures.is_empty() {
+            result.status = TestStatus::Failed;
+            result.failures.append(&mut failures);
+        }
+    }
+
+    extract_variables(state, &stage.response, &result);
+    Ok(result)
+}
+
+// extract variables and add them to the state
+fn extract_variables(
+    state: &mut State,
+    response: &Option<ResponseDescriptor>,
+    result: &StageResult,
+) {
+    if let (Some(r), Some(a)) = (response, &result.details.actual) {
+        for v in &r.extract {
+            match extract_json(&v.field, 0, a.body.clone()) {
+                Ok(value) => {
+                    state.variables.insert(v.name.clone(), state_value(value));
+                }
+                Err(error) => {
+                    error!("no json result found: {}", error);
                 }
             }
         }
     }
+}
 
-    Ok(result)
+fn record_results(
+    td: &test::Definition,
+    results: &[StageResult],
+    mode: record::Mode,
+    headers: &[String],
+) {
+    match record::record(&td.filename, results, mode, headers) {
+        Ok(0) => {}
+        Ok(count) => info!(
+            "Recorded {} stage{} in `{}`.\n",
+            count,
+            if count != 1 { "s" } else { "" },
+            td.filename
+        ),
+        Err(e) => error!("unable to record responses in `{}`: {}", td.filename, e),
+    }
+}
+
+// replaces variables extracted from earlier responses, which are only known at runtime
+fn resolve_state(state: &State, value: &str) -> String {
+    let mut result = value.to_string();
+
+    for gv in state.variables.iter() {
+        let key_search = format!("${{{}}}", gv.0);
+        result = result.replace(&key_search, gv.1);
+    }
+
+    result
+}
+
+// the origin of the test's first stage. `--base-url` only replaces urls on this origin,
+// so requests to other hosts, such as an auth server, are sent as written
+fn test_origin(state: &State, td: &test::Definition, iteration: u32) -> Option<url::Origin> {
+    let stage = td.stages.first()?;
+    let url = td.get_url(
+        iteration,
+        &stage.request.url,
+        &stage.request.params,
+        &[&stage.variables[..], &td.variables[..]].concat(),
+    );
+
+    Url::parse(&resolve_state(state, &url))
+        .ok()
+        .map(|u| origin_of(&u))
+}
+
+// websocket urls share the origin of the http urls on their host
+fn origin_of(url: &Url) -> url::Origin {
+    let scheme = match url.scheme() {
+        "ws" => "http",
+        "wss" => "https",
+        _ => return url.origin(),
+    };
+
+    let mut url = url.clone();
+    _ = url.set_scheme(scheme);
+    url.origin()
+}
+
+// sends requests on the test's origin to another server, such as `jk mock`, keeping their
+// path and query. requests without an origin, like compare requests, are left alone
+fn rebase_url(state: &State, origin: Option<&url::Origin>, url: &str) -> String {
+    let (base, origin) = match (&state.base_url, origin) {
+        (Some(b), Some(o)) => (b, o),
+        _ => return url.to_string(),
+    };
+
+    match Url::parse(url) {
+        Ok(u) if origin_of(&u) == *origin => {
+            let mut rebased = format!("{}{}", base.trim_end_matches('/'), u.path());
+            if let Some(q) = u.query() {
+                rebased = format!("{}?{}", rebased, q);
+            }
+
+            // websockets stay websockets on the other server
+            if matches!(u.scheme(), "ws" | "wss") {
+                if let Some(rest) = rebased.strip_prefix("https://") {
+                    rebased = format!("wss://{}", rest);
+                } else if let Some(rest) = rebased.strip_prefix("http://") {
+                    rebased = format!("ws://{}", rest);
+                }
+            }
+            rebased
+        }
+        _ => url.to_string(),
+    }
+}
+
+fn to_resolved_request(
+    state: &State,
+    origin: Option<&url::Origin>,
+    request: &RequestDetails,
+) -> definition::ResolvedRequest {
+    definition::ResolvedRequest::new(
+        rebase_url(state, origin, &request.url),
+        request.method.clone(),
+        request
+            .headers
+            .iter()
+            .map(|h| (h.header.clone(), resolve_state(state, &h.value)))
+            .collect(),
+        match &request.body {
+            serde_json::Value::Null => None,
+            b => Some(b.clone()),
+        },
+    )
+}
+
+// prints a curl command for each request made by a failed stage so it can be replayed by hand
+fn print_failed_requests(state: &State, origin: Option<&url::Origin>, results: &[StageResult]) {
+    for result in results.iter().filter(|r| r.status == TestStatus::Failed) {
+        let label = match result.stage_type {
+            StageType::Setup => "setup".to_string(),
+            StageType::Normal => format!("stage {}", result.stage + 1),
+            StageType::Cleanup => "cleanup".to_string(),
+        };
+
+        let request = to_resolved_request(state, origin, &result.details.request);
+        info!("{} request:\n{}\n", label, export::curl(&request));
+
+        if let Some(compare) = &result.details.compare_request {
+            let request = to_resolved_request(state, None, compare);
+            info!("{} compare request:\n{}\n", label, export::curl(&request));
+        }
+    }
+}
+
+// waits for the request's turn when requests are paced, so the wait isn't timed
+async fn pace(state: &State) {
+    if let Some(pacer) = &state.pacer {
+        pacer.wait().await;
+    }
 }
 
 async fn process_request(
     state: &State,
+    origin: Option<&url::Origin>,
     resolved_request: test::definition::ResolvedRequest,
+    grpc: Option<&test::grpc::Method>,
 ) -> Result<hyper::Response<Body>, Box<dyn Error + Send + Sync>> {
-    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
-    debug!("url({})", resolved_request.url);
-    match Url::parse(&resolved_request.url) {
+    let client = if grpc.is_some() {
+        &state.clients.grpc
+    } else {
+        &state.clients.http
+    };
+    let mut url = rebase_url(state, origin, &resolve_state(state, &resolved_request.url));
+
+    if let Some(method) = grpc {
+        url = method.url(&url);
+    }
+
+    debug!("url({})", url);
+    match Url::parse(&url) {
         Ok(_) => {}
         Err(error) => {
             return Err(Box::from(format!("invalid request url: {}", error)));
         }
     }
 
-    let mut req_builder = Request::builder().uri(resolved_request.url);
+    let mut req_builder = Request::builder().uri(url);
     req_builder = req_builder.method(resolved_request.method.to_hyper());
 
     for header in resolved_request.headers {
-        let mut header_value: String = header.1;
-
-        for gv in state.variables.iter() {
-            let key_search = format!("${{{}}}", gv.0);
-            header_value = header_value.replace(&key_search, gv.1);
-        }
+        let header_value = resolve_state(state, &header.1);
 
         debug!("header({}) value({})", &header.0, &header_value);
         req_builder = req_builder.header(&header.0, header_value);
     }
 
-    let req_body = match resolved_request.body {
-        Some(b) => {
+    let req_body = match (grpc, resolved_request.body) {
+        (Some(method), body) => {
+            req_builder = req_builder
+                .header("Content-Type", HeaderValue::from_static("application/grpc"))
+                .header("TE", HeaderValue::from_static("trailers"));
+            let message = match body {
+                Some(b) => {
+                    serde_json::from_str(&resolve_state(state, &serde_json::to_string(&b)?))?
+                }
+                None => serde_json::json!({}),
+            };
+            Body::from(method.encode(&message)?)
+        }
+        (None, Some(b)) => {
             req_builder =
                 req_builder.header("Content-Type", HeaderValue::from_static("application/json"));
-            Body::from(serde_json::to_string(&b)?)
+            Body::from(resolve_state(state, &serde_json::to_string(&b)?))
         }
-        None => Body::empty(),
+        (None, None) => Body::empty(),
+    };
+
+    let req = match req_builder.body(req_body) {
+        Ok(req) => req,
+        Err(error) => return Err(Box::from(format!("bad request result: {}", error))),
     };
 
-    let req_opt = req_builder.body(req_body);
-    match req_opt {
-        Ok(req) => Ok(client.request(req).await?),
-        Err(error) => Err(Box::from(format!("bad request result: {}", error))),
+    // a server that never answers mustn't hold up cleanup once the run is interrupted
+    let interrupted = async {
+        state.interrupted.wait().await;
+        tokio::time::sleep(state.interrupt_timeout).await;
+    };
+
+    tokio::select! {
+        response = client.request(req) => Ok(response?),
+        _ = interrupted => Err(Box::from(format!(
+            "no response within {:?} of the run being interrupted",
+            state.interrupt_timeout
+        ))),
     }
 }
 
@@ -1005,7 +1884,7 @@ fn validate_dry_run(
 ) -> Result<bool, Box<dyn Error + Send + Sync>> {
     // construct request block
 
-    if let Some(setup) = &td.setup {
+    for setup in td.setup.iter() {
         let setup_method = setup.request.method.as_method();
         let setup_url = &td.get_url(
             iteration,
@@ -1013,7 +1892,7 @@ fn validate_dry_run(
             &setup.request.params,
             &td.variables,
         );
-        let setup_headers = td.get_setup_request_headers(iteration);
+        let setup_headers = td.get_headers(&setup.request.headers, iteration);
         let setup_body = td.get_body(&setup.request, &td.variables, iteration);
         info!("setup: {} {}\n", setup_method, setup_url);
         if !setup_headers.is_empty() {
@@ -1097,6 +1976,32 @@ fn validate_dry_run(
             info!("body: {}\n", body);
         }
 
+        for message in stage.stream.iter().flat_map(|s| s.messages.iter()) {
+            match message {
+                definition::StreamMessage::Send(body) => {
+                    info!("send stream message: {}\n", body.data);
+                }
+                definition::StreamMessage::Receive {
+                    body,
+                    extract,
+                    timeout,
+                    ..
+                } => {
+                    info!(
+                        "wait up to {:?} for a stream message matching: {}\n",
+                        timeout, body.data
+                    );
+
+                    for v in extract.iter() {
+                        info!(
+                            "attempt to extract value from message: {} = valueOf({})\n",
+                            v.name, v.field
+                        );
+                    }
+                }
+            }
+        }
+
         if let Some(r) = &stage.response {
             // compare to response definition
             if let Some(stage_response_status) = r.status {
@@ -1168,10 +2073,7 @@ fn validate_dry_run(
                 Some(b) => {
                     stage_compare_headers
                         .insert("Content-Type".to_string(), "application/json".to_string());
-                    match serde_json::to_string(b) {
-                        Ok(body) => Some(body),
-                        Err(_) => None,
-                    }
+                    serde_json::to_string(b).ok()
                 }
                 None => None,
             };
@@ -1196,82 +2098,692 @@ fn validate_dry_run(
             // compare to comparison response
             info!("validate request_status_code matches compare_request_status_code\n");
 
-            if let Some(r) = &stage.response {
-                if !r.ignore.is_empty() {
-                    info!("prune fields from compare_response_body\n");
-                    for i in r.ignore.iter() {
-                        info!("filter: {}\n", i);
-                    }
+            let rules = &stage_compare.response;
+            if rules.compares_headers() {
+                if rules.headers.is_empty() {
+                    info!("validate response_headers match compare_response_headers\n");
+                } else {
                     info!(
-                        "validate filtered response_body matches filtered compare_response_body\n"
+                        "validate response_headers match compare_response_headers: {}\n",
+                        rules.headers.join(", ")
                     );
-                } else {
-                    info!("validate response_body matches compare_response_body\n");
                 }
+
+                for h in rules.ignore_headers.iter() {
+                    info!("ignore header: {}\n", h);
+                }
+            }
+
+            let ignore = match &rules.ignore {
+                Some(ignore) => ignore.clone(),
+                None => stage.response.clone().map_or(Vec::new(), |r| r.ignore),
+            };
+
+            if !ignore.is_empty() {
+                info!("prune fields from compare_response_body\n");
+                for i in ignore.iter() {
+                    info!("filter: {}\n", i);
+                }
+                info!("validate filtered response_body matches filtered compare_response_body\n");
             } else {
                 info!("validate response_body matches compare_response_body\n");
             }
+
+            if rules.tolerance > 0.0 {
+                info!("allow numbers to differ by up to {}\n", rules.tolerance);
+            }
+
+            if rules.unordered_arrays {
+                info!("allow array elements in any order\n");
+            }
         }
     }
 
-    if let Some(onsuccess) = &td.cleanup.onsuccess {
-        info!("when test successful, run onsuccess request:\n");
-        let onsuccess_method = onsuccess.method.as_method();
-        let onsuccess_url =
-            &td.get_url(iteration, &onsuccess.url, &onsuccess.params, &td.variables);
-        let onsuccess_headers = td.get_setup_request_headers(iteration);
-        let onsuccess_body = td.get_body(onsuccess, &td.variables, iteration);
-        info!("onsuccess: {} {}\n", onsuccess_method, onsuccess_url);
-        if !onsuccess_headers.is_empty() {
-            info!("onsuccess_headers:\n");
-            for (key, value) in onsuccess_headers.iter() {
-                info!("-- {}: {}\n", key, value);
-            }
+    let cleanup = [
+        (
+            "onsuccess",
+            "when test successful, run onsuccess requests",
+            &td.cleanup.onsuccess,
+        ),
+        (
+            "onfailure",
+            "when test fails, run onfailure requests",
+            &td.cleanup.onfailure,
+        ),
+        ("cleanup", "run cleanup requests", &td.cleanup.always),
+    ];
+
+    for (label, description, steps) in cleanup.iter() {
+        if !steps.is_empty() {
+            info!("{}:\n", description);
         }
 
-        if let Some(body) = onsuccess_body {
-            info!("onsuccess_body: {}\n", body);
+        for step in steps.iter() {
+            let step_method = step.request.method.as_method();
+            let step_url = &td.get_url(
+                iteration,
+                &step.request.url,
+                &step.request.params,
+                &td.variables,
+            );
+            let step_headers = td.get_headers(&step.request.headers, iteration);
+            let step_body = td.get_body(&step.request, &td.variables, iteration);
+            info!("{}: {} {}\n", label, step_method, step_url);
+            if !step_headers.is_empty() {
+                info!("{}_headers:\n", label);
+                for (key, value) in step_headers.iter() {
+                    info!("-- {}: {}\n", key, value);
+                }
+            }
+
+            if let Some(body) = step_body {
+                info!("{}_body: {}\n", label, body);
+            }
+
+            if let Some(status) = step.response.as_ref().and_then(|r| r.status) {
+                info!(
+                    "validate {}_response_status with defined_status: {}\n",
+                    label, status
+                );
+            }
         }
     }
 
-    if let Some(onfailure) = &td.cleanup.onfailure {
-        info!("when test fails, run onfailure request:\n");
-        let onfailure_method = onfailure.method.as_method();
-        let onfailure_url =
-            &td.get_url(iteration, &onfailure.url, &onfailure.params, &td.variables);
-        let onfailure_headers = td.get_setup_request_headers(iteration);
-        let onfailure_body = td.get_body(onfailure, &td.variables, iteration);
-        info!("onfailure: {} {}\n", onfailure_method, onfailure_url);
-        if !onfailure_headers.is_empty() {
-            info!("onfailure_headers:\n");
-            for (key, value) in onfailure_headers.iter() {
-                info!("-- {}: {}\n", key, value);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server};
+    use std::convert::Infallible;
+    use std::net::{SocketAddr, TcpListener};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use tempfile::tempdir;
+
+    // answers each request with how often its path has been requested and a fixed list of
+    // ids, using the status from a `/status/<code>` path, and keeps the paths requested in order
+    pub(super) fn server() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let (address, requests, _) = server_counting_connections();
+        (address, requests)
+    }
+
+    pub(super) fn server_counting_connections(
+    ) -> (SocketAddr, Arc<Mutex<Vec<String>>>, Arc<AtomicUsize>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let log = requests.clone();
+        let accepted = connections.clone();
+
+        let service = make_service_fn(move |_| {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let log = log.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let log = log.clone();
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let count = {
+                            let mut log = log.lock().unwrap();
+                            log.push(path.clone());
+                            log.iter().filter(|p| **p == path).count()
+                        };
+                        let status = path
+                            .strip_prefix("/status/")
+                            .and_then(|code| code.parse().ok())
+                            .unwrap_or(200);
+
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .header("Content-Type", "application/json")
+                                .body(Body::from(format!(
+                                    "{{\"count\": {}, \"ids\": [1, 2, 3]}}",
+                                    count
+                                )))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(service));
+        (address, requests, connections)
+    }
+
+    pub(super) fn write(dir: &Path, name: &str, contents: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    pub(super) async fn execute(
+        config: config::Config,
+        files: Vec<String>,
+        options: RunOptions,
+    ) -> Report {
+        execute_tests(
+            config,
+            files,
+            false,
+            select::Filter::default(),
+            Box::new(Value::Null),
+            options,
+        )
+        .await
+    }
+
+    #[test]
+    fn the_most_severe_outcome_decides_the_exit_code() {
+        let report = |failed, errored, invalid, interrupted| Report {
+            failed,
+            errored,
+            invalid,
+            interrupted,
+            ..Default::default()
+        };
+
+        assert_eq!(EXIT_PASSED, report(0, 0, 0, false).exit_code());
+        assert_eq!(EXIT_FAILED, report(1, 0, 0, false).exit_code());
+        assert_eq!(EXIT_INVALID, report(1, 0, 1, false).exit_code());
+        assert_eq!(EXIT_ERROR, report(1, 1, 1, false).exit_code());
+        assert_eq!(EXIT_INTERRUPTED, report(1, 1, 1, true).exit_code());
+        assert_eq!(
+            EXIT_PASSED,
+            Report {
+                skipped: 2,
+                xfailed: 1,
+                ..Default::default()
+            }
+            .exit_code()
+        );
+    }
+
+    #[tokio::test]
+    async fn the_run_stops_at_the_first_failure_unless_told_to_continue() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let files = vec![
+            write(
+                tmp_dir.path(),
+                "a.jkt",
+                &format!(
+                    "request:\n  url: http://{}/status/500\nresponse:\n  status: 200\n",
+                    address
+                ),
+            ),
+            write(
+                tmp_dir.path(),
+                "b.jkt",
+                &format!("iterate: 2\nrequest:\n  url: http://{}/b\n", address),
+            ),
+        ];
+
+        let report = execute(
+            config::Config::default(),
+            files.clone(),
+            RunOptions::default(),
+        )
+        .await;
+
+        assert_eq!(1, report.run);
+        assert_eq!(1, report.failed);
+        assert_eq!(2, report.skipped);
+        assert_eq!(EXIT_FAILED, report.exit_code());
+        assert_eq!(vec!["/status/500"], *requests.lock().unwrap());
+
+        let report = execute(
+            config::Config::default(),
+            files,
+            RunOptions {
+                continue_on_failure: Some(true),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert_eq!(3, report.run);
+        assert_eq!(2, report.passed);
+        assert_eq!(0, report.skipped);
+        assert_eq!(EXIT_FAILED, report.exit_code());
+    }
+
+    #[tokio::test]
+    async fn skipped_and_expected_failures_are_reported_separately() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let files = vec![
+            write(
+                tmp_dir.path(),
+                "a.jkt",
+                &format!("skip: flaky\nrequest:\n  url: http://{}/a\n", address),
+            ),
+            write(
+                tmp_dir.path(),
+                "b.jkt",
+                &format!(
+                    "xfail: issue 12\nrequest:\n  url: http://{}/status/500\nresponse:\n  status: 200\n",
+                    address
+                ),
+            ),
+            write(
+                tmp_dir.path(),
+                "c.jkt",
+                &format!(
+                    "stages:\n  - request:\n      url: http://{0}/c\n  - request:\n      url: http://{0}/d\n    skip: not deployed\n",
+                    address
+                ),
+            ),
+        ];
+        let (progress, mut updates) = tokio::sync::mpsc::unbounded_channel();
+
+        let report = execute(
+            config::Config::default(),
+            files,
+            RunOptions {
+                progress: Some(progress),
+                continue_on_failure: Some(true),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert_eq!(2, report.run);
+        assert_eq!(1, report.passed);
+        assert_eq!(1, report.xfailed);
+        assert_eq!(1, report.skipped);
+        assert_eq!(EXIT_PASSED, report.exit_code());
+        assert_eq!(vec!["/status/500", "/c"], *requests.lock().unwrap());
+
+        let mut skipped = Vec::new();
+        let mut stages = Vec::new();
+        while let Ok(update) = updates.try_recv() {
+            match update {
+                Progress::Skipped { test, reason } => skipped.push((test, reason)),
+                Progress::Finished { results, .. } => {
+                    stages.extend(results.into_iter().map(|r| r.status))
+                }
+                _ => {}
             }
         }
+        assert_eq!(vec![(0, "flaky".to_string())], skipped);
+        assert!(stages == vec![TestStatus::Failed, TestStatus::Passed, TestStatus::Skipped]);
+    }
+
+    #[tokio::test]
+    async fn only_focused_tests_run_when_any_are_focused() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let files = vec![
+            write(
+                tmp_dir.path(),
+                "a.jkt",
+                &format!("request:\n  url: http://{}/a\n", address),
+            ),
+            write(
+                tmp_dir.path(),
+                "b.jkt",
+                &format!("only: true\nrequest:\n  url: http://{}/b\n", address),
+            ),
+        ];
+
+        let report = execute(config::Config::default(), files, RunOptions::default()).await;
+
+        assert_eq!(1, report.run);
+        assert_eq!(0, report.skipped);
+        assert_eq!(vec!["/b"], *requests.lock().unwrap());
+    }
+
+    pub(super) fn definition(dir: &Path, contents: &str) -> test::Definition {
+        let path = write(dir, "test.jkt", contents);
+        validation::validate_file(test::file::load(&path).unwrap(), &[]).unwrap()
+    }
 
-        if let Some(body) = onfailure_body {
-            info!("onfailure_body: {}\n", body);
+    pub(super) fn state() -> State {
+        State {
+            variables: HashMap::new(),
+            base_url: None,
+            interruptible: false,
+            cleanup_failure: config::CleanupFailure::default(),
+            pacer: None,
+            interrupted: interrupt::Flag::default(),
+            interrupt_timeout: INTERRUPT_TIMEOUT,
+            clients: Clients::new(),
         }
     }
 
-    if let Some(request) = &td.cleanup.always {
-        info!("run cleanup requests:\n");
-        let cleanup_method = request.method.as_method();
-        let cleanup_url = &td.get_url(iteration, &request.url, &request.params, &td.variables);
-        let cleanup_headers = td.get_setup_request_headers(iteration);
-        let cleanup_body = td.get_body(request, &td.variables, iteration);
-        info!("cleanup: {} {}\n", cleanup_method, cleanup_url);
-        if !cleanup_headers.is_empty() {
-            info!("cleanup_headers:\n");
-            for (key, value) in cleanup_headers.iter() {
-                info!("-- {}: {}\n", key, value);
-            }
+    #[tokio::test]
+    async fn stages_repeat_for_each_item_and_until_or_while_a_condition_holds() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let td = definition(
+            tmp_dir.path(),
+            &format!(
+                "stages:\n\
+                 - request:\n    url: http://{0}/list\n  response:\n    extract:\n      - name: ids\n        field: ids\n\
+                 - request:\n    url: http://{0}/pets/${{id}}\n  forEach: ids\n  as: id\n\
+                 - request:\n    url: http://{0}/poll\n  until: count >= 3\n  response:\n    extract:\n      - name: count\n        field: count\n\
+                 - request:\n    url: http://{0}/next\n  while: count < 5\n  response:\n    extract:\n      - name: count\n        field: count\n",
+                address
+            ),
+        );
+
+        let (passed, results) = run(&mut state(), &td, 0, None).await.unwrap();
+
+        assert!(passed);
+        assert_eq!(12, results.len());
+        let requests = requests.lock().unwrap();
+        let count = |path: &str| requests.iter().filter(|r| *r == path).count();
+        assert_eq!(
+            vec!["/list", "/pets/1", "/pets/2", "/pets/3", "/poll"],
+            requests[..5]
+        );
+        assert_eq!(3, count("/poll"));
+        // `count` is 3 after polling, then each run of `/next` sets it to its own count
+        assert_eq!(5, count("/next"));
+    }
+
+    #[tokio::test]
+    async fn repeated_stages_fail_past_max_iterations() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let td = definition(
+            tmp_dir.path(),
+            &format!(
+                "stages:\n\
+                 - request:\n    url: http://{0}/spin\n  until: count == 0\n  maxIterations: 2\n  response:\n    extract:\n      - name: count\n        field: count\n\
+                 - request:\n    url: http://{0}/after\n",
+                address
+            ),
+        );
+
+        let (passed, results) = run(&mut state(), &td, 0, None).await.unwrap();
+
+        assert!(!passed);
+        assert_eq!(vec!["/spin", "/spin"], *requests.lock().unwrap());
+        assert_eq!(2, results.len());
+        assert!(results[1].status == TestStatus::Failed);
+        match &results[1].failures[..] {
+            [Failure::Error(reason)] => assert_eq!(
+                "stage 1 stopped after 2 iterations, the limit set by `maxIterations`",
+                reason
+            ),
+            _ => panic!("expected the stage to stop at its limit"),
         }
+    }
+
+    #[tokio::test]
+    async fn cleanup_runs_when_a_test_is_interrupted() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let td = definition(
+            tmp_dir.path(),
+            &format!(
+                "stages:\n  - request:\n      url: http://{0}/first\n  - request:\n      url: http://{0}/second\n\
+                 cleanup:\n  onsuccess:\n    url: http://{0}/passed\n  onfailure:\n    url: http://{0}/failed\n  always:\n    url: http://{0}/always\n",
+                address
+            ),
+        );
+        let mut state = State {
+            interruptible: true,
+            ..state()
+        };
+        state.interrupted.set();
 
-        if let Some(body) = cleanup_body {
-            info!("cleanup_body: {}\n", body);
+        match run(&mut state, &td, 0, None).await {
+            Err(e) => assert_eq!("interrupted before stage 1", e.to_string()),
+            Ok(_) => panic!("expected the test to be interrupted"),
         }
+        assert_eq!(vec!["/failed", "/always"], *requests.lock().unwrap());
     }
 
-    Ok(true)
+    #[tokio::test]
+    async fn cleanup_runs_when_an_interrupted_request_never_gets_a_response() {
+        let (address, requests) = server();
+        // accepts connections and never answers on them
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_address = silent.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = silent.accept().await {
+                connections.push(connection);
+            }
+        });
+
+        let tmp_dir = tempdir().unwrap();
+        let td = definition(
+            tmp_dir.path(),
+            &format!(
+                "request:\n  url: http://{1}/hangs\n\
+                 cleanup:\n  always:\n    url: http://{0}/always\n",
+                address, silent_address
+            ),
+        );
+        let mut state = State {
+            interruptible: true,
+            interrupt_timeout: Duration::from_millis(100),
+            ..state()
+        };
+        let interrupted = state.interrupted.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            interrupted.set();
+        });
+
+        let result = tokio::time::timeout(Duration::from_secs(5), run(&mut state, &td, 0, None))
+            .await
+            .expect("the run waited on the request after being interrupted");
+        match result {
+            Err(e) => assert_eq!(
+                "no response within 100ms of the run being interrupted",
+                e.to_string()
+            ),
+            Ok(_) => panic!("expected the request to be given up"),
+        }
+        assert_eq!(vec!["/always"], *requests.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn cleanup_steps_run_in_order_and_failures_follow_the_config() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let td = definition(
+            tmp_dir.path(),
+            &format!(
+                "request:\n  url: http://{0}/test\n\
+                 cleanup:\n  always:\n\
+                 \x20   - request:\n        url: http://{0}/status/500\n      response:\n        status: 204\n\
+                 \x20   - request:\n        url: http://{0}/second\n\
+                 \x20   - request:\n        url: http://{0}/third\n",
+                address
+            ),
+        );
+        let cleanup_statuses = |results: &[StageResult]| -> Vec<u32> {
+            results
+                .iter()
+                .filter(|r| r.stage_type == StageType::Cleanup)
+                .map(|r| r.status.clone() as u32)
+                .collect()
+        };
+
+        let (passed, results) = run(&mut state(), &td, 0, None).await.unwrap();
+
+        assert!(passed);
+        assert_eq!(
+            vec![
+                TestStatus::Warning as u32,
+                TestStatus::Passed as u32,
+                TestStatus::Passed as u32
+            ],
+            cleanup_statuses(&results)
+        );
+        assert_eq!(
+            vec!["/test", "/status/500", "/second", "/third"],
+            *requests.lock().unwrap()
+        );
+
+        let mut failing = State {
+            cleanup_failure: config::CleanupFailure::Fail,
+            ..state()
+        };
+        let (passed, results) = run(&mut failing, &td, 0, None).await.unwrap();
+
+        assert!(!passed);
+        assert_eq!(
+            vec![
+                TestStatus::Failed as u32,
+                TestStatus::Passed as u32,
+                TestStatus::Passed as u32
+            ],
+            cleanup_statuses(&results)
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_setup_errors_the_test_and_still_cleans_up() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let files = vec![write(
+            tmp_dir.path(),
+            "a.jkt",
+            &format!(
+                "setup:\n\
+                 \x20 - request:\n      url: http://{0}/tenant\n\
+                 \x20 - request:\n      url: http://{0}/status/500\n    response:\n      status: 200\n\
+                 \x20 - request:\n      url: http://{0}/login\n\
+                 request:\n  url: http://{0}/test\n\
+                 cleanup:\n  onfailure:\n    url: http://{0}/failed\n  always:\n    url: http://{0}/always\n",
+                address
+            ),
+        )];
+
+        let report = execute(config::Config::default(), files, RunOptions::default()).await;
+
+        assert_eq!(1, report.errored);
+        assert_eq!(0, report.failed);
+        assert_eq!(EXIT_ERROR, report.exit_code());
+        assert_eq!(
+            vec!["/tenant", "/status/500", "/failed", "/always"],
+            *requests.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn only_requests_on_the_test_origin_are_sent_to_the_base_url() {
+        let (base, rebased) = server();
+        let (other, unchanged) = server();
+        let tmp_dir = tempdir().unwrap();
+        // nothing listens on the test's own origin, so its requests only succeed when rebased
+        let files = vec![write(
+            tmp_dir.path(),
+            "a.jkt",
+            &format!(
+                "setup:\n\
+                 \x20 - request:\n      url: http://{0}/token\n\
+                 \x20 - request:\n      url: http://127.0.0.1:9/tenant\n\
+                 stages:\n\
+                 \x20 - request:\n      url: http://127.0.0.1:9/pets\n    compare:\n      url: http://{0}/pets\n\
+                 cleanup:\n  always:\n    url: http://127.0.0.1:9/reset\n",
+                other
+            ),
+        )];
+
+        let report = execute(
+            config::Config::default(),
+            files,
+            RunOptions {
+                base_url: Some(format!("http://{}", base)),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert_eq!(1, report.passed);
+        assert_eq!(vec!["/tenant", "/pets", "/reset"], *rebased.lock().unwrap());
+        assert_eq!(vec!["/token", "/pets"], *unchanged.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn unary_grpc_calls_are_framed_and_read_their_status_from_trailers() {
+        // `Update` echoes the pet back, `Get` never finds one
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let path = req.uri().path().to_string();
+                assert_eq!("application/grpc", req.headers()["content-type"]);
+                let frame = body::to_bytes(req.into_body()).await.unwrap();
+                let (mut sender, body) = Body::channel();
+
+                tokio::spawn(async move {
+                    let mut trailers = hyper::HeaderMap::new();
+                    if path == "/pets.v1.PetService/Update" {
+                        sender.send_data(frame).await.unwrap();
+                        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+                    } else {
+                        trailers.insert("grpc-status", HeaderValue::from_static("5"));
+                        trailers.insert(
+                            "grpc-message",
+                            HeaderValue::from_static("pet%20not%20found"),
+                        );
+                    }
+                    sender.send_trailers(trailers).await.unwrap();
+                });
+
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .header("content-type", "application/grpc")
+                        .body(body)
+                        .unwrap(),
+                )
+            }))
+        });
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .http2_only(true)
+                .serve(service),
+        );
+
+        let tmp_dir = tempdir().unwrap();
+        write(
+            tmp_dir.path(),
+            "pets.proto",
+            "syntax = \"proto3\";\npackage pets.v1;\n\
+             message Pet { int32 id = 1; string name = 2; bool adopted = 3; }\n\
+             service PetService {\n  rpc Update(Pet) returns (Pet);\n  rpc Get(Pet) returns (Pet);\n}\n",
+        );
+        let td = definition(
+            tmp_dir.path(),
+            &format!(
+                "stages:\n\
+                 \x20 - request:\n      url: http://{0}\n      grpc:\n        proto: pets.proto\n        method: pets.v1.PetService/Update\n      body:\n        id: 7\n        name: Rex\n\
+                 \x20   response:\n      body:\n        id: 7\n        name: Rex\n        adopted: false\n\
+                 \x20 - request:\n      url: http://{0}\n      grpc:\n        proto: pets.proto\n        method: pets.v1.PetService/Get\n      body:\n        id: 8\n\
+                 \x20   response:\n      status: 5\n",
+                address
+            ),
+        );
+
+        let (passed, results) = run(&mut state(), &td, 0, None).await.unwrap();
+
+        assert!(passed);
+        let actual: Vec<&ResultData> = results
+            .iter()
+            .map(|r| r.details.actual.as_ref().unwrap())
+            .collect();
+        assert_eq!(0, actual[0].status);
+        assert_eq!(
+            serde_json::json!({"id": 7, "name": "Rex", "adopted": false}),
+            actual[0].body
+        );
+        assert_eq!(5, actual[1].status);
+        assert_eq!("pet not found", test::grpc::message(&actual[1].headers));
+    }
 }
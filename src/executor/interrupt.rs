@@ -0,0 +1,135 @@
+use super::EXIT_INTERRUPTED;
+use log::error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use tokio::sync::Notify;
+
+// set once Ctrl-C or SIGTERM is received while tests run. each run has its own flag,
+// which is kept in its state
+#[derive(Clone, Default)]
+pub struct Flag(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    set: AtomicBool,
+    notify: Notify,
+}
+
+impl Flag {
+    pub fn is_set(&self) -> bool {
+        self.0.set.load(Ordering::SeqCst)
+    }
+
+    // returns whether it was already set
+    pub fn set(&self) -> bool {
+        let already = self.0.set.swap(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+        already
+    }
+
+    // resolves once the flag is set
+    pub async fn wait(&self) {
+        let notified = self.0.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        if !self.is_set() {
+            notified.await;
+        }
+    }
+}
+
+// the flag of the run in progress, if any, which signals are passed to
+static CURRENT: Mutex<Option<Flag>> = Mutex::new(None);
+static HANDLER: Once = Once::new();
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {},
+                _ = terminate.recv() => {},
+            }
+        }
+        Err(_) => _ = tokio::signal::ctrl_c().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    _ = tokio::signal::ctrl_c().await;
+}
+
+// stops passing signals to the run's flag when dropped
+pub struct Listener {
+    flag: Flag,
+}
+
+impl Listener {
+    pub fn flag(&self) -> Flag {
+        self.flag.clone()
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let mut current = CURRENT.lock().unwrap();
+        if current
+            .as_ref()
+            .is_some_and(|f| Arc::ptr_eq(&f.0, &self.flag.0))
+        {
+            *current = None;
+        }
+    }
+}
+
+// the first signal lets the in-flight request finish and cleanup run before the run stops,
+// a second one quits straight away. the handler stays installed once a run has started,
+// so a signal received between runs quits straight away as well
+pub fn listen() -> Listener {
+    let flag = Flag::default();
+    *CURRENT.lock().unwrap() = Some(flag.clone());
+
+    HANDLER.call_once(|| {
+        tokio::spawn(async {
+            loop {
+                signal().await;
+
+                let current = CURRENT.lock().unwrap().clone();
+                let already_interrupted = match current {
+                    Some(flag) => flag.set(),
+                    // between runs there's nothing to finish
+                    None => std::process::exit(EXIT_INTERRUPTED),
+                };
+
+                if already_interrupted {
+                    error!("interrupted again, quitting without cleanup");
+                    std::process::exit(EXIT_INTERRUPTED);
+                }
+
+                error!("interrupted, finishing the current request and running cleanup. press Ctrl-C again to quit");
+            }
+        });
+    });
+
+    Listener { flag }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_are_set_once() {
+        let flag = Flag::default();
+        let shared = flag.clone();
+
+        assert!(!flag.is_set());
+        assert!(!shared.set());
+        assert!(flag.is_set());
+        assert!(shared.set());
+    }
+}
@@ -0,0 +1,618 @@
+use super::{
+    interrupt, plan_tests, run, select, suite, Clients, StageResult, State, TestStatus, EXIT_ERROR,
+    EXIT_FAILED, EXIT_INTERRUPTED, EXIT_INVALID, EXIT_PASSED, INTERRUPT_TIMEOUT,
+};
+use crate::config;
+use crate::logger;
+use crate::test;
+use log::{error, info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::{JoinSet, LocalSet};
+use tokio::time::Instant;
+use url::Url;
+
+// 90s, 2m, 500ms or a number of seconds
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`, expected e.g. 30s or 2m", text))?;
+    let seconds = match unit {
+        "" | "s" => value,
+        "ms" => value / 1000.0,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => {
+            return Err(format!(
+                "unknown duration unit `{}`, expected ms, s, m or h",
+                unit
+            ))
+        }
+    };
+
+    if seconds <= 0.0 {
+        return Err("duration must be more than zero".to_string());
+    }
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+pub fn parse_rate(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!(
+            "invalid rate `{}`, expected requests per second",
+            text
+        )),
+    }
+}
+
+// hands out evenly spaced start times, shared by every worker
+pub struct Pacer {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    pub fn new(rate: f64) -> Pacer {
+        Pacer {
+            interval: Duration::from_secs_f64(1.0 / rate),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub async fn wait(&self) {
+        let at = {
+            let mut next = self.next.lock().await;
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+
+        tokio::time::sleep_until(at).await;
+    }
+}
+
+pub struct Options {
+    pub concurrency: u32,
+    // requests per second across every worker, as fast as possible when not given
+    pub rate: Option<f64>,
+    pub duration: Duration,
+    pub thresholds: Thresholds,
+}
+
+// latency and error rate limits hold for every stage, throughput for the whole run
+#[derive(Default)]
+pub struct Thresholds {
+    pub p50: Option<u32>,
+    pub p90: Option<u32>,
+    pub p99: Option<u32>,
+    // a percentage of requests
+    pub error_rate: Option<f64>,
+    // requests per second
+    pub throughput: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct Report {
+    pub requests: u32,
+    pub errors: u32,
+    pub breaches: u16,
+    // suite setup or teardown that failed
+    pub errored: u16,
+    pub invalid: u16,
+    pub interrupted: bool,
+}
+
+impl Report {
+    pub fn exit_code(&self) -> i32 {
+        if self.interrupted {
+            EXIT_INTERRUPTED
+        } else if self.errored > 0 {
+            EXIT_ERROR
+        } else if self.invalid > 0 {
+            EXIT_INVALID
+        } else if self.breaches > 0 {
+            EXIT_FAILED
+        } else {
+            EXIT_PASSED
+        }
+    }
+}
+
+// a request made under load. a test that stops because a request couldn't be sent is
+// counted as an error with no latency
+struct Sample {
+    test: usize,
+    label: String,
+    endpoint: String,
+    runtime: Option<u32>,
+    status: Option<u16>,
+    error: bool,
+}
+
+impl Sample {
+    fn from_result(test: usize, result: &StageResult) -> Sample {
+        let request = &result.details.request;
+        let path = Url::parse(&request.url)
+            .map(|u| u.path().to_string())
+            .unwrap_or(request.url.clone());
+
+        Sample {
+            test,
+            label: result.label(),
+            endpoint: format!("{} {}", request.method, path),
+            runtime: Some(result.runtime),
+            status: result.details.actual.as_ref().map(|a| a.status),
+            error: result.status == TestStatus::Failed || result.details.actual.is_none(),
+        }
+    }
+
+    fn unsent(test: usize) -> Sample {
+        Sample {
+            test,
+            label: "unsent".to_string(),
+            endpoint: "request couldn't be sent".to_string(),
+            runtime: None,
+            status: None,
+            error: true,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    requests: u32,
+    errors: u32,
+    // sorted, in milliseconds
+    latencies: Vec<u32>,
+    statuses: BTreeMap<u16, u32>,
+}
+
+impl Stats {
+    fn add(&mut self, sample: &Sample) {
+        self.requests += 1;
+
+        if sample.error {
+            self.errors += 1;
+        }
+
+        if let Some(runtime) = sample.runtime {
+            self.latencies.push(runtime);
+        }
+
+        if let Some(status) = sample.status {
+            *self.statuses.entry(status).or_default() += 1;
+        }
+    }
+
+    fn sort(&mut self) {
+        self.latencies.sort_unstable();
+    }
+
+    // nearest rank, so the value is one that was measured
+    fn percentile(&self, p: f64) -> Option<u32> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+
+        let rank = ((p / 100.0) * self.latencies.len() as f64).ceil() as usize;
+        Some(self.latencies[rank.clamp(1, self.latencies.len()) - 1])
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.errors as f64 * 100.0 / self.requests as f64
+        }
+    }
+
+    fn lines(&self, elapsed: Duration) -> Vec<String> {
+        let latency = |p: f64| {
+            self.percentile(p)
+                .map(|l| format!("{}ms", l))
+                .unwrap_or("-".to_string())
+        };
+        let statuses: Vec<String> = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{} x{}", status, count))
+            .collect();
+
+        vec![
+            format!(
+                "requests {} ({:.1}/s), errors {} ({:.2}%)",
+                self.requests,
+                self.requests as f64 / elapsed.as_secs_f64(),
+                self.errors,
+                self.error_rate()
+            ),
+            format!(
+                "latency p50 {}, p90 {}, p99 {}",
+                latency(50.0),
+                latency(90.0),
+                latency(99.0)
+            ),
+            format!(
+                "status {}",
+                if statuses.is_empty() {
+                    "-".to_string()
+                } else {
+                    statuses.join(", ")
+                }
+            ),
+        ]
+    }
+
+    // the thresholds this stage's results exceed
+    fn breaches(&self, thresholds: &Thresholds) -> Vec<String> {
+        let mut breaches = Vec::new();
+
+        for (name, p, limit) in [
+            ("p50", 50.0, thresholds.p50),
+            ("p90", 90.0, thresholds.p90),
+            ("p99", 99.0, thresholds.p99),
+        ] {
+            if let (Some(limit), Some(actual)) = (limit, self.percentile(p)) {
+                if actual > limit {
+                    breaches.push(format!("{} {}ms is over {}ms", name, actual, limit));
+                }
+            }
+        }
+
+        if let Some(limit) = thresholds.error_rate {
+            if self.error_rate() > limit {
+                breaches.push(format!(
+                    "error rate {:.2}% is over {}%",
+                    self.error_rate(),
+                    limit
+                ));
+            }
+        }
+
+        breaches
+    }
+}
+
+// the stages of each test, in the order they first ran
+fn summarize(samples: &[Sample]) -> (Vec<(usize, String, String, Stats)>, Stats) {
+    let mut stages: Vec<(usize, String, String, Stats)> = Vec::new();
+    let mut total = Stats::default();
+
+    for sample in samples {
+        let position = stages
+            .iter()
+            .position(|s| s.0 == sample.test && s.1 == sample.label);
+        let index = match position {
+            Some(i) => i,
+            None => {
+                stages.push((
+                    sample.test,
+                    sample.label.clone(),
+                    sample.endpoint.clone(),
+                    Stats::default(),
+                ));
+                stages.len() - 1
+            }
+        };
+
+        stages[index].3.add(sample);
+        total.add(sample);
+    }
+
+    stages.sort_by_key(|s| s.0);
+    for stage in stages.iter_mut() {
+        stage.3.sort();
+    }
+    total.sort();
+
+    (stages, total)
+}
+
+// runs the tests in order, over and over, until the time is up
+async fn worker(
+    tests: Rc<Vec<test::Definition>>,
+    mut state: State,
+    deadline: Instant,
+) -> Vec<Sample> {
+    let mut samples = Vec::new();
+
+    'load: loop {
+        for (i, td) in tests.iter().enumerate() {
+            for iteration in 0..td.iterate {
+                if Instant::now() >= deadline || state.interrupted.is_set() {
+                    break 'load;
+                }
+
+                match run(&mut state, td, iteration, None).await {
+                    Ok((_, results)) => samples.extend(
+                        results
+                            .iter()
+                            .filter(|r| r.status != TestStatus::Skipped)
+                            .map(|r| Sample::from_result(i, r)),
+                    ),
+                    Err(_) if state.interrupted.is_set() => {}
+                    Err(_) => samples.push(Sample::unsent(i)),
+                }
+            }
+        }
+    }
+
+    samples
+}
+
+// warnings and errors logged by the workers, which would otherwise flood the console
+#[derive(Default)]
+struct Logged {
+    count: usize,
+    first: Option<String>,
+}
+
+impl Logged {
+    fn add(&mut self, messages: Vec<String>) {
+        self.count += messages.len();
+        if self.first.is_none() {
+            self.first = messages.into_iter().next();
+        }
+    }
+}
+
+// the workers share a thread, as tests can't be sent between threads, which suits
+// workers that spend their time waiting on responses
+async fn apply_load(
+    tests: Rc<Vec<test::Definition>>,
+    state: &State,
+    options: &Options,
+) -> (Vec<Sample>, Logged, Duration) {
+    let pacer = options.rate.map(|r| Arc::new(Pacer::new(r)));
+    let start_time = Instant::now();
+    let deadline = start_time + options.duration;
+    let local = LocalSet::new();
+
+    local
+        .run_until(async {
+            let mut workers = JoinSet::new();
+
+            logger::start_capture();
+            for _ in 0..options.concurrency {
+                // each worker has its own variables, starting from those the suite setup extracted
+                let worker_state = State {
+                    variables: state.variables.clone(),
+                    base_url: state.base_url.clone(),
+                    interruptible: true,
+                    cleanup_failure: state.cleanup_failure,
+                    pacer: pacer.clone(),
+                    interrupted: state.interrupted.clone(),
+                    interrupt_timeout: state.interrupt_timeout,
+                    clients: state.clients.clone(),
+                };
+                workers.spawn_local(worker(tests.clone(), worker_state, deadline));
+            }
+
+            let mut samples = Vec::new();
+            let mut logged = Logged::default();
+            loop {
+                tokio::select! {
+                    joined = workers.join_next() => match joined {
+                        Some(Ok(s)) => samples.extend(s),
+                        Some(Err(e)) => logged.add(vec![e.to_string()]),
+                        None => break,
+                    },
+                    _ = tokio::time::sleep(Duration::from_secs(1)) => logged.add(logger::take_captured()),
+                }
+            }
+            logged.add(logger::stop_capture());
+
+            (samples, logged, start_time.elapsed())
+        })
+        .await
+}
+
+pub async fn load_tests(
+    config: config::Config,
+    files: Vec<String>,
+    filter: select::Filter,
+    base_url: Option<String>,
+    options: Options,
+) -> Report {
+    let global_variables = config.generate_global_variables();
+    let (planned, mut invalid) = plan_tests(&files, &global_variables, &filter);
+    let (mut suites, suite_invalid) = suite::discover(&planned, &global_variables);
+    invalid += suite_invalid;
+
+    let listener = interrupt::listen();
+    let mut state = State {
+        variables: HashMap::new(),
+        base_url,
+        interruptible: true,
+        cleanup_failure: config.settings.cleanup_failure,
+        pacer: None,
+        interrupted: listener.flag(),
+        interrupt_timeout: INTERRUPT_TIMEOUT,
+        clients: Clients::new(),
+    };
+    let mut report = Report {
+        invalid,
+        ..Default::default()
+    };
+
+    report.errored += suite::set_up(&mut state, &mut suites).await.len() as u16;
+    let tests: Vec<test::Definition> = planned
+        .into_iter()
+        .filter(|td| td.skip.is_none() && suite::failed_suite(&suites, td).is_none())
+        .collect();
+
+    if tests.is_empty() {
+        error!("no tests to put under load");
+    } else {
+        info!(
+            "Jikken is running {} test{} with {} worker{} for {:.0?}{}...\n",
+            tests.len(),
+            if tests.len() != 1 { "s" } else { "" },
+            options.concurrency,
+            if options.concurrency != 1 { "s" } else { "" },
+            options.duration,
+            options
+                .rate
+                .map(|r| format!(" at {} requests per second", r))
+                .unwrap_or_default()
+        );
+
+        let tests = Rc::new(tests);
+        let (samples, logged, elapsed) = apply_load(tests.clone(), &state, &options).await;
+
+        if let Some(first) = &logged.first {
+            warn!(
+                "{} warning{} or error{} logged under load, the first: {}",
+                logged.count,
+                if logged.count != 1 { "s" } else { "" },
+                if logged.count != 1 { "s" } else { "" },
+                first
+            );
+        }
+
+        let (stages, total) = summarize(&samples);
+        for (test, label, endpoint, stats) in stages.iter() {
+            let name = tests[*test]
+                .name
+                .clone()
+                .unwrap_or(format!("Test {}", test + 1));
+            let mut lines = vec![format!("`{}` {}: {}", name, label, endpoint)];
+            lines.extend(stats.lines(elapsed).into_iter().map(|l| format!("  {}", l)));
+
+            for breach in stats.breaches(&options.thresholds) {
+                lines.push(format!("  \x1b[31mSLA breached: {}\x1b[0m", breach));
+                report.breaches += 1;
+            }
+
+            info!("\n{}\n", lines.join("\n"));
+        }
+
+        let mut lines = vec!["Total".to_string()];
+        lines.extend(total.lines(elapsed).into_iter().map(|l| format!("  {}", l)));
+
+        let throughput = total.requests as f64 / elapsed.as_secs_f64();
+        if let Some(limit) = options.thresholds.throughput {
+            if throughput < limit {
+                lines.push(format!(
+                    "  \x1b[31mSLA breached: throughput {:.1}/s is under {}/s\x1b[0m",
+                    throughput, limit
+                ));
+                report.breaches += 1;
+            }
+        }
+
+        info!("\n{}\n\n", lines.join("\n"));
+        report.requests = total.requests;
+        report.errors = total.errors;
+    }
+
+    report.errored += suite::tear_down(&mut state, &suites).await.len() as u16;
+    report.interrupted = state.interrupted.is_set();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::tests::{server_counting_connections, write};
+    use std::sync::atomic::Ordering;
+    use tempfile::tempdir;
+
+    fn sample(label: &str, runtime: u32, status: u16) -> Sample {
+        Sample {
+            test: 0,
+            label: label.to_string(),
+            endpoint: "GET /pets".to_string(),
+            runtime: Some(runtime),
+            status: Some(status),
+            error: status >= 500,
+        }
+    }
+
+    #[test]
+    fn stages_are_summarized_and_checked_against_thresholds() {
+        let mut samples: Vec<Sample> = (1..=100).map(|ms| sample("stage 1", ms, 200)).collect();
+        samples.push(sample("stage 2", 40, 503));
+        samples.push(Sample::unsent(0));
+
+        let (stages, total) = summarize(&samples);
+
+        assert_eq!(3, stages.len());
+        let first = &stages[0].3;
+        assert_eq!(Some(50), first.percentile(50.0));
+        assert_eq!(Some(90), first.percentile(90.0));
+        assert_eq!(Some(99), first.percentile(99.0));
+        assert_eq!(102, total.requests);
+        assert_eq!(2, total.errors);
+        assert_eq!(
+            vec![
+                "requests 102 (51.0/s), errors 2 (1.96%)",
+                "latency p50 50ms, p90 90ms, p99 99ms",
+                "status 200 x100, 503 x1",
+            ],
+            total.lines(Duration::from_secs(2))
+        );
+
+        let thresholds = Thresholds {
+            p90: Some(80),
+            p99: Some(100),
+            error_rate: Some(1.0),
+            ..Default::default()
+        };
+        assert_eq!(vec!["p90 90ms is over 80ms"], first.breaches(&thresholds));
+        assert_eq!(
+            vec!["error rate 100.00% is over 1%"],
+            stages[1].3.breaches(&thresholds)
+        );
+
+        assert_eq!(Duration::from_secs(90), parse_duration("90").unwrap());
+        assert_eq!(Duration::from_millis(1500), parse_duration("1.5s").unwrap());
+        assert_eq!(Duration::from_secs(120), parse_duration("2m").unwrap());
+        assert!(parse_duration("2d").is_err());
+    }
+
+    #[tokio::test]
+    async fn workers_share_connections_to_the_server() {
+        let (address, requests, connections) = server_counting_connections();
+        let tmp_dir = tempdir().unwrap();
+        let files = vec![write(
+            tmp_dir.path(),
+            "a.jkt",
+            &format!(
+                "stages:\n  - request:\n      url: http://{0}/pets\n  - request:\n      url: http://{0}/pets/1\n",
+                address
+            ),
+        )];
+        let options = Options {
+            concurrency: 3,
+            rate: None,
+            duration: Duration::from_millis(300),
+            thresholds: Thresholds::default(),
+        };
+
+        let report = load_tests(
+            config::Config::default(),
+            files,
+            select::Filter::default(),
+            None,
+            options,
+        )
+        .await;
+
+        assert_eq!(0, report.errors);
+        assert_eq!(EXIT_PASSED, report.exit_code());
+        assert!(report.requests > 10);
+        assert_eq!(report.requests as usize, requests.lock().unwrap().len());
+        assert!(connections.load(Ordering::SeqCst) <= 3);
+    }
+}
@@ -0,0 +1,534 @@
+use super::{ResultData, StageResult, StageType, TestStatus};
+use crate::errors::GenericError;
+use crate::test::validation::document::{self, Node, NodeKind};
+use std::collections::HashSet;
+use std::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // write every stage that ran
+    All,
+    // only rewrite the stages that failed, like updating snapshots
+    Failed,
+}
+
+struct Edit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+// renders `key: value` for a value that starts at `indent`, nested lines are indented to match
+fn render_entry(key: &str, value: &serde_yaml::Value, indent: usize) -> String {
+    let rendered = serde_yaml::to_string(value).unwrap_or_default();
+    let rendered = rendered.trim_end();
+
+    match value {
+        serde_yaml::Value::Mapping(m) if !m.is_empty() => {
+            let padding = " ".repeat(indent + 2);
+            let lines: Vec<String> = rendered
+                .lines()
+                .map(|l| format!("\n{}{}", padding, l))
+                .collect();
+            format!("{}:{}", key, lines.concat())
+        }
+        serde_yaml::Value::Sequence(s) if !s.is_empty() => {
+            let padding = " ".repeat(indent + 2);
+            let lines: Vec<String> = rendered
+                .lines()
+                .map(|l| format!("\n{}{}", padding, l))
+                .collect();
+            format!("{}:{}", key, lines.concat())
+        }
+        _ => {
+            let padding = " ".repeat(indent);
+            let mut lines = rendered.lines();
+            let first = lines.next().unwrap_or_default();
+            let rest: Vec<String> = lines.map(|l| format!("\n{}{}", padding, l)).collect();
+            format!("{}: {}{}", key, first, rest.concat())
+        }
+    }
+}
+
+// new entries go after the given byte index, but after any comment on the same line
+fn insertion_point(source: &str, start: usize) -> usize {
+    let line_end = source[start..]
+        .find('\n')
+        .map(|i| start + i)
+        .unwrap_or(source.len());
+    let remainder = source[start..line_end].trim();
+
+    if remainder.is_empty() || remainder.starts_with('#') {
+        line_end
+    } else {
+        start
+    }
+}
+
+fn recorded_headers(
+    existing: Option<&Node>,
+    data: &ResultData,
+    selected: &[String],
+) -> Option<serde_yaml::Value> {
+    let mut names: Vec<String> = existing
+        .map(|h| {
+            h.items()
+                .iter()
+                .filter_map(|i| i.get("header").and_then(|n| n.as_str()))
+                .map(|n| n.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    for name in selected.iter() {
+        if !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            names.push(name.clone());
+        }
+    }
+
+    let headers: Vec<serde_yaml::Value> = names
+        .iter()
+        .filter_map(|name| {
+            let value = data
+                .headers
+                .iter()
+                .find(|h| h.header.eq_ignore_ascii_case(name))
+                .map(|h| h.value.clone())
+                .or_else(|| {
+                    // keep expectations for headers the response didn't include
+                    existing?
+                        .items()
+                        .iter()
+                        .find(|i| i.get("header").and_then(|n| n.as_str()) == Some(name))?
+                        .get("value")?
+                        .as_str()
+                        .map(|v| v.to_string())
+                })?;
+
+            let mut entry = serde_yaml::Mapping::new();
+            entry.insert("header".into(), name.as_str().into());
+            entry.insert("value".into(), value.into());
+            Some(serde_yaml::Value::Mapping(entry))
+        })
+        .collect();
+
+    if headers.is_empty() {
+        None
+    } else {
+        Some(serde_yaml::Value::Sequence(headers))
+    }
+}
+
+fn recorded_fields(
+    response: Option<&Node>,
+    data: &ResultData,
+    headers: &[String],
+) -> Vec<(&'static str, serde_yaml::Value)> {
+    let mut fields = vec![("status", serde_yaml::Value::from(data.status))];
+
+    if let Some(h) = recorded_headers(response.and_then(|r| r.get("headers")), data, headers) {
+        fields.push(("headers", h));
+    }
+
+    // non JSON bodies are not captured, so any existing expectation is left as is
+    if !data.body.is_null() {
+        if let Ok(body) = serde_yaml::to_value(&data.body) {
+            fields.push(("body", body));
+        }
+    }
+
+    fields
+}
+
+// rewrites the status, headers and body of a stage's response block, everything else in the
+// file (comments, ignore and extract lists, other keys) is left untouched
+fn response_edits(
+    source: &str,
+    container: &Node,
+    data: &ResultData,
+    headers: &[String],
+) -> Result<Vec<Edit>, Box<dyn Error + Send + Sync>> {
+    let entries = match &container.kind {
+        NodeKind::Mapping(entries) if !container.is_flow() && !entries.is_empty() => entries,
+        _ => {
+            return Err(Box::new(GenericError {
+                reason: format!(
+                    "unable to record line {}, only block style stages can be recorded",
+                    container.line
+                ),
+            }))
+        }
+    };
+    let indent = entries[0].0.column - 1;
+
+    let response = match container.entry("response") {
+        Some((_, r)) if matches!(r.kind, NodeKind::Mapping(_)) && !r.is_flow() => r,
+        Some((key, r)) => {
+            // empty or flow style responses are rewritten in block style
+            let existing: Option<serde_yaml::Value> =
+                serde_yaml::from_str(&source[r.index..r.content_end()]).ok();
+            let mut mapping = match existing {
+                Some(serde_yaml::Value::Mapping(m)) => m,
+                _ => serde_yaml::Mapping::new(),
+            };
+
+            for (field, value) in recorded_fields(None, data, headers) {
+                mapping.insert(field.into(), value);
+            }
+
+            return Ok(vec![Edit {
+                start: key.index,
+                end: r.content_end(),
+                text: render_entry("response", &serde_yaml::Value::Mapping(mapping), indent),
+            }]);
+        }
+        None => {
+            let mut mapping = serde_yaml::Mapping::new();
+            for (field, value) in recorded_fields(None, data, headers) {
+                mapping.insert(field.into(), value);
+            }
+
+            // keep the request, compare, response ordering the templates use
+            let after = container
+                .get("compare")
+                .or_else(|| container.get("request"))
+                .unwrap_or(&entries[entries.len() - 1].1);
+            let point = insertion_point(source, after.content_end());
+
+            return Ok(vec![Edit {
+                start: point,
+                end: point,
+                text: format!(
+                    "\n{}{}",
+                    " ".repeat(indent),
+                    render_entry("response", &serde_yaml::Value::Mapping(mapping), indent)
+                ),
+            }]);
+        }
+    };
+
+    let response_entries = match &response.kind {
+        NodeKind::Mapping(e) => e,
+        _ => return Ok(Vec::new()),
+    };
+    let response_indent = response_entries[0].0.column - 1;
+    let mut edits = Vec::new();
+    let mut missing = String::new();
+
+    for (field, value) in recorded_fields(Some(response), data, headers) {
+        match response.entry(field) {
+            Some((key, existing)) => edits.push(Edit {
+                start: key.index,
+                end: existing.content_end(),
+                text: render_entry(field, &value, response_indent),
+            }),
+            None => {
+                missing.push_str(&format!(
+                    "\n{}{}",
+                    " ".repeat(response_indent),
+                    render_entry(field, &value, response_indent)
+                ));
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        let point = insertion_point(source, response.content_end());
+        edits.push(Edit {
+            start: point,
+            end: point,
+            text: missing,
+        });
+    }
+
+    Ok(edits)
+}
+
+// finds the mapping holding the request for a stage result. the top-level request, when
+// present, is stage 0 and the entries under `stages` follow it
+fn stage_container<'a>(root: &'a Node, result: &StageResult) -> Option<&'a Node> {
+    match result.stage_type {
+        StageType::Setup => {
+            let setup = root.get("setup")?;
+
+            match setup.kind {
+                NodeKind::Sequence(_) => setup.items().get(result.stage as usize),
+                _ => Some(setup),
+            }
+        }
+        StageType::Normal => {
+            let offset = if root.get("request").is_some() { 1 } else { 0 };
+
+            if result.stage < offset {
+                Some(root)
+            } else {
+                root.get("stages")?
+                    .items()
+                    .get((result.stage - offset) as usize)
+            }
+        }
+        StageType::Cleanup => None,
+    }
+}
+
+// updates the response blocks of a test file with the actual responses from a run,
+// returning the number of stages that were recorded
+pub fn record(
+    filename: &str,
+    results: &[StageResult],
+    mode: Mode,
+    headers: &[String],
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let source = std::fs::read_to_string(filename)?;
+    let root = match document::parse(&source) {
+        Ok(Some(root)) => root,
+        Ok(None) => return Ok(0),
+        Err(e) => {
+            return Err(Box::new(GenericError {
+                reason: format!("{}:{}:{}: {}", filename, e.line, e.column, e.reason),
+            }))
+        }
+    };
+
+    let mut edits = Vec::new();
+    let mut recorded = 0;
+
+    let mut seen = HashSet::new();
+
+    for result in results.iter() {
+        if mode == Mode::Failed && result.status != TestStatus::Failed {
+            continue;
+        }
+
+        // stages repeated by `forEach`, `while` or `until` are recorded from their first run
+        if !seen.insert((result.stage_type.clone() as u32, result.stage)) {
+            continue;
+        }
+
+        // streams check their messages rather than a response, so they aren't recorded
+        let (container, data) = match (stage_container(&root, result), &result.details.actual) {
+            (Some(c), Some(d)) if c.get("stream").is_none() => (c, d),
+            _ => continue,
+        };
+
+        edits.append(&mut response_edits(&source, container, data, headers)?);
+        recorded += 1;
+    }
+
+    if edits.is_empty() {
+        return Ok(0);
+    }
+
+    // apply from the end of the file so earlier offsets stay valid
+    edits.sort_by_key(|e| std::cmp::Reverse(e.start));
+    let mut updated = source.clone();
+    for edit in edits.iter() {
+        updated.replace_range(edit.start..edit.end, &edit.text);
+    }
+
+    std::fs::write(filename, updated)?;
+    Ok(recorded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{RequestDetails, ResultDetails};
+    use crate::test::http;
+    use serde_json::json;
+
+    fn result(stage: u32, status: TestStatus, body: serde_json::Value) -> StageResult {
+        let data = ResultData {
+            headers: vec![http::Header::new(
+                "content-type".to_string(),
+                "application/json".to_string(),
+            )],
+            status: 201,
+            body,
+        };
+
+        StageResult {
+            stage,
+            stage_type: StageType::Normal,
+            runtime: 0,
+            status,
+            details: ResultDetails {
+                request: RequestDetails {
+                    headers: Vec::new(),
+                    url: String::new(),
+                    method: http::Verb::Get.as_method(),
+                    body: serde_json::Value::Null,
+                },
+                expected: ResultData::default(),
+                actual: Some(data),
+                compare_request: None,
+                compare_actual: None,
+            },
+            failures: Vec::new(),
+        }
+    }
+
+    fn record_source(
+        source: &str,
+        results: &[StageResult],
+        mode: Mode,
+        headers: &[String],
+    ) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("test.jkt");
+        std::fs::write(&filename, source).unwrap();
+        record(filename.to_str().unwrap(), results, mode, headers).unwrap();
+        std::fs::read_to_string(&filename).unwrap()
+    }
+
+    #[test]
+    fn existing_responses_keep_comments_and_ignore() {
+        let source = "\
+# a pet
+request:
+  url: https://jikken.io/pets
+response:
+  status: 200 # created
+  body: {
+    \"id\": 1
+  }
+  ignore:
+    - id
+";
+        let updated = record_source(
+            source,
+            &[result(
+                0,
+                TestStatus::Passed,
+                json!({"id": 2, "tags": ["a"]}),
+            )],
+            Mode::All,
+            &["Content-Type".to_string()],
+        );
+
+        assert_eq!(
+            "\
+# a pet
+request:
+  url: https://jikken.io/pets
+response:
+  status: 201 # created
+  body:
+    id: 2
+    tags:
+    - a
+  ignore:
+    - id
+  headers:
+    - header: Content-Type
+      value: application/json
+",
+            updated
+        );
+    }
+
+    #[test]
+    fn offsets_after_non_ascii_text_are_kept() {
+        let source = "\
+# ペットの作成 🐾
+name: créer
+request:
+  url: https://jikken.io/pets
+response:
+  status: 200
+  body: {\"name\": \"ポチ\"}
+";
+        let updated = record_source(
+            source,
+            &[result(0, TestStatus::Passed, json!({"name": "タマ"}))],
+            Mode::All,
+            &[],
+        );
+
+        assert_eq!(
+            "\
+# ペットの作成 🐾
+name: créer
+request:
+  url: https://jikken.io/pets
+response:
+  status: 201
+  body:
+    name: タマ
+",
+            updated
+        );
+    }
+
+    #[test]
+    fn missing_responses_are_added_after_the_request() {
+        let source = "\
+stages:
+  - request:
+      url: https://jikken.io/a
+  - request:
+      url: https://jikken.io/b
+    # check b
+    variables: []
+";
+        let updated = record_source(
+            source,
+            &[
+                result(0, TestStatus::Passed, json!({"a": 1})),
+                result(1, TestStatus::Failed, json!({"b": 1})),
+            ],
+            Mode::Failed,
+            &[],
+        );
+
+        assert_eq!(
+            "\
+stages:
+  - request:
+      url: https://jikken.io/a
+  - request:
+      url: https://jikken.io/b
+    response:
+      status: 201
+      body:
+        b: 1
+    # check b
+    variables: []
+",
+            updated
+        );
+    }
+
+    #[test]
+    fn setup_steps_are_recorded_by_position() {
+        let source = "\
+setup:
+  - request:
+      url: https://jikken.io/tenant
+  - request:
+      url: https://jikken.io/login
+request:
+  url: https://jikken.io/a
+";
+        let mut login = result(1, TestStatus::Passed, json!({"token": "t"}));
+        login.stage_type = StageType::Setup;
+        let updated = record_source(source, &[login], Mode::All, &[]);
+
+        assert_eq!(
+            "\
+setup:
+  - request:
+      url: https://jikken.io/tenant
+  - request:
+      url: https://jikken.io/login
+    response:
+      status: 201
+      body:
+        token: t
+request:
+  url: https://jikken.io/a
+",
+            updated
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use crate::errors::GenericError;
+use crate::test;
+use crate::TagMode;
+use log::debug;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+
+// a boolean expression over tags, such as `smoke and not (slow or flaky)`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Tag(String),
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    // `or` binds loosest, then `and`, then `not`
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut expression = self.and()?;
+
+        while self.peek() == Some("or") {
+            self.next();
+            expression = Expression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut expression = self.not()?;
+
+        while self.peek() == Some("and") {
+            self.next();
+            expression = Expression::And(Box::new(expression), Box::new(self.not()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn not(&mut self) -> Result<Expression, String> {
+        match self.next().as_deref() {
+            Some("not") => Ok(Expression::Not(Box::new(self.not()?))),
+            Some("(") => {
+                let expression = self.or()?;
+                match self.next().as_deref() {
+                    Some(")") => Ok(expression),
+                    _ => Err("missing `)`".to_string()),
+                }
+            }
+            Some(t @ ("and" | "or" | ")")) => Err(format!("unexpected `{}`", t)),
+            Some(t) => Ok(Expression::Tag(t.to_string())),
+            None => Err("expected a tag".to_string()),
+        }
+    }
+}
+
+impl Expression {
+    // tags are matched case-insensitively, as test files' tags are lowercased when loaded
+    pub fn parse(text: &str) -> Result<Expression, GenericError> {
+        let mut parser = Parser {
+            tokens: tokenize(text),
+            position: 0,
+        };
+        let result = parser.or().and_then(|e| match parser.peek() {
+            Some(t) => Err(format!("unexpected `{}`", t)),
+            None => Ok(e),
+        });
+
+        result.map_err(|reason| GenericError {
+            reason: format!("invalid tag expression `{}`: {}", text, reason),
+        })
+    }
+
+    pub fn matches(&self, tags: &HashSet<String>) -> bool {
+        match self {
+            Expression::Tag(t) => tags.contains(t),
+            Expression::Not(e) => !e.matches(tags),
+            Expression::And(a, b) => a.matches(tags) && b.matches(tags),
+            Expression::Or(a, b) => a.matches(tags) || b.matches(tags),
+        }
+    }
+}
+
+// used by clap to reject malformed expressions before anything runs
+pub fn parse_tag_expression(text: &str) -> Result<String, String> {
+    Expression::parse(text)
+        .map(|_| text.to_string())
+        .map_err(|e| e.to_string())
+}
+
+pub fn parse_name_pattern(text: &str) -> Result<String, String> {
+    Regex::new(text)
+        .map(|_| text.to_string())
+        .map_err(|e| e.to_string())
+}
+
+// which tests a run selects. every condition given must hold for a test to be selected
+#[derive(Clone)]
+pub struct Filter {
+    pub tags: Vec<Expression>,
+    pub tag_mode: TagMode,
+    pub exclude_tags: Vec<String>,
+    pub name: Option<Regex>,
+    pub ids: Vec<String>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            tags: Vec::new(),
+            tag_mode: TagMode::AND,
+            exclude_tags: Vec::new(),
+            name: None,
+            ids: Vec::new(),
+        }
+    }
+}
+
+impl Filter {
+    pub fn new(
+        tags: &[String],
+        tags_or: bool,
+        exclude_tags: &[String],
+        name: Option<&str>,
+        ids: &[String],
+    ) -> Result<Filter, GenericError> {
+        Ok(Filter {
+            tags: tags
+                .iter()
+                .map(|t| Expression::parse(t))
+                .collect::<Result<Vec<_>, _>>()?,
+            tag_mode: if tags_or { TagMode::OR } else { TagMode::AND },
+            exclude_tags: exclude_tags.iter().map(|t| t.to_lowercase()).collect(),
+            name: name
+                .map(|n| {
+                    Regex::new(n).map_err(|e| GenericError {
+                        reason: format!("invalid name pattern `{}`: {}", n, e),
+                    })
+                })
+                .transpose()?,
+            ids: ids.iter().map(|i| i.to_lowercase()).collect(),
+        })
+    }
+
+    pub fn matches(&self, td: &test::Definition) -> bool {
+        let name = td.name.clone().unwrap_or_else(|| td.filename.clone());
+        let td_tags: HashSet<String> = HashSet::from_iter(td.tags.clone());
+
+        if !self.ids.is_empty() && !self.ids.contains(&td.id) {
+            debug!(
+                "test `{}` doesn't match any ids: {}",
+                name,
+                self.ids.join(", ")
+            );
+            return false;
+        }
+
+        if let Some(pattern) = &self.name {
+            if !pattern.is_match(&name) {
+                debug!("test `{}` doesn't match name: {}", name, pattern);
+                return false;
+            }
+        }
+
+        if let Some(t) = self.exclude_tags.iter().find(|t| td_tags.contains(*t)) {
+            debug!("test `{}` has excluded tag: {}", name, t);
+            return false;
+        }
+
+        let selected = match self.tag_mode {
+            TagMode::OR => self.tags.is_empty() || self.tags.iter().any(|e| e.matches(&td_tags)),
+            TagMode::AND => self.tags.iter().all(|e| e.matches(&td_tags)),
+        };
+
+        if !selected {
+            debug!("test `{}` doesn't match the tag pattern", name);
+        }
+
+        selected
+    }
+}
+
+// orders the selected tests by name, each preceded by the chain of tests it `requires`,
+// which are pulled in from `others` when they weren't selected themselves
+pub fn with_dependencies(
+    mut selected: Vec<test::Definition>,
+    others: Vec<test::Definition>,
+) -> Vec<test::Definition> {
+    let tests_by_id: HashMap<String, test::Definition> = selected
+        .iter()
+        .cloned()
+        .chain(others)
+        .map(|td| (td.id.clone(), td))
+        .collect();
+
+    selected.sort_by(|a, b| a.name.partial_cmp(&b.name).unwrap());
+
+    let mut duplicate_filter: HashSet<String> = HashSet::new();
+    let mut ordered: Vec<test::Definition> = Vec::new();
+
+    for td in selected.into_iter() {
+        let mut requires = td.requires.clone();
+        let mut chain = vec![td];
+
+        while let Some(req) = requires {
+            if duplicate_filter.contains(&req) || chain.iter().any(|t| t.id == req) {
+                break;
+            }
+
+            match tests_by_id.get(&req) {
+                Some(dependency) => {
+                    requires = dependency.requires.clone();
+                    chain.push(dependency.clone());
+                }
+                None => break,
+            }
+        }
+
+        for td in chain.into_iter().rev() {
+            if duplicate_filter.insert(td.id.clone()) {
+                ordered.push(td);
+            }
+        }
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::validation;
+
+    #[test]
+    fn tag_expressions_respect_precedence_and_case() {
+        let tags: HashSet<String> = HashSet::from_iter(["smoke".to_string(), "slow".to_string()]);
+
+        assert!(Expression::parse("SMOKE").unwrap().matches(&tags));
+        assert!(!Expression::parse("smoke and not slow")
+            .unwrap()
+            .matches(&tags));
+        assert!(Expression::parse("regression or smoke and slow")
+            .unwrap()
+            .matches(&tags));
+        assert!(
+            !Expression::parse("(regression or smoke) and not (slow or flaky)")
+                .unwrap()
+                .matches(&tags)
+        );
+        assert!(Expression::parse("smoke and").is_err());
+        assert!(Expression::parse("(smoke or slow").is_err());
+        assert!(Expression::parse("smoke slow").is_err());
+    }
+
+    #[test]
+    fn ids_and_requirements_ignore_case() {
+        let definition = |contents: &str| {
+            let file: test::File = serde_yaml::from_str(contents).unwrap();
+            validation::validate_file(file, &[]).unwrap()
+        };
+        let login = definition("id: Login\nname: login\nrequest:\n  url: http://localhost/login\n");
+        let profile = definition(
+            "id: profile\nname: profile\nrequires: LOGIN\nrequest:\n  url: http://localhost/me\n",
+        );
+
+        let filter = Filter::new(&[], false, &[], None, &["PROFILE".to_string()]).unwrap();
+        assert!(filter.matches(&profile));
+        assert!(!filter.matches(&login));
+
+        let ids: Vec<String> = with_dependencies(vec![profile], vec![login])
+            .into_iter()
+            .map(|td| td.id)
+            .collect();
+        assert_eq!(vec!["login", "profile"], ids);
+    }
+}
@@ -0,0 +1,549 @@
+use super::{
+    interrupt, plan_tests, run, select, suite, summary, validate_compare, Clients, Failure,
+    StageResult, StageType, State, TestStatus, EXIT_ERROR, EXIT_FAILED, EXIT_INTERRUPTED,
+    EXIT_INVALID, EXIT_PASSED, INTERRUPT_TIMEOUT,
+};
+use crate::config;
+use crate::errors::GenericError;
+use crate::test;
+use crate::test::definition::CompareResponseDescriptor;
+use log::{error, info};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use url::Url;
+
+// headers which differ on every response, left out unless asked for with `--header`
+const VOLATILE_HEADERS: [&str; 1] = ["date"];
+
+#[derive(Default)]
+pub struct Report {
+    pub compared: u16,
+    pub matched: u16,
+    pub diverged: u16,
+    // iterations where a request couldn't be sent to either environment
+    pub errored: u16,
+    pub invalid: u16,
+    pub interrupted: bool,
+}
+
+impl Report {
+    pub fn exit_code(&self) -> i32 {
+        if self.interrupted {
+            EXIT_INTERRUPTED
+        } else if self.errored > 0 {
+            EXIT_ERROR
+        } else if self.invalid > 0 {
+            EXIT_INVALID
+        } else if self.diverged > 0 {
+            EXIT_FAILED
+        } else {
+            EXIT_PASSED
+        }
+    }
+}
+
+// what's compared between the two environments' responses
+#[derive(Default)]
+pub struct Rules {
+    // body paths ignored in every stage, along with each stage's own `ignore` list
+    pub ignore: Vec<String>,
+    pub headers: Vec<String>,
+    pub ignore_headers: Vec<String>,
+}
+
+impl Rules {
+    fn response(&self) -> CompareResponseDescriptor {
+        let mut ignore_headers = self.ignore_headers.clone();
+        ignore_headers.extend(
+            VOLATILE_HEADERS
+                .iter()
+                .filter(|v| !self.headers.iter().any(|h| h.eq_ignore_ascii_case(v)))
+                .map(|v| v.to_string()),
+        );
+
+        CompareResponseDescriptor {
+            ignore: None,
+            headers: self.headers.clone(),
+            ignore_headers,
+            ..Default::default()
+        }
+    }
+}
+
+// an environment is either named in the config's `environments` table or given as a base url
+pub fn base_url(config: &config::Config, environment: &str) -> Result<String, GenericError> {
+    if let Some(url) = config.environments.get(environment) {
+        return Ok(url.clone());
+    }
+
+    match Url::parse(environment) {
+        Ok(u) if u.scheme() == "http" || u.scheme() == "https" => Ok(environment.to_string()),
+        _ => Err(GenericError {
+            reason: format!(
+                "unknown environment `{}`, expected a base url or a name from the `environments` table of the config",
+                environment
+            ),
+        }),
+    }
+}
+
+fn state(config: &config::Config, base_url: &str, interrupted: interrupt::Flag) -> State {
+    State {
+        variables: HashMap::new(),
+        base_url: Some(base_url.to_string()),
+        interruptible: true,
+        cleanup_failure: config.settings.cleanup_failure,
+        pacer: None,
+        interrupted,
+        interrupt_timeout: INTERRUPT_TIMEOUT,
+        clients: Clients::new(),
+    }
+}
+
+fn stage_ignore(td: &test::Definition, result: &StageResult) -> Vec<String> {
+    let response = match result.stage_type {
+        StageType::Setup => td
+            .setup
+            .get(result.stage as usize)
+            .and_then(|s| s.response.as_ref()),
+        StageType::Normal => td
+            .stages
+            .get(result.stage as usize)
+            .and_then(|s| s.response.as_ref()),
+        StageType::Cleanup => None,
+    };
+
+    response.map(|r| r.ignore.clone()).unwrap_or_default()
+}
+
+// pairs up the results of each environment by stage and by how often that stage has run, so
+// a stage repeated more often in one environment doesn't shift the ones after it. cleanup
+// steps are numbered after the stages that ran, so they're paired by their order instead
+fn pair<'a>(
+    baseline: &'a [StageResult],
+    candidate: &'a [StageResult],
+) -> Vec<(Option<&'a StageResult>, Option<&'a StageResult>)> {
+    let keyed = |results: &'a [StageResult]| {
+        let mut runs: HashMap<(u32, u32), u32> = HashMap::new();
+        results
+            .iter()
+            .map(|r| {
+                let stage = match r.stage_type {
+                    StageType::Cleanup => 0,
+                    _ => r.stage,
+                };
+                let run = runs
+                    .entry((r.stage_type.clone() as u32, stage))
+                    .or_default();
+                *run += 1;
+                ((r.stage_type.clone() as u32, stage, *run), r)
+            })
+            .collect::<Vec<_>>()
+    };
+    let baseline = keyed(baseline);
+    let mut candidate = keyed(candidate);
+
+    let mut pairs: Vec<(Option<&StageResult>, Option<&StageResult>)> = baseline
+        .into_iter()
+        .map(|(key, b)| {
+            let c = candidate
+                .iter()
+                .position(|(k, _)| *k == key)
+                .map(|i| candidate.remove(i).1);
+            (Some(b), c)
+        })
+        .collect();
+    pairs.extend(candidate.into_iter().map(|(_, c)| (None, Some(c))));
+    pairs
+}
+
+// returns the candidate's results for the requests whose responses differ, along with the
+// requests only one environment made, such as when a failing stage stopped its test early
+fn divergences(
+    td: &test::Definition,
+    baseline: &[StageResult],
+    candidate: &[StageResult],
+    rules: &Rules,
+) -> Vec<StageResult> {
+    let response_rules = rules.response();
+    let mut diverged = Vec::new();
+
+    for (b, c) in pair(baseline, candidate) {
+        let (b, c) = match (b, c) {
+            (Some(b), Some(c)) => (b, c),
+            (Some(only), None) | (None, Some(only)) => {
+                let mut result = only.clone();
+                result.status = TestStatus::Failed;
+                result.failures = vec![Failure::Error(
+                    if b.is_some() {
+                        "only requested from the baseline"
+                    } else {
+                        "only requested from the candidate"
+                    }
+                    .to_string(),
+                )];
+                diverged.push(result);
+                continue;
+            }
+            (None, None) => continue,
+        };
+
+        if b.status == TestStatus::Skipped && c.status == TestStatus::Skipped {
+            continue;
+        }
+
+        let failures = match (&b.details.actual, &c.details.actual) {
+            (Some(b_actual), Some(c_actual)) => {
+                let ignore = [stage_ignore(td, b), rules.ignore.clone()].concat();
+                validate_compare(c_actual, b_actual, &response_rules, &ignore)
+            }
+            (None, None) => Vec::new(),
+            (Some(_), None) => vec![Failure::Error("no response from the candidate".to_string())],
+            (None, Some(_)) => vec![Failure::Error("no response from the baseline".to_string())],
+        };
+
+        if !failures.is_empty() {
+            let mut result = c.clone();
+            result.status = TestStatus::Failed;
+            result.failures = failures;
+            diverged.push(result);
+        }
+    }
+
+    diverged
+}
+
+fn describe(failure: &Failure) -> Vec<String> {
+    match failure {
+        Failure::CompareStatus { compare, actual } => vec![format!(
+            "status: baseline returned {}, candidate returned {}",
+            compare, actual
+        )],
+        Failure::CompareHeader {
+            header,
+            compare,
+            actual,
+        } => {
+            let describe_value = |v: &Option<String>| match v {
+                Some(v) => format!("`{}`", v),
+                None => "no value".to_string(),
+            };
+            vec![format!(
+                "header `{}`: baseline returned {}, candidate returned {}",
+                header,
+                describe_value(compare),
+                describe_value(actual)
+            )]
+        }
+        Failure::CompareBody(differences) => {
+            summary::side_by_side(differences, "baseline", "candidate")
+        }
+        other => summary::describe(other),
+    }
+}
+
+// the method and path of a request, which is the same in both environments
+fn endpoint(result: &StageResult) -> String {
+    let url = &result.details.request.url;
+    let path = Url::parse(url)
+        .map(|u| u.path().to_string())
+        .unwrap_or(url.clone());
+
+    format!("{} {}", result.details.request.method, path)
+}
+
+fn endpoint_lines(index: usize, test: &summary::FailedTest) -> Vec<String> {
+    let iteration = if test.iterations > 1 {
+        format!(
+            ", iteration {}/{}{}",
+            test.iteration + 1,
+            test.iterations,
+            test.label
+        )
+    } else {
+        String::new()
+    };
+    let mut lines = vec![format!(
+        "{}) \x1b[31m`{}`\x1b[0m ({}{})",
+        index + 1,
+        test.name,
+        test.filename,
+        iteration
+    )];
+
+    for stage in test.stages.iter() {
+        lines.push(format!("   {}: {}", stage.label(), endpoint(stage)));
+
+        for failure in stage.failures.iter() {
+            lines.extend(describe(failure).into_iter().map(|l| format!("     {}", l)));
+        }
+    }
+
+    if let Some(e) = &test.error {
+        lines.extend(e.lines().map(|l| format!("   {}", l)));
+    }
+
+    lines
+}
+
+fn print(tests: &[summary::FailedTest]) {
+    if tests.is_empty() {
+        return;
+    }
+
+    info!("\nDivergent endpoints:\n");
+
+    for (i, test) in tests.iter().enumerate() {
+        info!("\n{}\n", endpoint_lines(i, test).join("\n"));
+    }
+
+    info!("\n");
+}
+
+// runs every selected test against both environments, a test's requests use the variables
+// extracted from the responses of the environment they're sent to
+pub async fn compare_tests(
+    config: config::Config,
+    files: Vec<String>,
+    filter: select::Filter,
+    baseline_url: &str,
+    candidate_url: &str,
+    rules: Rules,
+) -> Report {
+    let global_variables = config.generate_global_variables();
+    let (tests, mut invalid) = plan_tests(&files, &global_variables, &filter);
+    let (mut baseline_suites, suite_invalid) = suite::discover(&tests, &global_variables);
+    let (mut candidate_suites, _) = suite::discover(&tests, &global_variables);
+    invalid += suite_invalid;
+
+    let listener = interrupt::listen();
+    let mut baseline = state(&config, baseline_url, listener.flag());
+    let mut candidate = state(&config, candidate_url, listener.flag());
+    let mut report = Report {
+        invalid,
+        ..Default::default()
+    };
+    let total_count = tests.len();
+
+    let mut diverged_tests = suite::set_up(&mut baseline, &mut baseline_suites).await;
+    diverged_tests.extend(suite::set_up(&mut candidate, &mut candidate_suites).await);
+    report.errored += diverged_tests.len() as u16;
+
+    for (i, td) in tests.into_iter().enumerate() {
+        if listener.flag().is_set() {
+            break;
+        }
+
+        let name = td.name.clone().unwrap_or(format!("Test {}", i + 1));
+        let failed_suite = suite::failed_suite(&baseline_suites, &td)
+            .or(suite::failed_suite(&candidate_suites, &td));
+
+        if let Some(suite) = failed_suite {
+            info!(
+                "Skipping Test ({}\\{}) `{}`: suite setup failed (`{}`)\n",
+                i + 1,
+                total_count,
+                name,
+                suite.filename
+            );
+            continue;
+        }
+
+        if let Some(reason) = &td.skip {
+            info!(
+                "Skipping Test ({}\\{}) `{}`: {}\n",
+                i + 1,
+                total_count,
+                name,
+                reason
+            );
+            continue;
+        }
+
+        for iteration in 0..td.iterate {
+            if listener.flag().is_set() {
+                break;
+            }
+
+            report.compared += 1;
+            info!(
+                "Comparing Test ({}\\{}) `{}` Iteration ({}\\{}){}...",
+                i + 1,
+                total_count,
+                name,
+                iteration + 1,
+                td.iterate,
+                td.iteration_label(iteration),
+            );
+            io::stdout().flush().unwrap();
+
+            let baseline_result = run(&mut baseline, &td, iteration, None).await;
+            let candidate_result = run(&mut candidate, &td, iteration, None).await;
+
+            let (stages, error) = match (baseline_result, candidate_result) {
+                (Ok((_, b)), Ok((_, c))) => (divergences(&td, &b, &c, &rules), None),
+                (Err(e), _) => (Vec::new(), Some(format!("baseline: {}", e))),
+                (_, Err(e)) => (Vec::new(), Some(format!("candidate: {}", e))),
+            };
+
+            if let Some(e) = &error {
+                info!("\x1b[31mERRORED\x1b[0m\n");
+                error!("{}", e);
+                report.errored += 1;
+            } else if stages.is_empty() {
+                info!("\x1b[32mMATCHED\x1b[0m\n");
+                report.matched += 1;
+                continue;
+            } else {
+                info!("\x1b[31mDIVERGED\x1b[0m\n");
+                report.diverged += 1;
+            }
+
+            diverged_tests.push(summary::FailedTest {
+                name: name.clone(),
+                filename: td.filename.clone(),
+                iteration,
+                iterations: td.iterate,
+                label: td.iteration_label(iteration),
+                stages,
+                error,
+            });
+        }
+    }
+
+    let mut teardown_failures = suite::tear_down(&mut baseline, &baseline_suites).await;
+    teardown_failures.extend(suite::tear_down(&mut candidate, &candidate_suites).await);
+    report.errored += teardown_failures.len() as u16;
+    diverged_tests.extend(teardown_failures);
+
+    report.interrupted = listener.flag().is_set();
+    print(&diverged_tests);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{RequestDetails, ResultData, ResultDetails};
+    use crate::test::{http, validation};
+    use serde_json::json;
+
+    fn result(stage: u32, status: u16, body: serde_json::Value) -> StageResult {
+        StageResult {
+            stage,
+            stage_type: StageType::Normal,
+            runtime: 0,
+            status: TestStatus::Passed,
+            details: ResultDetails {
+                request: RequestDetails {
+                    headers: Vec::new(),
+                    url: "https://jikken.io/pets/1?full=true".to_string(),
+                    method: http::Verb::Get.as_method(),
+                    body: serde_json::Value::Null,
+                },
+                expected: ResultData::default(),
+                actual: Some(ResultData {
+                    headers: vec![http::Header::new(
+                        "Date".to_string(),
+                        format!("day {}", status),
+                    )],
+                    status,
+                    body,
+                }),
+                compare_request: None,
+                compare_actual: None,
+            },
+            failures: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn environments_are_diffed_stage_by_stage_using_ignore_rules() {
+        let file: test::File = serde_yaml::from_str(
+            "stages:\n  - request:\n      url: https://jikken.io/pets\n    response:\n      ignore:\n        - updated\n  - request:\n      url: https://jikken.io/pets/1\n",
+        )
+        .unwrap();
+        let td = validation::validate_file(file, &[]).unwrap();
+        let baseline = vec![
+            result(0, 200, json!({"id": 1, "updated": "monday"})),
+            result(1, 200, json!({"name": "Rex"})),
+        ];
+        let candidate = vec![
+            result(0, 200, json!({"id": 1, "updated": "tuesday"})),
+            result(1, 500, json!({"name": "Rex", "error": true})),
+        ];
+
+        let diverged = divergences(&td, &baseline, &candidate, &Rules::default());
+
+        assert_eq!(1, diverged.len());
+        assert_eq!("GET /pets/1", endpoint(&diverged[0]));
+        let lines: Vec<String> = diverged[0].failures.iter().flat_map(describe).collect();
+        assert_eq!(
+            vec![
+                "status: baseline returned 200, candidate returned 500",
+                "            baseline   candidate",
+                "\x1b[36mbody.error\x1b[0m  (missing)  true",
+            ],
+            lines
+        );
+
+        let config = config::Config {
+            environments: std::collections::BTreeMap::from([(
+                "prod".to_string(),
+                "https://api.jikken.io".to_string(),
+            )]),
+            ..Default::default()
+        };
+        assert_eq!("https://api.jikken.io", base_url(&config, "prod").unwrap());
+        assert_eq!(
+            "http://localhost:5001",
+            base_url(&config, "http://localhost:5001").unwrap()
+        );
+        assert!(base_url(&config, "staging").is_err());
+    }
+
+    #[test]
+    fn stages_run_in_only_one_environment_diverge() {
+        let file: test::File = serde_yaml::from_str(
+            "stages:\n  - request:\n      url: https://jikken.io/pets\n  - request:\n      url: https://jikken.io/pets/1\n  - request:\n      url: https://jikken.io/pets/2\n",
+        )
+        .unwrap();
+        let td = validation::validate_file(file, &[]).unwrap();
+        // the second stage repeated once more against the baseline, and the candidate's
+        // third stage returned something else
+        let baseline = vec![
+            result(0, 200, json!({})),
+            result(1, 200, json!({"page": 1})),
+            result(1, 200, json!({"page": 2})),
+            result(2, 200, json!({"name": "Rex"})),
+        ];
+        let candidate = vec![
+            result(0, 200, json!({})),
+            result(1, 200, json!({"page": 1})),
+            result(2, 200, json!({"name": "Max"})),
+        ];
+
+        let diverged = divergences(&td, &baseline, &candidate, &Rules::default());
+
+        assert_eq!(2, diverged.len());
+        assert_eq!(
+            vec!["only requested from the baseline"],
+            summary::describe(&diverged[0].failures[0])
+        );
+        assert_eq!(2, diverged[1].stage);
+        assert!(matches!(
+            diverged[1].failures[..],
+            [Failure::CompareBody(_)]
+        ));
+
+        // a test stopped early against the candidate
+        let diverged = divergences(&td, &baseline[..1], &candidate[..2], &Rules::default());
+
+        assert_eq!(1, diverged.len());
+        assert_eq!(1, diverged[0].stage);
+        assert_eq!(
+            vec!["only requested from the candidate"],
+            summary::describe(&diverged[0].failures[0])
+        );
+    }
+}
@@ -0,0 +1,522 @@
+use super::{
+    pace, rebase_url, resolve_state, state_value, test_origin, Failure, RequestDetails, ResultData,
+    ResultDetails, StageResult, StageType, State, TestStatus,
+};
+use crate::errors::GenericError;
+use crate::json::diff;
+use crate::json::extractor::extract_json;
+use crate::json::filter::filter_json;
+use crate::test;
+use crate::test::definition::{StageDescriptor, StreamDescriptor, StreamMessage};
+use crate::test::http;
+use futures_util::{SinkExt, StreamExt};
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use log::{debug, error, trace};
+use serde_json::Value;
+use std::error::Error;
+use std::time::Instant;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+// ws(s) urls open a WebSocket, anything else is read as server-sent events
+enum Connection {
+    WebSocket(Box<WebSocketStream<MaybeTlsStream<TcpStream>>>),
+    Events {
+        body: Body,
+        buffer: Vec<u8>,
+        data: Vec<String>,
+    },
+}
+
+fn stream_error(reason: String) -> Box<dyn Error + Send + Sync> {
+    Box::new(GenericError { reason })
+}
+
+fn headers_of<T>(response: &hyper::Response<T>) -> Vec<http::Header> {
+    response
+        .headers()
+        .iter()
+        .map(|h| http::Header::new(h.0.to_string(), h.1.to_str().unwrap_or("").to_string()))
+        .collect()
+}
+
+// messages are matched as JSON, those which aren't are kept as strings
+fn to_value(text: &str) -> Value {
+    serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+}
+
+impl Connection {
+    // the handshake's status and headers are returned along with the connection, which is
+    // missing when the server refused it
+    async fn open(
+        url: &str,
+        headers: &[http::Header],
+        client: &Client<HttpsConnector<HttpConnector>>,
+    ) -> Result<(Option<Connection>, ResultData), Box<dyn Error + Send + Sync>> {
+        if url.starts_with("ws://") || url.starts_with("wss://") {
+            let mut request = url.into_client_request()?;
+            for header in headers.iter() {
+                request.headers_mut().insert(
+                    HeaderName::from_bytes(header.header.as_bytes())?,
+                    HeaderValue::from_str(&header.value)?,
+                );
+            }
+
+            return match tokio_tungstenite::connect_async(request).await {
+                Ok((socket, response)) => Ok((
+                    Some(Connection::WebSocket(Box::new(socket))),
+                    ResultData {
+                        status: response.status().as_u16(),
+                        headers: headers_of(&response),
+                        body: Value::Null,
+                    },
+                )),
+                Err(tokio_tungstenite::tungstenite::Error::Http(response)) => Ok((
+                    None,
+                    ResultData {
+                        status: response.status().as_u16(),
+                        headers: headers_of(&response),
+                        body: Value::Null,
+                    },
+                )),
+                Err(e) => Err(stream_error(format!("unable to open websocket: {}", e))),
+            };
+        }
+
+        let mut builder = Request::builder()
+            .uri(url)
+            .header("Accept", "text/event-stream");
+        for header in headers.iter() {
+            builder = builder.header(&header.header, &header.value);
+        }
+
+        let response = client.request(builder.body(Body::empty())?).await?;
+        let result = ResultData {
+            status: response.status().as_u16(),
+            headers: headers_of(&response),
+            body: Value::Null,
+        };
+
+        if !response.status().is_success() {
+            return Ok((None, result));
+        }
+
+        Ok((
+            Some(Connection::Events {
+                body: response.into_body(),
+                buffer: Vec::new(),
+                data: Vec::new(),
+            }),
+            result,
+        ))
+    }
+
+    async fn send(&mut self, message: &Value) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            Connection::WebSocket(socket) => {
+                let text = match message {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                Ok(socket.send(Message::Text(text)).await?)
+            }
+            Connection::Events { .. } => Err(stream_error(
+                "messages can't be sent on a server-sent event stream".to_string(),
+            )),
+        }
+    }
+
+    // the next message, or none once the stream is closed
+    async fn receive(&mut self) -> Result<Option<Value>, Box<dyn Error + Send + Sync>> {
+        match self {
+            Connection::WebSocket(socket) => loop {
+                match socket.next().await {
+                    Some(Ok(Message::Text(text))) => return Ok(Some(to_value(&text))),
+                    Some(Ok(Message::Binary(bytes))) => {
+                        return Ok(Some(to_value(&String::from_utf8_lossy(&bytes))))
+                    }
+                    Some(Ok(Message::Close(_))) | None => return Ok(None),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(Box::new(e)),
+                }
+            },
+            // an event's `data` lines are joined, and it's dispatched by a blank line
+            Connection::Events { body, buffer, data } => loop {
+                if let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=end).collect();
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.trim_end_matches(['\n', '\r']);
+
+                    if line.is_empty() && !data.is_empty() {
+                        let event = data.join("\n");
+                        data.clear();
+                        return Ok(Some(to_value(&event)));
+                    }
+
+                    if let Some(value) = line.strip_prefix("data:") {
+                        data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+                    }
+
+                    continue;
+                }
+
+                match body.data().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                    Some(Err(e)) => return Err(Box::new(e)),
+                    None => return Ok(None),
+                }
+            },
+        }
+    }
+
+    async fn close(self) {
+        if let Connection::WebSocket(mut socket) = self {
+            if let Err(e) = socket.close().await {
+                debug!("unable to close websocket: {}", e);
+            }
+        }
+    }
+}
+
+// replaces variables extracted by earlier stages
+fn resolve_message(state: &State, message: Value) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    Ok(serde_json::from_str(&resolve_state(
+        state,
+        &serde_json::to_string(&message)?,
+    ))?)
+}
+
+fn differences(
+    actual: &Value,
+    expected: &Value,
+    ignore: &[String],
+) -> Result<Vec<diff::Difference>, Box<dyn Error + Send + Sync>> {
+    let mut actual = actual.clone();
+    let mut expected = expected.clone();
+
+    for path in ignore.iter() {
+        actual = filter_json(path, 0, actual)?;
+        expected = filter_json(path, 0, expected)?;
+    }
+
+    Ok(diff::differences(
+        &expected,
+        &actual,
+        &diff::Rules::default(),
+    ))
+}
+
+// opens the stream, then sends and waits for its messages in order. a message which isn't
+// received fails the stage, and the rest of its messages are skipped
+pub(super) async fn validate_stream(
+    state: &mut State,
+    td: &test::Definition,
+    stage: &StageDescriptor,
+    stream: &StreamDescriptor,
+    stage_index: usize,
+    iteration: u32,
+) -> Result<StageResult, Box<dyn Error + Send + Sync>> {
+    let variables = [&stage.variables[..], &td.variables[..]].concat();
+    let url = resolve_state(
+        state,
+        &td.get_url(
+            iteration,
+            &stage.request.url,
+            &stage.request.params,
+            &variables,
+        ),
+    );
+    let url = rebase_url(state, test_origin(state, td, iteration).as_ref(), &url);
+    let headers: Vec<http::Header> = td
+        .get_headers(&stage.request.headers, iteration)
+        .into_iter()
+        .map(|h| http::Header::new(h.0, resolve_state(state, &h.1)))
+        .collect();
+
+    debug!("opening stream: {}", url);
+    pace(state).await;
+    let start_time = Instant::now();
+    let open = Connection::open(&url, &headers, &state.clients.http);
+    let (connection, mut actual) = match tokio::time::timeout(stream.timeout, open).await {
+        Ok(opened) => opened?,
+        Err(_) => {
+            return Err(stream_error(format!(
+                "unable to open stream within {:?}",
+                stream.timeout
+            )))
+        }
+    };
+
+    let mut sent = Vec::new();
+    let mut expected = Vec::new();
+    let mut received = Vec::new();
+    let mut failures = Vec::new();
+
+    match connection {
+        None => failures.push(Failure::Status {
+            expected: if url.starts_with("ws") { 101 } else { 200 },
+            actual: actual.status,
+        }),
+        Some(mut connection) => {
+            for (index, message) in stream.messages.iter().enumerate() {
+                match message {
+                    StreamMessage::Send(body) => {
+                        let message = resolve_message(
+                            state,
+                            td.get_stream_body(body, &variables, iteration),
+                        )?;
+                        trace!("sending stream message: {}", message);
+                        connection.send(&message).await?;
+                        sent.push(message);
+                    }
+                    StreamMessage::Receive {
+                        body,
+                        ignore,
+                        extract,
+                        timeout,
+                    } => {
+                        let expectation = resolve_message(
+                            state,
+                            td.get_stream_body(body, &variables, iteration),
+                        )?;
+                        expected.push(expectation.clone());
+                        let deadline = tokio::time::Instant::now() + *timeout;
+                        // the differences from the last message, to show what arrived instead
+                        let mut closest = Vec::new();
+
+                        let unreceived = loop {
+                            let message =
+                                match tokio::time::timeout_at(deadline, connection.receive()).await
+                                {
+                                    Err(_) => {
+                                        break Some(format!("nothing matched within {:?}", timeout))
+                                    }
+                                    Ok(Ok(None)) => {
+                                        break Some("the stream closed before a match".to_string())
+                                    }
+                                    Ok(Err(e)) => break Some(e.to_string()),
+                                    Ok(Ok(Some(m))) => m,
+                                };
+
+                            trace!("received stream message: {}", message);
+                            received.push(message.clone());
+                            closest = differences(&message, &expectation, ignore)?;
+
+                            if closest.is_empty() {
+                                for v in extract.iter() {
+                                    match extract_json(&v.field, 0, message.clone()) {
+                                        Ok(value) => {
+                                            state
+                                                .variables
+                                                .insert(v.name.clone(), state_value(value));
+                                        }
+                                        Err(error) => {
+                                            error!("no json result found: {}", error);
+                                        }
+                                    }
+                                }
+
+                                break None;
+                            }
+                        };
+
+                        if let Some(reason) = unreceived {
+                            failures.push(Failure::Unreceived {
+                                message: index + 1,
+                                reason,
+                                differences: closest,
+                            });
+                            break;
+                        }
+                    }
+                }
+            }
+
+            connection.close().await;
+        }
+    }
+
+    actual.body = Value::Array(received);
+
+    Ok(StageResult {
+        stage: stage_index as u32,
+        stage_type: StageType::Normal,
+        runtime: start_time.elapsed().as_millis() as u32,
+        status: if failures.is_empty() {
+            TestStatus::Passed
+        } else {
+            TestStatus::Failed
+        },
+        details: ResultDetails {
+            request: RequestDetails {
+                headers,
+                url,
+                method: stage.request.method.as_method(),
+                body: Value::Array(sent),
+            },
+            expected: ResultData {
+                headers: Vec::new(),
+                status: 0,
+                body: Value::Array(expected),
+            },
+            actual: Some(actual),
+            compare_request: None,
+            compare_actual: None,
+        },
+        failures,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::tests::state;
+    use crate::test::validation;
+    use tempfile::tempdir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn definition(dir: &std::path::Path, contents: &str) -> test::Definition {
+        let path = dir.join("stream.jkt");
+        std::fs::write(&path, contents).unwrap();
+        validation::validate_file(test::file::load(path.to_str().unwrap()).unwrap(), &[]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn messages_are_exchanged_over_websockets_and_events() {
+        // greets each client, then echoes what it's sent
+        let sockets = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_address = sockets.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = sockets.accept().await {
+                tokio::spawn(async move {
+                    let mut socket = tokio_tungstenite::accept_async(tcp).await.unwrap();
+                    let hello = "{\"type\":\"hello\",\"session\":42}".to_string();
+                    socket.send(Message::Text(hello)).await.unwrap();
+                    while let Some(Ok(message)) = socket.next().await {
+                        if message.is_text() && socket.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let events = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sse_address = events.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut tcp, _) = events.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = tcp.read(&mut request).await.unwrap();
+            tcp.write_all(
+                b"HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n\
+                  : comment\r\nevent: count\r\ndata: {\"n\":\r\ndata: 1}\r\n\r\ndata: {\"n\": 2}\n\n",
+            )
+            .await
+            .unwrap();
+        });
+
+        let tmp_dir = tempdir().unwrap();
+        let td = definition(
+            tmp_dir.path(),
+            &format!(
+                "stages:\n\
+                 - request:\n    url: ws://{0}/\n  stream:\n    messages:\n\
+                 \x20     - receive: {{\"type\": \"hello\"}}\n        ignore: [session]\n        extract:\n          - name: session\n            field: session\n\
+                 \x20     - send: {{\"session\": \"${{session}}\"}}\n\
+                 \x20     - receive: {{\"session\": \"42\"}}\n\
+                 - request:\n    url: ws://{0}/\n  stream:\n    timeout: 200ms\n    messages:\n\
+                 \x20     - receive: {{\"type\": \"goodbye\"}}\n        ignore: [session]\n\
+                 - request:\n    url: http://{1}/events\n  stream:\n    messages:\n\
+                 \x20     - receive: {{\"n\": 2}}\n",
+                ws_address, sse_address
+            ),
+        );
+
+        let mut state = state();
+
+        let mut results = Vec::new();
+        for (index, stage) in td.stages.iter().enumerate() {
+            let stream = stage.stream.as_ref().unwrap();
+            results.push(
+                validate_stream(&mut state, &td, stage, stream, index, 0)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        assert_eq!(Some(&"42".to_string()), state.variables.get("session"));
+        assert!(results[0].status == TestStatus::Passed);
+        assert_eq!(
+            serde_json::json!([{"session": "42"}]),
+            results[0].details.request.body
+        );
+
+        assert!(results[1].status == TestStatus::Failed);
+        match &results[1].failures[..] {
+            [Failure::Unreceived {
+                message,
+                reason,
+                differences,
+            }] => {
+                assert_eq!(1, *message);
+                assert_eq!("nothing matched within 200ms", reason);
+                assert_eq!("type", differences[0].path);
+            }
+            _ => panic!("expected the message not to be received"),
+        }
+
+        assert!(results[2].status == TestStatus::Passed);
+        assert_eq!(
+            serde_json::json!([{"n": 1}, {"n": 2}]),
+            results[2].details.actual.as_ref().unwrap().body
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_follow_the_base_url_and_time_out_while_opening() {
+        // completes websocket handshakes on the first connection, then stops answering
+        let sockets = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = sockets.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (tcp, _) = sockets.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(tcp).await.unwrap();
+            socket.send(Message::Text("{}".to_string())).await.unwrap();
+
+            let mut silent = Vec::new();
+            while let Ok((tcp, _)) = sockets.accept().await {
+                silent.push(tcp);
+            }
+        });
+
+        // nothing listens on the test's own origin, so the stream only opens when rebased
+        let tmp_dir = tempdir().unwrap();
+        let td = definition(
+            tmp_dir.path(),
+            "request:\n  url: ws://127.0.0.1:9/\nstream:\n  timeout: 200ms\n  messages:\n    - receive: {}\n",
+        );
+        let stage = &td.stages[0];
+        let stream = stage.stream.as_ref().unwrap();
+        let mut state = State {
+            base_url: Some(format!("http://{}", address)),
+            ..state()
+        };
+
+        let result = validate_stream(&mut state, &td, stage, stream, 0, 0)
+            .await
+            .unwrap();
+        assert!(result.status == TestStatus::Passed);
+        assert_eq!(format!("ws://{}/", address), result.details.request.url);
+
+        match validate_stream(&mut state, &td, stage, stream, 0, 0).await {
+            Err(e) => assert_eq!("unable to open stream within 200ms", e.to_string()),
+            Ok(_) => panic!("expected opening the stream to time out"),
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use super::{run, summary, State};
+use crate::test;
+use crate::test::{file, validation};
+use log::{error, info};
+use serde::Deserialize;
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const SUITE_FILE: &str = "_suite.jkt";
+
+// a suite file holds setup which runs once before any test in its directory tree, and
+// teardown which runs once after them. extracted variables are visible to every test
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    setup: Option<Vec<file::UnvalidatedStage>>,
+    teardown: Option<Vec<file::UnvalidatedStage>>,
+    variables: Option<Vec<file::UnvalidatedVariable>>,
+}
+
+pub struct Suite {
+    pub filename: String,
+    directory: PathBuf,
+    setup: Option<test::Definition>,
+    // each step runs on its own, so one failing doesn't stop the rest
+    teardown: Vec<test::Definition>,
+    failed: bool,
+    // set once its setup has been attempted, suites that were never set up aren't torn down
+    started: bool,
+}
+
+// checks a suite file's contents can be read, for `jk validate`
+pub fn parse(text: &str) -> Result<(), serde_yaml::Error> {
+    serde_yaml::from_str::<File>(text).map(|_| ())
+}
+
+pub fn is_suite_file(path: &str) -> bool {
+    Path::new(path).file_name().and_then(|n| n.to_str()) == Some(SUITE_FILE)
+}
+
+fn definition(
+    filename: &str,
+    name: &str,
+    stages: Vec<file::UnvalidatedStage>,
+    variables: Option<Vec<file::UnvalidatedVariable>>,
+    global_variables: &[test::Variable],
+) -> Result<test::Definition, validation::Error> {
+    validation::validate_file(
+        test::File {
+            name: Some(name.to_string()),
+            id: None,
+            env: None,
+            tags: None,
+            requires: None,
+            iterate: None,
+            dataset: None,
+            skip: None,
+            only: None,
+            xfail: None,
+            setup: None,
+            request: None,
+            compare: None,
+            stream: None,
+            response: None,
+            stages: Some(stages),
+            cleanup: None,
+            variables,
+            filename: filename.to_string(),
+        },
+        global_variables,
+    )
+}
+
+fn load(
+    filename: &str,
+    directory: PathBuf,
+    global_variables: &[test::Variable],
+) -> Result<Suite, Box<dyn Error + Send + Sync>> {
+    let suite: File = serde_yaml::from_str(&std::fs::read_to_string(filename)?)?;
+
+    let setup = match suite.setup {
+        Some(stages) if !stages.is_empty() => Some(definition(
+            filename,
+            "suite setup",
+            stages,
+            suite.variables.clone(),
+            global_variables,
+        )?),
+        _ => None,
+    };
+
+    let teardown = suite
+        .teardown
+        .unwrap_or_default()
+        .into_iter()
+        .map(|stage| {
+            definition(
+                filename,
+                "suite teardown",
+                vec![stage],
+                suite.variables.clone(),
+                global_variables,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Suite {
+        filename: filename.to_string(),
+        directory,
+        setup,
+        teardown,
+        failed: false,
+        started: false,
+    })
+}
+
+fn directory_of(filename: &str) -> Option<PathBuf> {
+    Path::new(filename).parent()?.canonicalize().ok()
+}
+
+// finds the suite files in the directories of the tests and their parents, outermost first.
+// returns the suites along with the number which couldn't be loaded
+pub fn discover(
+    tests: &[test::Definition],
+    global_variables: &[test::Variable],
+) -> (Vec<Suite>, u16) {
+    let mut suites: Vec<Suite> = Vec::new();
+    let mut invalid_count = 0;
+
+    for td in tests.iter() {
+        let parent = Path::new(&td.filename).parent().unwrap_or(Path::new(""));
+
+        for directory in parent.ancestors() {
+            let path = directory.join(SUITE_FILE);
+            let canonical = match (path.is_file(), directory_of(&path.to_string_lossy())) {
+                (true, Some(d)) => d,
+                _ => continue,
+            };
+
+            if suites.iter().any(|s| s.directory == canonical) {
+                continue;
+            }
+
+            let filename = path.to_string_lossy().to_string();
+            match load(&filename, canonical, global_variables) {
+                Ok(suite) => suites.push(suite),
+                Err(e) => {
+                    error!("unable to load suite file ({}): {}", filename, e);
+                    invalid_count += 1;
+                }
+            }
+        }
+    }
+
+    suites.sort_by_key(|s| s.directory.components().count());
+    (suites, invalid_count)
+}
+
+impl Suite {
+    fn contains(&self, filename: &str) -> bool {
+        directory_of(filename)
+            .map(|d| d.starts_with(&self.directory))
+            .unwrap_or(false)
+    }
+}
+
+// the failed suite a test belongs to, if any. its tests are skipped
+pub fn failed_suite<'a>(suites: &'a [Suite], td: &test::Definition) -> Option<&'a Suite> {
+    suites.iter().find(|s| s.failed && s.contains(&td.filename))
+}
+
+async fn run_step(
+    state: &mut State,
+    td: &test::Definition,
+    label: &str,
+    filename: &str,
+) -> Option<summary::FailedTest> {
+    info!("Running Suite {} (`{}`)...", label, filename);
+    io::stdout().flush().unwrap();
+
+    let (stages, error) = match run(state, td, 0, None).await {
+        Ok((true, _)) => {
+            info!("\x1b[32mPASSED\x1b[0m\n");
+            return None;
+        }
+        Ok((false, results)) => (results, None),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+
+    info!("\x1b[31mFAILED\x1b[0m\n");
+    if let Some(e) = &error {
+        error!("{}", e);
+    }
+
+    Some(summary::FailedTest {
+        name: format!("suite {}", label.to_lowercase()),
+        filename: filename.to_string(),
+        iteration: 0,
+        iterations: 1,
+        label: String::new(),
+        stages: stages
+            .into_iter()
+            .filter(|r| r.status == super::TestStatus::Failed)
+            .collect(),
+        error,
+    })
+}
+
+// runs the setup of each suite, skipping suites inside one that already failed. once
+// interrupted no more setup runs, the tests are skipped and teardown still runs
+pub(super) async fn set_up(state: &mut State, suites: &mut [Suite]) -> Vec<summary::FailedTest> {
+    let mut failures = Vec::new();
+
+    for i in 0..suites.len() {
+        if state.interrupted.is_set() {
+            break;
+        }
+
+        let (outer, rest) = suites.split_at_mut(i);
+        let suite = &mut rest[0];
+
+        if outer
+            .iter()
+            .any(|o| o.failed && suite.directory.starts_with(&o.directory))
+        {
+            suite.failed = true;
+            continue;
+        }
+
+        suite.started = true;
+        if let Some(td) = &suite.setup {
+            if let Some(failure) = run_step(state, td, "Setup", &suite.filename).await {
+                suite.failed = true;
+                failures.push(failure);
+            }
+        }
+    }
+
+    failures
+}
+
+// runs every teardown step of the suites that were set up, innermost suites first, whether
+// or not anything failed
+pub(super) async fn tear_down(state: &mut State, suites: &[Suite]) -> Vec<summary::FailedTest> {
+    let mut failures = Vec::new();
+    state.interruptible = false;
+
+    for suite in suites.iter().rev().filter(|s| s.started) {
+        for td in suite.teardown.iter() {
+            if let Some(failure) = run_step(state, td, "Teardown", &suite.filename).await {
+                failures.push(failure);
+            }
+        }
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::executor::tests::{execute, server, write};
+    use crate::executor::RunOptions;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn suites_are_found_in_parent_directories_outermost_first() {
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path();
+        let nested = root.join("users");
+        fs::create_dir(&nested).unwrap();
+
+        let suite = "setup:\n  - request:\n      url: http://localhost/login\n";
+        fs::write(root.join(SUITE_FILE), suite).unwrap();
+        fs::write(nested.join(SUITE_FILE), "teardown: []\n").unwrap();
+
+        let test_file = nested.join("get.jkt");
+        fs::write(&test_file, "request:\n  url: http://localhost/users\n").unwrap();
+        let td =
+            validation::validate_file(test::file::load(test_file.to_str().unwrap()).unwrap(), &[])
+                .unwrap();
+
+        let (suites, invalid) = discover(&[td], &[]);
+
+        assert_eq!(0, invalid);
+        assert_eq!(2, suites.len());
+        assert!(suites[0].setup.is_some());
+        assert_eq!(nested.canonicalize().unwrap(), suites[1].directory);
+        assert!(suites[0].contains(test_file.to_str().unwrap()));
+        assert!(!is_suite_file(test_file.to_str().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn setup_runs_once_and_teardown_runs_after_a_failure() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path();
+        let nested = root.join("orders");
+        fs::create_dir(&nested).unwrap();
+
+        write(
+            root,
+            SUITE_FILE,
+            &format!(
+                "setup:\n  - request:\n      url: http://{0}/login\n    response:\n      extract:\n        - name: session\n          field: count\n\
+                 teardown:\n  - request:\n      url: http://{0}/logout/${{session}}\n",
+                address
+            ),
+        );
+        let files = vec![
+            write(
+                root,
+                "a.jkt",
+                &format!("request:\n  url: http://{}/users/${{session}}\n", address),
+            ),
+            write(
+                &nested,
+                "b.jkt",
+                &format!("request:\n  url: http://{}/orders/${{session}}\n", address),
+            ),
+            write(
+                root,
+                "c.jkt",
+                &format!(
+                    "request:\n  url: http://{}/status/500\nresponse:\n  status: 200\n",
+                    address
+                ),
+            ),
+        ];
+
+        let report = execute(config::Config::default(), files, RunOptions::default()).await;
+
+        assert_eq!(3, report.run);
+        assert_eq!(2, report.passed);
+        assert_eq!(1, report.failed);
+        assert_eq!(
+            vec![
+                "/login",
+                "/users/1",
+                "/orders/1",
+                "/status/500",
+                "/logout/1"
+            ],
+            *requests.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn suites_inside_a_failed_setup_are_not_torn_down() {
+        let (address, requests) = server();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path();
+        let nested = root.join("orders");
+        fs::create_dir(&nested).unwrap();
+
+        write(
+            root,
+            SUITE_FILE,
+            &format!(
+                "setup:\n  - request:\n      url: http://{0}/status/500\n    response:\n      status: 200\n\
+                 teardown:\n  - request:\n      url: http://{0}/logout\n",
+                address
+            ),
+        );
+        write(
+            &nested,
+            SUITE_FILE,
+            &format!(
+                "setup:\n  - request:\n      url: http://{0}/cart\n\
+                 teardown:\n  - request:\n      url: http://{0}/empty\n",
+                address
+            ),
+        );
+        let files = vec![write(
+            &nested,
+            "a.jkt",
+            &format!("request:\n  url: http://{}/orders\n", address),
+        )];
+
+        let report = execute(config::Config::default(), files, RunOptions::default()).await;
+
+        assert_eq!(0, report.run);
+        assert_eq!(1, report.skipped);
+        assert_eq!(vec!["/status/500", "/logout"], *requests.lock().unwrap());
+    }
+}
@@ -0,0 +1,318 @@
+use super::{Failure, StageResult};
+use crate::json::diff::Difference;
+use crate::test::grpc;
+use log::info;
+use serde_json::Value;
+
+const MAX_VALUE_LENGTH: usize = 60;
+
+// a failed iteration of a test, with the stages that failed
+pub struct FailedTest {
+    pub name: String,
+    pub filename: String,
+    pub iteration: u32,
+    pub iterations: u32,
+    // the dataset row, formatted as ` [label]`, or empty
+    pub label: String,
+    pub stages: Vec<StageResult>,
+    pub error: Option<String>,
+}
+
+fn value(value: &Value) -> String {
+    let text = value.to_string();
+
+    if text.chars().count() > MAX_VALUE_LENGTH {
+        format!(
+            "{}...",
+            text.chars().take(MAX_VALUE_LENGTH).collect::<String>()
+        )
+    } else {
+        text
+    }
+}
+
+fn plain_body_path(difference: &Difference) -> String {
+    if difference.path.is_empty() || difference.path.starts_with('[') {
+        format!("body{}", difference.path)
+    } else {
+        format!("body.{}", difference.path)
+    }
+}
+
+fn body_path(difference: &Difference) -> String {
+    format!("\x1b[36m{}\x1b[0m", plain_body_path(difference))
+}
+
+fn body_lines(differences: &[Difference]) -> Vec<String> {
+    differences
+        .iter()
+        .map(|d| match (&d.expected, &d.actual) {
+            (Some(e), Some(a)) => {
+                format!("{}: expected {}, got {}", body_path(d), value(e), value(a))
+            }
+            (Some(e), None) => format!("{}: missing, expected {}", body_path(d), value(e)),
+            (None, Some(a)) => format!("{}: unexpected {}", body_path(d), value(a)),
+            (None, None) => body_path(d),
+        })
+        .collect()
+}
+
+// the compared value and the actual value of each differing path, in columns
+pub(super) fn side_by_side(
+    differences: &[Difference],
+    compared: &str,
+    actual: &str,
+) -> Vec<String> {
+    let missing = "(missing)".to_string();
+    let rows: Vec<(String, String, String)> = differences
+        .iter()
+        .map(|d| {
+            (
+                plain_body_path(d),
+                d.expected.as_ref().map(value).unwrap_or(missing.clone()),
+                d.actual.as_ref().map(value).unwrap_or(missing.clone()),
+            )
+        })
+        .collect();
+
+    let path_width = rows.iter().map(|r| r.0.chars().count()).max().unwrap_or(0);
+    let compare_width = rows
+        .iter()
+        .map(|r| r.1.chars().count())
+        .chain([compared.chars().count()])
+        .max()
+        .unwrap_or(0);
+
+    let mut lines = vec![format!(
+        "{:<path_width$}  {:<compare_width$}  {}",
+        "", compared, actual
+    )];
+    lines.extend(rows.into_iter().map(|(path, compare, actual)| {
+        format!(
+            "\x1b[36m{:<path_width$}\x1b[0m  {:<compare_width$}  {}",
+            path, compare, actual
+        )
+    }));
+
+    lines
+}
+
+pub(super) fn describe(failure: &Failure) -> Vec<String> {
+    match failure {
+        Failure::NoResponse => vec!["no response was received".to_string()],
+        Failure::Status { expected, actual } => {
+            vec![format!("status: expected {}, got {}", expected, actual)]
+        }
+        Failure::HeaderMissing { header, expected } => {
+            vec![format!(
+                "header `{}`: missing, expected `{}`",
+                header, expected
+            )]
+        }
+        Failure::Header {
+            header,
+            expected,
+            actual,
+        } => vec![format!(
+            "header `{}`: expected `{}`, got `{}`",
+            header, expected, actual
+        )],
+        Failure::Body(differences) => body_lines(differences),
+        Failure::CompareStatus { compare, actual } => {
+            vec![format!(
+                "status: compare request returned {}, got {}",
+                compare, actual
+            )]
+        }
+        Failure::CompareBody(differences) => side_by_side(differences, "compare request", "actual"),
+        Failure::CompareHeader {
+            header,
+            compare,
+            actual,
+        } => {
+            let describe_value = |v: &Option<String>| match v {
+                Some(v) => format!("`{}`", v),
+                None => "no value".to_string(),
+            };
+            vec![format!(
+                "header `{}`: compare request returned {}, got {}",
+                header,
+                describe_value(compare),
+                describe_value(actual)
+            )]
+        }
+        Failure::Graphql(message) => vec![format!("graphql error: {}", message)],
+        Failure::GrpcStatus {
+            expected,
+            actual,
+            message,
+        } => {
+            let mut line = format!(
+                "grpc status: expected {}, got {}",
+                grpc::code_name(*expected),
+                grpc::code_name(*actual)
+            );
+            if !message.is_empty() {
+                line = format!("{}: {}", line, message);
+            }
+            vec![line]
+        }
+        Failure::Unreceived {
+            message,
+            reason,
+            differences,
+        } => {
+            let mut lines = vec![format!("stream message {}: {}", message, reason)];
+            if !differences.is_empty() {
+                lines.push("the last message received differed:".to_string());
+                lines.extend(body_lines(differences));
+            }
+            lines
+        }
+        Failure::Error(e) => vec![e.clone()],
+    }
+}
+
+fn test_lines(index: usize, test: &FailedTest) -> Vec<String> {
+    let iteration = if test.iterations > 1 {
+        format!(
+            ", iteration {}/{}{}",
+            test.iteration + 1,
+            test.iterations,
+            test.label
+        )
+    } else {
+        String::new()
+    };
+    let mut lines = vec![format!(
+        "{}) \x1b[31m`{}`\x1b[0m ({}{})",
+        index + 1,
+        test.name,
+        test.filename,
+        iteration
+    )];
+
+    for stage in test.stages.iter() {
+        lines.push(format!(
+            "   {}: {} {}",
+            stage.label(),
+            stage.details.request.method,
+            stage.details.request.url
+        ));
+
+        for failure in stage.failures.iter() {
+            lines.extend(describe(failure).into_iter().map(|l| format!("     {}", l)));
+        }
+    }
+
+    if let Some(e) = &test.error {
+        lines.extend(e.lines().map(|l| format!("   {}", l)));
+    }
+
+    lines
+}
+
+// lists every failure of the run in one place, after the per-test output has scrolled away
+pub fn print(tests: &[FailedTest]) {
+    if tests.is_empty() {
+        return;
+    }
+
+    info!("\nFailures:\n");
+
+    for (i, test) in tests.iter().enumerate() {
+        info!("\n{}\n", test_lines(i, test).join("\n"));
+    }
+
+    info!("\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{RequestDetails, ResultData, ResultDetails, StageType, TestStatus};
+    use crate::test::http;
+    use serde_json::json;
+
+    #[test]
+    fn failures_list_stage_and_reasons() {
+        let stage = StageResult {
+            stage: 1,
+            stage_type: StageType::Normal,
+            runtime: 12,
+            status: TestStatus::Failed,
+            details: ResultDetails {
+                request: RequestDetails {
+                    headers: Vec::new(),
+                    url: "https://jikken.io/pets/1".to_string(),
+                    method: http::Verb::Get.as_method(),
+                    body: Value::Null,
+                },
+                expected: ResultData::default(),
+                actual: None,
+                compare_request: None,
+                compare_actual: None,
+            },
+            failures: vec![
+                Failure::Status {
+                    expected: 200,
+                    actual: 404,
+                },
+                Failure::HeaderMissing {
+                    header: "ETag".to_string(),
+                    expected: "abc".to_string(),
+                },
+                Failure::Body(vec![Difference {
+                    path: "tags[0]".to_string(),
+                    expected: Some(json!("a")),
+                    actual: None,
+                }]),
+            ],
+        };
+        let test = FailedTest {
+            name: "Get pet".to_string(),
+            filename: "tests/pet.jkt".to_string(),
+            iteration: 1,
+            iterations: 2,
+            label: " [alice]".to_string(),
+            stages: vec![stage],
+            error: None,
+        };
+
+        assert_eq!(
+            vec![
+                "1) \x1b[31m`Get pet`\x1b[0m (tests/pet.jkt, iteration 2/2 [alice])",
+                "   stage 2: GET https://jikken.io/pets/1",
+                "     status: expected 200, got 404",
+                "     header `ETag`: missing, expected `abc`",
+                "     \x1b[36mbody.tags[0]\x1b[0m: missing, expected \"a\"",
+            ],
+            test_lines(0, &test)
+        );
+    }
+
+    #[test]
+    fn compare_differences_are_shown_side_by_side() {
+        let lines = describe(&Failure::CompareBody(vec![
+            Difference {
+                path: "id".to_string(),
+                expected: Some(json!(1)),
+                actual: Some(json!(2)),
+            },
+            Difference {
+                path: "owner.name".to_string(),
+                expected: None,
+                actual: Some(json!("x")),
+            },
+        ]));
+
+        assert_eq!(
+            vec![
+                "                 compare request  actual",
+                "\x1b[36mbody.id        \x1b[0m  1                2",
+                "\x1b[36mbody.owner.name\x1b[0m  (missing)        \"x\"",
+            ],
+            lines
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use crate::test::definition::ResolvedRequest;
+use crate::test::grpc;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Format {
+    /// A curl command
+    Curl,
+    /// An HTTPie command
+    Httpie,
+    /// A raw HTTP/1.1 request
+    Http,
+}
+
+// wraps a value in single quotes so the shell passes it through untouched
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// the executor sends bodies as JSON and adds a content type unless one was given
+fn headers_with_content_type(request: &ResolvedRequest) -> Vec<(String, String)> {
+    let mut headers = request.headers.clone();
+
+    if request.body.is_some()
+        && !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+    {
+        headers.push(("Content-Type".to_string(), "application/json".to_string()));
+    }
+
+    headers
+}
+
+fn body(request: &ResolvedRequest) -> Option<String> {
+    request
+        .body
+        .as_ref()
+        .map(|b| serde_json::to_string(b).unwrap_or_default())
+}
+
+pub fn curl(request: &ResolvedRequest) -> String {
+    let mut parts = vec![format!(
+        "curl -X {} {}",
+        request.method,
+        quote(&request.url)
+    )];
+
+    for (name, value) in headers_with_content_type(request) {
+        parts.push(format!("-H {}", quote(&format!("{}: {}", name, value))));
+    }
+
+    if let Some(b) = body(request) {
+        parts.push(format!("--data-raw {}", quote(&b)));
+    }
+
+    parts.join(" \\\n  ")
+}
+
+pub fn httpie(request: &ResolvedRequest) -> String {
+    let mut parts = vec![format!("http {} {}", request.method, quote(&request.url))];
+
+    for (name, value) in headers_with_content_type(request) {
+        parts.push(quote(&format!("{}:{}", name, value)));
+    }
+
+    if let Some(b) = body(request) {
+        parts.push(format!("--raw {}", quote(&b)));
+    }
+
+    parts.join(" \\\n  ")
+}
+
+pub fn http(request: &ResolvedRequest) -> String {
+    let parsed = url::Url::parse(&request.url).ok();
+
+    // urls that still contain unresolved variables are written out as given
+    let (target, host) = match &parsed {
+        Some(u) => {
+            let mut target = u.path().to_string();
+            if let Some(q) = u.query() {
+                target = format!("{}?{}", target, q);
+            }
+
+            let host = match (u.host_str(), u.port()) {
+                (Some(h), Some(p)) => Some(format!("{}:{}", h, p)),
+                (Some(h), None) => Some(h.to_string()),
+                _ => None,
+            };
+
+            (target, host)
+        }
+        None => (request.url.clone(), None),
+    };
+
+    let mut lines = vec![format!("{} {} HTTP/1.1", request.method, target)];
+
+    if let Some(h) = host {
+        lines.push(format!("Host: {}", h));
+    }
+
+    for (name, value) in headers_with_content_type(request) {
+        lines.push(format!("{}: {}", name, value));
+    }
+
+    match body(request) {
+        Some(b) => {
+            lines.push(format!("Content-Length: {}", b.len()));
+            lines.push(String::new());
+            lines.push(b);
+        }
+        None => lines.push(String::new()),
+    }
+
+    lines.join("\r\n")
+}
+
+// curl can't make gRPC calls, so they're exported as grpcurl commands whichever format is
+// chosen. the proto's directory is the last import path, as it is when the test runs
+pub fn grpcurl(method: &grpc::Method, request: &ResolvedRequest) -> String {
+    let parsed = url::Url::parse(&request.url).ok();
+    let mut parts = vec![match parsed.as_ref().map(|u| u.scheme()) {
+        Some("http") => "grpcurl -plaintext".to_string(),
+        _ => "grpcurl".to_string(),
+    }];
+
+    if let Some(proto) = &method.proto {
+        let path = std::path::Path::new(proto);
+        let directory = path
+            .parent()
+            .map(|d| d.to_string_lossy().to_string())
+            .filter(|d| !d.is_empty())
+            .unwrap_or_else(|| ".".to_string());
+
+        for import_path in method.import_paths.iter().chain([&directory]) {
+            parts.push(format!("-import-path {}", quote(import_path)));
+        }
+
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string());
+        parts.push(format!("-proto {}", quote(&name.unwrap_or_default())));
+    }
+
+    if let Some(descriptor_set) = &method.descriptor_set {
+        parts.push(format!("-protoset {}", quote(descriptor_set)));
+    }
+
+    for (name, value) in request.headers.iter() {
+        parts.push(format!("-H {}", quote(&format!("{}: {}", name, value))));
+    }
+
+    if let Some(b) = body(request) {
+        parts.push(format!("-d {}", quote(&b)));
+    }
+
+    // urls that still contain unresolved variables are written out as given
+    let address = match parsed
+        .as_ref()
+        .and_then(|u| Some((u.host_str()?, u.port_or_known_default()?)))
+    {
+        Some((host, port)) => format!("{}:{}", host, port),
+        None => request.url.clone(),
+    };
+    parts.push(quote(&address));
+    parts.push(method.name.clone());
+
+    parts.join(" \\\n  ")
+}
+
+pub fn render(format: Format, request: &ResolvedRequest) -> String {
+    match format {
+        Format::Curl => curl(request),
+        Format::Httpie => httpie(request),
+        Format::Http => http(request),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::http::Verb;
+    use serde_json::json;
+
+    fn request() -> ResolvedRequest {
+        ResolvedRequest::new(
+            "https://api.jikken.io/v1/pets?name=it's".to_string(),
+            Verb::Post.as_method(),
+            vec![("Authorization".to_string(), "Bearer abc".to_string())],
+            Some(json!({"name": "O'Brien"})),
+        )
+    }
+
+    #[test]
+    fn curl_escapes_single_quotes() {
+        assert_eq!(
+            "curl -X POST 'https://api.jikken.io/v1/pets?name=it'\\''s' \\\n  \
+             -H 'Authorization: Bearer abc' \\\n  \
+             -H 'Content-Type: application/json' \\\n  \
+             --data-raw '{\"name\":\"O'\\''Brien\"}'",
+            curl(&request())
+        );
+    }
+
+    #[test]
+    fn httpie_sends_raw_body() {
+        assert_eq!(
+            "http POST 'https://api.jikken.io/v1/pets?name=it'\\''s' \\\n  \
+             'Authorization:Bearer abc' \\\n  \
+             'Content-Type:application/json' \\\n  \
+             --raw '{\"name\":\"O'\\''Brien\"}'",
+            httpie(&request())
+        );
+    }
+
+    #[test]
+    fn http_uses_origin_form_and_content_length() {
+        assert_eq!(
+            "POST /v1/pets?name=it%27s HTTP/1.1\r\n\
+             Host: api.jikken.io\r\n\
+             Authorization: Bearer abc\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: 18\r\n\
+             \r\n\
+             {\"name\":\"O'Brien\"}",
+            http(&request())
+        );
+    }
+
+    #[test]
+    fn grpcurl_calls_the_method_on_the_server() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let source_path = format!("{}/", tmp_dir.path().to_str().unwrap());
+        std::fs::write(
+            tmp_dir.path().join("pets.proto"),
+            "syntax = \"proto3\";\npackage pets;\nmessage Pet { string name = 1; }\n\
+             service Pets { rpc Get(Pet) returns (Pet); }\n",
+        )
+        .unwrap();
+        let method = grpc::Method::new(
+            &crate::test::file::UnvalidatedGrpc {
+                proto: Some("pets.proto".to_string()),
+                descriptor_set: None,
+                import_paths: None,
+                method: "pets.Pets/Get".to_string(),
+            },
+            &source_path,
+        )
+        .unwrap();
+        let request = ResolvedRequest::new(
+            "http://localhost:50051".to_string(),
+            Verb::Post.as_method(),
+            vec![("Authorization".to_string(), "Bearer abc".to_string())],
+            Some(json!({"name": "Rex"})),
+        );
+
+        assert_eq!(
+            format!(
+                "grpcurl -plaintext \\\n  \
+                 -import-path '{}' \\\n  \
+                 -proto 'pets.proto' \\\n  \
+                 -H 'Authorization: Bearer abc' \\\n  \
+                 -d '{{\"name\":\"Rex\"}}' \\\n  \
+                 'localhost:50051' \\\n  \
+                 pets.Pets/Get",
+                tmp_dir.path().to_str().unwrap()
+            ),
+            grpcurl(&method, &request)
+        );
+    }
+}
@@ -0,0 +1,311 @@
+pub mod har;
+pub mod openapi;
+pub mod postman;
+
+use crate::errors::GenericError;
+use crate::test;
+use crate::test::http;
+use log::{error, info};
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+// field names whose values usually differ between recordings of the same request
+const VOLATILE_NAMES: &[&str] = &[
+    "id",
+    "uuid",
+    "guid",
+    "etag",
+    "nonce",
+    "token",
+    "accesstoken",
+    "refreshtoken",
+    "sessionid",
+    "requestid",
+    "traceid",
+    "correlationid",
+    "date",
+    "time",
+    "timestamp",
+    "created",
+    "createdat",
+    "createdon",
+    "updated",
+    "updatedat",
+    "updatedon",
+    "modified",
+    "modifiedat",
+    "lastmodified",
+    "expires",
+    "expiresat",
+    "expiresin",
+];
+
+// a generated test along with the file name (without extension) it should be saved as
+pub struct ImportedTest {
+    pub name: String,
+    pub file: test::File,
+}
+
+// loads a YAML or JSON document, YAML allows non-string keys (such as status codes)
+// so they are converted to strings to produce valid JSON
+pub fn load_document(filename: &str) -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+    let data = std::fs::read_to_string(filename)?;
+    let yaml: serde_yaml::Value = serde_yaml::from_str(&data)?;
+    Ok(yaml_to_json(yaml))
+}
+
+fn yaml_to_json(value: serde_yaml::Value) -> serde_json::Value {
+    match value {
+        serde_yaml::Value::Null => serde_json::Value::Null,
+        serde_yaml::Value::Bool(b) => serde_json::Value::Bool(b),
+        serde_yaml::Value::Number(n) => serde_json::to_value(n).unwrap_or_default(),
+        serde_yaml::Value::String(s) => serde_json::Value::String(s),
+        serde_yaml::Value::Sequence(seq) => {
+            serde_json::Value::Array(seq.into_iter().map(yaml_to_json).collect())
+        }
+        serde_yaml::Value::Mapping(map) => serde_json::Value::Object(
+            map.into_iter()
+                .map(|(k, v)| {
+                    let key = match k {
+                        serde_yaml::Value::String(s) => s,
+                        other => serde_yaml::to_string(&other)
+                            .unwrap_or_default()
+                            .trim()
+                            .to_string(),
+                    };
+                    (key, yaml_to_json(v))
+                })
+                .collect(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => yaml_to_json(tagged.value),
+    }
+}
+
+// test files leave out empty settings, but null values inside bodies are meaningful
+fn strip_nulls(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::Mapping(map) => {
+            map.retain(|_, v| !v.is_null());
+            for (k, v) in map.iter_mut() {
+                if k.as_str() != Some("body") && k.as_str() != Some("value") {
+                    strip_nulls(v);
+                }
+            }
+        }
+        serde_yaml::Value::Sequence(seq) => {
+            for v in seq.iter_mut() {
+                strip_nulls(v);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn str_field<'a>(value: &'a serde_json::Value, key: &str) -> &'a str {
+    value.get(key).and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+pub fn verb(method: &str) -> Option<http::Verb> {
+    match method.to_lowercase().as_str() {
+        "get" => Some(http::Verb::Get),
+        "post" => Some(http::Verb::Post),
+        "put" => Some(http::Verb::Put),
+        "patch" => Some(http::Verb::Patch),
+        _ => None,
+    }
+}
+
+// tags are space separated, so spaces inside a folder or group name become dashes
+pub fn tag(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<&str>>().join("-")
+}
+
+fn is_volatile_value(value: &str) -> bool {
+    let uuid = value.len() == 36
+        && value.chars().enumerate().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+    let jwt = value.starts_with("eyJ") && value.split('.').count() == 3;
+    let timestamp = chrono::DateTime::parse_from_rfc3339(value).is_ok()
+        || chrono::DateTime::parse_from_rfc2822(value).is_ok();
+
+    uuid || jwt || timestamp
+}
+
+fn collect_volatile(value: &serde_json::Value, path: &str, results: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, v) in map.iter() {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                let normalized = key.to_lowercase().replace(['_', '-'], "");
+                let volatile = VOLATILE_NAMES.contains(&normalized.as_str())
+                    || v.as_str().map(is_volatile_value).unwrap_or(false);
+
+                if volatile {
+                    if !results.contains(&field) {
+                        results.push(field);
+                    }
+                } else {
+                    collect_volatile(v, &field, results);
+                }
+            }
+        }
+        // ignore paths apply to every element of an array
+        serde_json::Value::Array(items) => {
+            for item in items.iter() {
+                collect_volatile(item, path, results);
+            }
+        }
+        _ => {}
+    }
+}
+
+// finds fields in a captured response body, such as ids and timestamps, that are unlikely
+// to match when the request is made again, as paths suitable for a response `ignore` list
+pub fn volatile_fields(body: &serde_json::Value) -> Vec<String> {
+    let mut results = Vec::new();
+    collect_volatile(body, "", &mut results);
+    results
+}
+
+// builds the expected response from a captured status and body, only JSON bodies are kept
+pub fn captured_response(
+    status: Option<u16>,
+    body: Option<&str>,
+) -> test::file::UnvalidatedResponse {
+    let body = body.and_then(|b| serde_json::from_str::<serde_json::Value>(b).ok());
+    let ignore = body
+        .as_ref()
+        .map(volatile_fields)
+        .filter(|fields| !fields.is_empty());
+
+    test::file::UnvalidatedResponse {
+        status,
+        headers: None,
+        body,
+        ignore,
+        extract: None,
+    }
+}
+
+pub fn to_yaml(file: &test::File) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut value = serde_yaml::to_value(file)?;
+    strip_nulls(&mut value);
+    Ok(serde_yaml::to_string(&value)?)
+}
+
+// converts free text (operation ids, paths, request names) into a safe file name
+pub fn sanitize_name(name: &str) -> String {
+    let mut result = String::new();
+
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            result.push(c);
+        } else if !result.ends_with('_') && !result.is_empty() {
+            result.push('_');
+        }
+    }
+
+    let result = result.trim_end_matches('_').to_string();
+
+    if result.is_empty() {
+        "test".to_string()
+    } else {
+        result
+    }
+}
+
+pub async fn write_tests(
+    tests: Vec<ImportedTest>,
+    output: Option<String>,
+    source: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let output_dir = output.unwrap_or_else(|| ".".to_string());
+    fs::create_dir_all(&output_dir).await?;
+
+    let mut created = 0;
+    let mut skipped = 0;
+    let mut used = HashSet::new();
+
+    for imported in tests.iter() {
+        // captures often repeat the same request, so later copies get a numbered name
+        let mut name = imported.name.clone();
+        let mut counter = 2;
+        while !used.insert(name.clone()) {
+            name = format!("{}_{}", imported.name, counter);
+            counter += 1;
+        }
+
+        let filename = Path::new(&output_dir).join(format!("{}.jkt", name));
+        let filename_str = filename.to_string_lossy().to_string();
+
+        if filename.exists() {
+            error!(
+                "`{}` already exists. Please pick a new location or delete the existing file.",
+                filename_str
+            );
+            skipped += 1;
+            continue;
+        }
+
+        let mut file = fs::File::create(&filename).await?;
+        file.write_all(to_yaml(&imported.file)?.as_bytes()).await?;
+        info!("Successfully created test (`{}`).\n", filename_str);
+        created += 1;
+    }
+
+    info!(
+        "Jikken imported {} test{} from `{}`.\n",
+        created,
+        if created != 1 { "s" } else { "" },
+        source
+    );
+
+    if skipped > 0 {
+        return Err(Box::new(GenericError {
+            reason: format!("{} test files already existed", skipped),
+        }));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn volatile_fields_are_found_by_name_and_value() {
+        let body = json!({
+            "id": 12,
+            "name": "Rex",
+            "owner": {"created_at": 1700000000, "email": "a@b.c"},
+            "visits": [
+                {"at": "2023-11-14T22:13:20Z", "vet": "Dr. Who"},
+                {"at": "2023-11-15T09:00:00+01:00", "vet": "Dr. No"}
+            ],
+            "ref": "6f1c1d7e-0a8b-4bd5-9c7b-3a0e1f2a4b5c"
+        });
+
+        assert_eq!(
+            vec!["id", "owner.created_at", "ref", "visits.at"],
+            volatile_fields(&body)
+        );
+    }
+
+    #[test]
+    fn names_are_sanitized() {
+        assert_eq!("GET_users_id", sanitize_name("GET /users/{id}"));
+        assert_eq!("test", sanitize_name("  / "));
+        assert_eq!("read-only", tag(" read   only "));
+    }
+}
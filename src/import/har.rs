@@ -0,0 +1,301 @@
+use crate::errors::GenericError;
+use crate::import::{captured_response, sanitize_name, str_field, verb, ImportedTest};
+use crate::test;
+use crate::test::{file, http, variable};
+use log::warn;
+use serde_json::Value;
+use std::cell::Cell;
+use std::error::Error;
+
+// headers the browser or transport sets on its own, they would only make tests brittle
+const SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "content-length",
+    "accept-encoding",
+    "accept-language",
+    "user-agent",
+    "referer",
+    "origin",
+    "cache-control",
+    "pragma",
+    "priority",
+    "dnt",
+    "upgrade-insecure-requests",
+    "if-none-match",
+    "if-modified-since",
+];
+
+// credentials must not end up in test files, their values become variables to fill in
+const CREDENTIAL_HEADERS: &[&str] = &["authorization", "cookie", "x-csrf-token", "x-xsrf-token"];
+
+// captures of page loads include assets alongside the API calls worth testing
+const SKIPPED_CONTENT: &[&str] = &[
+    "image/",
+    "font/",
+    "audio/",
+    "video/",
+    "text/css",
+    "text/html",
+    "javascript",
+];
+
+fn pairs(entries: Option<&Value>) -> Vec<(&str, &str)> {
+    match entries {
+        Some(Value::Array(entries)) => entries
+            .iter()
+            .map(|e| (str_field(e, "name"), str_field(e, "value")))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn convert_entry(entry: &Value) -> Option<ImportedTest> {
+    let request = entry.get("request")?;
+    let response = entry.get("response").unwrap_or(&Value::Null);
+    let method = str_field(request, "method");
+    let full_url = str_field(request, "url");
+
+    let parsed = url::Url::parse(full_url).ok()?;
+    let display = format!("{} {}", method.to_uppercase(), parsed.path());
+
+    let verb = match verb(method) {
+        Some(v) => v,
+        None => {
+            warn!(
+                "skipping `{}`, the {} method is not supported",
+                display,
+                method.to_uppercase()
+            );
+            return None;
+        }
+    };
+
+    let mut base = parsed.clone();
+    base.set_query(None);
+    base.set_fragment(None);
+
+    let params: Vec<http::Parameter> = parsed
+        .query_pairs()
+        .map(|(param, value)| http::Parameter {
+            param: param.to_string(),
+            value: value.to_string(),
+            matches_variable: Cell::from(false),
+        })
+        .collect();
+
+    let mut variables = Vec::new();
+    let headers: Vec<http::Header> = pairs(request.get("headers"))
+        .into_iter()
+        .filter(|(name, _)| {
+            let lower = name.to_lowercase();
+            !name.starts_with(':')
+                && !lower.starts_with("sec-")
+                && !SKIPPED_HEADERS.contains(&lower.as_str())
+        })
+        .map(|(name, value)| {
+            let lower = name.to_lowercase();
+            if !CREDENTIAL_HEADERS.contains(&lower.as_str()) {
+                return http::Header::new(name.to_string(), value.to_string());
+            }
+
+            let placeholder = lower.replace('-', "_");
+            if !variables
+                .iter()
+                .any(|v: &file::UnvalidatedVariable| v.name == placeholder)
+            {
+                variables.push(file::UnvalidatedVariable {
+                    name: placeholder.clone(),
+                    data_type: Some(variable::Type::String),
+                    value: Some(serde_yaml::Value::String(String::new())),
+                    modifier: None,
+                    format: None,
+                    file: None,
+                });
+            }
+            http::Header::new(name.to_string(), format!("${{{}}}", placeholder))
+        })
+        .collect();
+
+    let body = request.get("postData").and_then(|p| {
+        let text = str_field(p, "text");
+        if text.is_empty() {
+            None
+        } else {
+            Some(serde_json::from_str(text).unwrap_or(Value::String(text.to_string())))
+        }
+    });
+
+    // encoded content is binary, so only the status is kept
+    let content = response.get("content").unwrap_or(&Value::Null);
+    let captured_body = match content.get("encoding") {
+        Some(_) => None,
+        None => content.get("text").and_then(|t| t.as_str()),
+    };
+    let status = response
+        .get("status")
+        .and_then(|s| s.as_u64())
+        .map(|s| s as u16);
+
+    Some(ImportedTest {
+        name: sanitize_name(&format!("{}_{}", method.to_uppercase(), parsed.path())),
+        file: test::File {
+            name: Some(display),
+            id: None,
+            env: None,
+            tags: None,
+            requires: None,
+            iterate: None,
+            dataset: None,
+            skip: None,
+            only: None,
+            xfail: None,
+            setup: None,
+            request: Some(file::UnvalidatedRequest {
+                method: Some(verb),
+                url: base.to_string(),
+                params: if params.is_empty() {
+                    None
+                } else {
+                    Some(params)
+                },
+                headers: if headers.is_empty() {
+                    None
+                } else {
+                    Some(headers)
+                },
+                body,
+                graphql: None,
+                grpc: None,
+            }),
+            compare: None,
+            stream: None,
+            response: Some(captured_response(status, captured_body)),
+            stages: None,
+            cleanup: None,
+            variables: if variables.is_empty() {
+                None
+            } else {
+                Some(variables)
+            },
+            filename: String::new(),
+        },
+    })
+}
+
+// generates one test per captured API request in a HAR file, skipping static assets
+pub fn convert(document: &Value) -> Result<Vec<ImportedTest>, Box<dyn Error + Send + Sync>> {
+    let entries = match document.get("log").and_then(|l| l.get("entries")) {
+        Some(Value::Array(entries)) => entries,
+        _ => {
+            return Err(Box::new(GenericError {
+                reason: "document is not a HAR file".to_string(),
+            }))
+        }
+    };
+
+    let mut results = Vec::new();
+    let mut assets = 0;
+
+    for entry in entries.iter() {
+        let response = entry.get("response").unwrap_or(&Value::Null);
+        let mime_type = response
+            .get("content")
+            .map(|c| str_field(c, "mimeType"))
+            .unwrap_or_default()
+            .to_lowercase();
+        let status = response.get("status").and_then(|s| s.as_u64()).unwrap_or(0);
+
+        // a status of 0 means the browser never received a response
+        if status == 0 || SKIPPED_CONTENT.iter().any(|c| mime_type.contains(c)) {
+            assets += 1;
+            continue;
+        }
+
+        if let Some(imported) = convert_entry(entry) {
+            results.push(imported);
+        }
+    }
+
+    if assets > 0 {
+        warn!(
+            "skipped {} entr{} for static assets or requests without a response",
+            assets,
+            if assets != 1 { "ies" } else { "y" }
+        );
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn api_requests_become_tests() {
+        let har = json!({"log": {"entries": [
+            {
+                "request": {
+                    "method": "GET",
+                    "url": "https://api.example.com/v1/pets?limit=2#top",
+                    "headers": [
+                        {"name": ":authority", "value": "api.example.com"},
+                        {"name": "User-Agent", "value": "Mozilla/5.0"},
+                        {"name": "sec-fetch-mode", "value": "cors"},
+                        {"name": "Authorization", "value": "Bearer abc"},
+                        {"name": "Cookie", "value": "session=abc"},
+                        {"name": "X-CSRF-Token", "value": "abc"},
+                        {"name": "Accept", "value": "application/json"}
+                    ]
+                },
+                "response": {
+                    "status": 200,
+                    "content": {
+                        "mimeType": "application/json",
+                        "text": "[{\"id\": \"6f1c1d7e-0a8b-4bd5-9c7b-3a0e1f2a4b5c\", \"name\": \"Rex\"}]"
+                    }
+                }
+            },
+            {
+                "request": {"method": "GET", "url": "https://example.com/logo.png", "headers": []},
+                "response": {"status": 200, "content": {"mimeType": "image/png", "encoding": "base64", "text": "AA=="}}
+            }
+        ]}});
+
+        let tests = convert(&har).unwrap();
+        assert_eq!(1, tests.len());
+        assert_eq!("GET_v1_pets", tests[0].name);
+
+        let request = tests[0].file.request.as_ref().unwrap();
+        assert_eq!("https://api.example.com/v1/pets", request.url);
+        assert_eq!("limit", request.params.as_ref().unwrap()[0].param);
+
+        let headers = request.headers.as_ref().unwrap();
+        let written: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|h| (h.header.as_str(), h.value.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("Authorization", "${authorization}"),
+                ("Cookie", "${cookie}"),
+                ("X-CSRF-Token", "${x_csrf_token}"),
+                ("Accept", "application/json")
+            ],
+            written
+        );
+
+        let variables = tests[0].file.variables.as_ref().unwrap();
+        let names: Vec<&str> = variables.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(vec!["authorization", "cookie", "x_csrf_token"], names);
+        assert!(variables
+            .iter()
+            .all(|v| v.value == Some(serde_yaml::Value::String(String::new()))));
+
+        let response = tests[0].file.response.as_ref().unwrap();
+        assert_eq!(Some(200), response.status);
+        assert_eq!(Some(vec!["id".to_string()]), response.ignore);
+    }
+}
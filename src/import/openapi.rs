@@ -0,0 +1,593 @@
+use crate::errors::GenericError;
+use crate::import::{sanitize_name, tag, verb, ImportedTest};
+use crate::test;
+use crate::test::{file, http, variable};
+use log::warn;
+use serde_json::{Map, Value};
+use std::cell::Cell;
+use std::error::Error;
+
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+const MAX_DEPTH: usize = 8;
+
+enum Version {
+    Swagger2,
+    OpenApi3,
+}
+
+struct Spec<'a> {
+    root: &'a Value,
+    version: Version,
+}
+
+impl<'a> Spec<'a> {
+    // follows local `$ref` pointers, such as `#/components/schemas/Pet`
+    fn resolve(&self, value: &'a Value) -> &'a Value {
+        let mut current = value;
+
+        for _ in 0..MAX_DEPTH {
+            match current.get("$ref").and_then(|r| r.as_str()) {
+                Some(reference) if reference.starts_with('#') => {
+                    match self.root.pointer(&reference[1..]) {
+                        Some(v) => current = v,
+                        None => return current,
+                    }
+                }
+                _ => return current,
+            }
+        }
+
+        current
+    }
+
+    fn base_url(&self) -> String {
+        match self.version {
+            Version::OpenApi3 => {
+                let server = self
+                    .root
+                    .get("servers")
+                    .and_then(|s| s.get(0))
+                    .map(|s| self.resolve(s));
+
+                let url = match server {
+                    Some(s) => {
+                        let mut url = s
+                            .get("url")
+                            .and_then(|u| u.as_str())
+                            .unwrap_or_default()
+                            .to_string();
+
+                        if let Some(Value::Object(vars)) = s.get("variables") {
+                            for (name, var) in vars.iter() {
+                                if let Some(default) = var.get("default").and_then(|d| d.as_str()) {
+                                    url = url.replace(&format!("{{{}}}", name), default);
+                                }
+                            }
+                        }
+
+                        url
+                    }
+                    None => String::new(),
+                };
+
+                if url.contains("://") {
+                    url.trim_end_matches('/').to_string()
+                } else {
+                    format!("${{baseUrl}}{}", url.trim_end_matches('/'))
+                }
+            }
+            Version::Swagger2 => {
+                let base_path = self
+                    .root
+                    .get("basePath")
+                    .and_then(|b| b.as_str())
+                    .unwrap_or_default()
+                    .trim_end_matches('/');
+
+                match self.root.get("host").and_then(|h| h.as_str()) {
+                    Some(host) => {
+                        let scheme = self
+                            .root
+                            .get("schemes")
+                            .and_then(|s| s.get(0))
+                            .and_then(|s| s.as_str())
+                            .unwrap_or("https");
+                        format!("{}://{}{}", scheme, host, base_path)
+                    }
+                    None => format!("${{baseUrl}}{}", base_path),
+                }
+            }
+        }
+    }
+
+    // builds an example value from a schema, preferring the examples and defaults it defines
+    fn example(&self, schema: &'a Value, depth: usize) -> Value {
+        let schema = self.resolve(schema);
+
+        if depth > MAX_DEPTH {
+            return Value::Null;
+        }
+
+        if let Some(example) = schema.get("example") {
+            return example.clone();
+        }
+
+        if let Some(default) = schema.get("default") {
+            return default.clone();
+        }
+
+        if let Some(first) = schema.get("enum").and_then(|e| e.get(0)) {
+            return first.clone();
+        }
+
+        if let Some(Value::Array(all)) = schema.get("allOf") {
+            let mut merged = Map::new();
+            for s in all.iter() {
+                if let Value::Object(m) = self.example(s, depth + 1) {
+                    merged.extend(m);
+                }
+            }
+            return Value::Object(merged);
+        }
+
+        for key in ["oneOf", "anyOf"] {
+            if let Some(first) = schema.get(key).and_then(|s| s.get(0)) {
+                return self.example(first, depth + 1);
+            }
+        }
+
+        let schema_type = schema.get("type").and_then(|t| t.as_str()).unwrap_or(
+            if schema.get("properties").is_some() {
+                "object"
+            } else {
+                ""
+            },
+        );
+
+        match schema_type {
+            "object" => {
+                let mut result = Map::new();
+                if let Some(Value::Object(properties)) = schema.get("properties") {
+                    for (name, property) in properties.iter() {
+                        result.insert(name.clone(), self.example(property, depth + 1));
+                    }
+                }
+                Value::Object(result)
+            }
+            "array" => match schema.get("items") {
+                Some(items) => Value::Array(vec![self.example(items, depth + 1)]),
+                None => Value::Array(Vec::new()),
+            },
+            "integer" | "number" => Value::from(0),
+            "boolean" => Value::Bool(false),
+            "string" => Value::String(String::new()),
+            _ => Value::Null,
+        }
+    }
+
+    fn parameter_example(&self, parameter: &'a Value) -> Value {
+        if let Some(example) = parameter.get("example") {
+            return example.clone();
+        }
+
+        if let Some(Value::Object(examples)) = parameter.get("examples") {
+            if let Some(value) = examples
+                .values()
+                .next()
+                .and_then(|e| self.resolve(e).get("value"))
+            {
+                return value.clone();
+            }
+        }
+
+        match parameter.get("schema") {
+            Some(schema) => self.example(schema, 0),
+            // swagger 2 describes non-body parameters inline
+            None => self.example(parameter, 0),
+        }
+    }
+
+    // openapi 3 nests examples under media types, swagger 2 lists them by mime type
+    fn content_example(&self, container: &'a Value, generate: bool) -> Option<Value> {
+        match self.version {
+            Version::OpenApi3 => {
+                let content = container.get("content")?;
+                let media = content
+                    .get("application/json")
+                    .or_else(|| content.as_object().and_then(|c| c.values().next()))?;
+
+                if let Some(example) = media.get("example") {
+                    return Some(example.clone());
+                }
+
+                if let Some(Value::Object(examples)) = media.get("examples") {
+                    if let Some(value) = examples
+                        .values()
+                        .next()
+                        .and_then(|e| self.resolve(e).get("value"))
+                    {
+                        return Some(value.clone());
+                    }
+                }
+
+                let schema = media.get("schema")?;
+                if generate {
+                    Some(self.example(schema, 0))
+                } else {
+                    self.resolve(schema).get("example").cloned()
+                }
+            }
+            Version::Swagger2 => {
+                if let Some(example) = container
+                    .get("examples")
+                    .and_then(|e| e.get("application/json"))
+                {
+                    return Some(example.clone());
+                }
+
+                let schema = container.get("schema")?;
+                if generate {
+                    Some(self.example(schema, 0))
+                } else {
+                    self.resolve(schema).get("example").cloned()
+                }
+            }
+        }
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+// operation parameters override path level parameters with the same name and location
+fn merge_parameters<'a>(
+    spec: &Spec<'a>,
+    path_item: &'a Value,
+    operation: &'a Value,
+) -> Vec<&'a Value> {
+    let mut results: Vec<&'a Value> = Vec::new();
+
+    for source in [path_item, operation] {
+        if let Some(Value::Array(parameters)) = source.get("parameters") {
+            for p in parameters.iter().map(|p| spec.resolve(p)) {
+                results.retain(|r| r.get("name") != p.get("name") || r.get("in") != p.get("in"));
+                results.push(p);
+            }
+        }
+    }
+
+    results
+}
+
+fn convert_operation(
+    spec: &Spec,
+    base_url: &str,
+    path: &str,
+    path_item: &Value,
+    method: &str,
+    operation: &Value,
+) -> Option<ImportedTest> {
+    let verb = verb(method)?;
+    let operation_id = operation.get("operationId").and_then(|o| o.as_str());
+    let display = format!("{} {}", method.to_uppercase(), path);
+
+    let mut url = format!("{}{}", base_url, path);
+    let mut variables = Vec::new();
+    let mut params = Vec::new();
+    let mut headers = Vec::new();
+    let mut body = None;
+
+    for parameter in merge_parameters(spec, path_item, operation) {
+        let name = parameter
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or_default();
+        let required = parameter
+            .get("required")
+            .and_then(|r| r.as_bool())
+            .unwrap_or(false);
+
+        match parameter.get("in").and_then(|i| i.as_str()) {
+            Some("path") => {
+                let example = spec.parameter_example(parameter);
+                let (data_type, value) = match example {
+                    Value::Number(n) => (
+                        variable::Type::Int,
+                        serde_yaml::to_value(n).unwrap_or_default(),
+                    ),
+                    other => (
+                        variable::Type::String,
+                        serde_yaml::Value::String(value_to_string(&other)),
+                    ),
+                };
+
+                url = url.replace(&format!("{{{}}}", name), &format!("${{{}}}", name));
+                variables.push(file::UnvalidatedVariable {
+                    name: name.to_string(),
+                    data_type: Some(data_type),
+                    value: Some(value),
+                    modifier: None,
+                    format: None,
+                    file: None,
+                });
+            }
+            Some("query") if required => params.push(http::Parameter {
+                param: name.to_string(),
+                value: value_to_string(&spec.parameter_example(parameter)),
+                matches_variable: Cell::from(false),
+            }),
+            Some("header") if required => {
+                headers.push(http::Header::new(
+                    name.to_string(),
+                    value_to_string(&spec.parameter_example(parameter)),
+                ));
+            }
+            Some("body") => {
+                body = parameter.get("schema").map(|s| spec.example(s, 0));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(request_body) = operation.get("requestBody") {
+        body = spec.content_example(spec.resolve(request_body), true);
+    }
+
+    // the lowest success code is the expected outcome when the operation is called with examples
+    let mut success: Vec<(&String, &Value)> = match operation.get("responses") {
+        Some(Value::Object(responses)) => responses
+            .iter()
+            .filter(|(code, _)| code.starts_with('2'))
+            .collect(),
+        _ => Vec::new(),
+    };
+    success.sort_by(|a, b| a.0.cmp(b.0));
+
+    let response = success
+        .first()
+        .map(|(code, definition)| file::UnvalidatedResponse {
+            status: code.parse::<u16>().ok(),
+            headers: None,
+            body: spec.content_example(spec.resolve(definition), false),
+            ignore: None,
+            extract: None,
+        });
+
+    let tags = operation.get("tags").and_then(|t| t.as_array()).map(|t| {
+        t.iter()
+            .filter_map(|tag| tag.as_str())
+            .map(tag)
+            .collect::<Vec<String>>()
+            .join(" ")
+    });
+
+    let name = operation
+        .get("summary")
+        .and_then(|s| s.as_str())
+        .or(operation_id)
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| display.clone());
+
+    Some(ImportedTest {
+        name: sanitize_name(&operation_id.map(|o| o.to_string()).unwrap_or(display)),
+        file: test::File {
+            name: Some(name),
+            id: operation_id.map(|o| o.to_string()),
+            env: None,
+            tags: tags.filter(|t| !t.is_empty()),
+            requires: None,
+            iterate: None,
+            dataset: None,
+            skip: None,
+            only: None,
+            xfail: None,
+            setup: None,
+            request: Some(file::UnvalidatedRequest {
+                method: Some(verb),
+                url,
+                params: if params.is_empty() {
+                    None
+                } else {
+                    Some(params)
+                },
+                headers: if headers.is_empty() {
+                    None
+                } else {
+                    Some(headers)
+                },
+                body,
+                graphql: None,
+                grpc: None,
+            }),
+            compare: None,
+            stream: None,
+            response,
+            stages: None,
+            cleanup: None,
+            variables: if variables.is_empty() {
+                None
+            } else {
+                Some(variables)
+            },
+            filename: String::new(),
+        },
+    })
+}
+
+// generates one test per operation in an OpenAPI 3 or Swagger 2 document
+pub fn convert(document: &Value) -> Result<Vec<ImportedTest>, Box<dyn Error + Send + Sync>> {
+    let version = if document.get("openapi").is_some() {
+        Version::OpenApi3
+    } else if document.get("swagger").is_some() {
+        Version::Swagger2
+    } else {
+        return Err(Box::new(GenericError {
+            reason: "document is not an OpenAPI or Swagger specification".to_string(),
+        }));
+    };
+
+    let spec = Spec {
+        root: document,
+        version,
+    };
+    let base_url = spec.base_url();
+    let mut results = Vec::new();
+
+    // relative servers are left to a global, as every generated test shares it
+    if base_url.starts_with("${baseUrl}") {
+        warn!(
+            "the specification's server url is relative, define a `baseUrl` global variable such as `baseUrl=\"https://localhost:5001\"` under `[globals]` in .jikken to run the generated tests"
+        );
+    }
+
+    if let Some(Value::Object(paths)) = document.get("paths") {
+        for (path, path_item) in paths.iter() {
+            let path_item = spec.resolve(path_item);
+
+            for method in METHODS.iter() {
+                let operation = match path_item.get(*method) {
+                    Some(o) => o,
+                    None => continue,
+                };
+
+                match convert_operation(&spec, &base_url, path, path_item, method, operation) {
+                    Some(imported) => results.push(imported),
+                    None => warn!(
+                        "skipping `{} {}`, the {} method is not supported",
+                        method.to_uppercase(),
+                        path,
+                        method.to_uppercase()
+                    ),
+                }
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r#"
+openapi: 3.0.0
+servers:
+  - url: https://{env}.example.com/v1
+    variables:
+      env:
+        default: api
+paths:
+  /pets/{petId}:
+    parameters:
+      - name: petId
+        in: path
+        required: true
+        schema:
+          type: integer
+          example: 7
+    get:
+      operationId: getPet
+      summary: Find a pet
+      tags: [pets, read only]
+      parameters:
+        - name: verbose
+          in: query
+          required: true
+          schema:
+            type: boolean
+      responses:
+        200:
+          description: ok
+          content:
+            application/json:
+              example:
+                id: 7
+                name: Rex
+    delete:
+      responses:
+        204:
+          description: deleted
+  /pets:
+    post:
+      requestBody:
+        content:
+          application/json:
+            schema:
+              $ref: '#/components/schemas/Pet'
+      responses:
+        201:
+          description: created
+        400:
+          description: bad request
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        name:
+          type: string
+          example: Rex
+        age:
+          type: integer
+"#;
+
+    fn convert_spec() -> Vec<ImportedTest> {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(SPEC).unwrap();
+        convert(&crate::import::yaml_to_json(yaml)).unwrap()
+    }
+
+    fn find(tests: &[ImportedTest], name: &str) -> test::File {
+        tests.iter().find(|t| t.name == name).unwrap().file.clone()
+    }
+
+    #[test]
+    fn operations_become_tests() {
+        let tests = convert_spec();
+        let mut names: Vec<&str> = tests.iter().map(|t| t.name.as_str()).collect();
+        names.sort();
+        assert_eq!(vec!["POST_pets", "getPet"], names);
+    }
+
+    #[test]
+    fn path_parameters_become_variables() {
+        let tests = convert_spec();
+        let get = &find(&tests, "getPet");
+        let request = get.request.as_ref().unwrap();
+
+        assert_eq!("https://api.example.com/v1/pets/${petId}", request.url);
+        assert_eq!("verbose", request.params.as_ref().unwrap()[0].param);
+        assert_eq!(Some("pets read-only".to_string()), get.tags);
+
+        let variables = get.variables.as_ref().unwrap();
+        assert_eq!("petId", variables[0].name);
+        assert_eq!(Some(variable::Type::Int), variables[0].data_type);
+
+        let response = get.response.as_ref().unwrap();
+        assert_eq!(Some(200), response.status);
+        assert_eq!(
+            Some(serde_json::json!({"id": 7, "name": "Rex"})),
+            response.body
+        );
+    }
+
+    #[test]
+    fn request_bodies_are_generated_from_schemas() {
+        let tests = convert_spec();
+        let post = &find(&tests, "POST_pets");
+
+        assert_eq!(
+            Some(serde_json::json!({"name": "Rex", "age": 0})),
+            post.request.as_ref().unwrap().body
+        );
+        assert_eq!(Some(201), post.response.as_ref().unwrap().status);
+        assert_eq!(None, post.response.as_ref().unwrap().body);
+    }
+}
@@ -0,0 +1,446 @@
+use crate::errors::GenericError;
+use crate::import::{captured_response, sanitize_name, str_field, tag, verb, ImportedTest};
+use crate::test;
+use crate::test::{file, http, variable};
+use log::warn;
+use serde_json::Value;
+use std::cell::Cell;
+use std::error::Error;
+
+// postman writes variables as `{{name}}`, jikken uses `${name}`
+fn convert_variables(text: &str) -> String {
+    text.replace("{{", "${").replace("}}", "}")
+}
+
+fn referenced_variables(text: &str, results: &mut Vec<String>) {
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        match rest.find("}}") {
+            Some(end) => {
+                let name = rest[..end].trim().to_string();
+                if !name.is_empty() && !results.contains(&name) {
+                    results.push(name);
+                }
+                rest = &rest[end + 2..];
+            }
+            None => break,
+        }
+    }
+}
+
+fn enabled(entries: Option<&Value>) -> Vec<&Value> {
+    match entries {
+        Some(Value::Array(entries)) => entries
+            .iter()
+            .filter(|e| !e.get("disabled").and_then(|d| d.as_bool()).unwrap_or(false))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// urls are either a raw string or an object with the raw string alongside its parts
+fn raw_url(url: Option<&Value>) -> String {
+    match url {
+        Some(Value::String(s)) => s.clone(),
+        Some(u) => match u.get("raw").and_then(|r| r.as_str()) {
+            Some(raw) => raw.to_string(),
+            None => {
+                let join = |key: &str, separator: &str| match u.get(key) {
+                    Some(Value::Array(parts)) => parts
+                        .iter()
+                        .filter_map(|p| p.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(separator),
+                    Some(Value::String(s)) => s.clone(),
+                    _ => String::new(),
+                };
+
+                let protocol = u
+                    .get("protocol")
+                    .and_then(|p| p.as_str())
+                    .map(|p| format!("{}://", p))
+                    .unwrap_or_default();
+                format!("{}{}/{}", protocol, join("host", "."), join("path", "/"))
+            }
+        },
+        None => String::new(),
+    }
+}
+
+fn auth_header(auth: Option<&Value>) -> Option<http::Header> {
+    let auth = auth?;
+    let setting = |kind: &str, key: &str| {
+        enabled(auth.get(kind))
+            .into_iter()
+            .find(|e| str_field(e, "key") == key)
+            .map(|e| match e.get("value") {
+                Some(Value::String(s)) => s.clone(),
+                Some(other) => other.to_string(),
+                None => String::new(),
+            })
+    };
+
+    match str_field(auth, "type") {
+        "bearer" => Some(http::Header::new(
+            "Authorization".to_string(),
+            format!("Bearer {}", setting("bearer", "token")?),
+        )),
+        "apikey" if setting("apikey", "in").as_deref() != Some("query") => Some(http::Header::new(
+            setting("apikey", "key")?,
+            setting("apikey", "value").unwrap_or_default(),
+        )),
+        _ => None,
+    }
+}
+
+struct Context<'a> {
+    tags: Vec<String>,
+    auth: Option<&'a Value>,
+    variables: &'a [(String, Value)],
+}
+
+fn convert_request(
+    context: &Context,
+    name: &str,
+    item: &Value,
+    results: &mut Vec<ImportedTest>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let request = item.get("request").unwrap_or(&Value::Null);
+
+    // a request may be saved as just its url, in which case it is a GET
+    let (method, url, request) = match request {
+        Value::String(url) => ("GET", url.clone(), &Value::Null),
+        r => (
+            r.get("method").and_then(|m| m.as_str()).unwrap_or("GET"),
+            raw_url(r.get("url")),
+            r,
+        ),
+    };
+
+    let verb = match verb(method) {
+        Some(v) => v,
+        None => {
+            warn!(
+                "skipping `{}`, the {} method is not supported",
+                name,
+                method.to_uppercase()
+            );
+            return Ok(());
+        }
+    };
+
+    let mut referenced = Vec::new();
+    referenced_variables(&url, &mut referenced);
+
+    let (base, query) = match url.split_once('?') {
+        Some((b, q)) => (b.to_string(), Some(q.to_string())),
+        None => (url.clone(), None),
+    };
+
+    let params: Vec<http::Parameter> = query
+        .iter()
+        .flat_map(|q| q.split('&'))
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (param, value) = pair.split_once('=').unwrap_or((pair, ""));
+            http::Parameter {
+                param: convert_variables(param),
+                value: convert_variables(value),
+                matches_variable: Cell::from(false),
+            }
+        })
+        .collect();
+
+    let mut headers: Vec<http::Header> = enabled(request.get("header"))
+        .into_iter()
+        .map(|h| {
+            let key = str_field(h, "key");
+            let value = str_field(h, "value");
+            referenced_variables(key, &mut referenced);
+            referenced_variables(value, &mut referenced);
+            http::Header::new(convert_variables(key), convert_variables(value))
+        })
+        .collect();
+
+    let auth = match request.get("auth") {
+        Some(a) => Some(a),
+        None => context.auth,
+    };
+    if let Some(header) = auth_header(auth) {
+        referenced_variables(&header.value, &mut referenced);
+        referenced_variables(&header.header, &mut referenced);
+        headers.push(http::Header::new(
+            convert_variables(&header.header),
+            convert_variables(&header.value),
+        ));
+    }
+
+    let body = match request.get("body") {
+        Some(b) if str_field(b, "mode") == "raw" => {
+            let raw = str_field(b, "raw");
+            if raw.trim().is_empty() {
+                None
+            } else {
+                referenced_variables(raw, &mut referenced);
+                let raw = convert_variables(raw);
+                Some(serde_json::from_str(&raw).unwrap_or(Value::String(raw)))
+            }
+        }
+        Some(b) if b.get("mode").is_some() => {
+            warn!(
+                "`{}` uses a {} body, which is not supported and has been left out",
+                name,
+                str_field(b, "mode")
+            );
+            None
+        }
+        _ => None,
+    };
+
+    // saved example responses become the expected response
+    let response = item.get("response").and_then(|r| r.get(0)).map(|r| {
+        captured_response(
+            r.get("code").and_then(|c| c.as_u64()).map(|c| c as u16),
+            r.get("body").and_then(|b| b.as_str()),
+        )
+    });
+
+    let variables: Vec<file::UnvalidatedVariable> = context
+        .variables
+        .iter()
+        .filter(|(key, _)| referenced.contains(key))
+        .map(|(key, value)| {
+            let (data_type, value) = match value {
+                Value::Number(n) if n.is_i64() => (
+                    variable::Type::Int,
+                    serde_yaml::to_value(n).unwrap_or_default(),
+                ),
+                Value::String(s) => (variable::Type::String, serde_yaml::Value::String(s.clone())),
+                other => (
+                    variable::Type::String,
+                    serde_yaml::Value::String(other.to_string()),
+                ),
+            };
+
+            file::UnvalidatedVariable {
+                name: key.clone(),
+                data_type: Some(data_type),
+                value: Some(value),
+                modifier: None,
+                format: None,
+                file: None,
+            }
+        })
+        .collect();
+
+    results.push(ImportedTest {
+        name: sanitize_name(name),
+        file: test::File {
+            name: Some(name.to_string()),
+            id: None,
+            env: None,
+            tags: if context.tags.is_empty() {
+                None
+            } else {
+                Some(context.tags.join(" "))
+            },
+            requires: None,
+            iterate: None,
+            dataset: None,
+            skip: None,
+            only: None,
+            xfail: None,
+            setup: None,
+            request: Some(file::UnvalidatedRequest {
+                method: Some(verb),
+                url: convert_variables(&base),
+                params: if params.is_empty() {
+                    None
+                } else {
+                    Some(params)
+                },
+                headers: if headers.is_empty() {
+                    None
+                } else {
+                    Some(headers)
+                },
+                body,
+                graphql: None,
+                grpc: None,
+            }),
+            compare: None,
+            stream: None,
+            response,
+            stages: None,
+            cleanup: None,
+            variables: if variables.is_empty() {
+                None
+            } else {
+                Some(variables)
+            },
+            filename: String::new(),
+        },
+    });
+
+    Ok(())
+}
+
+// folders nest arbitrarily deep, each folder adds its name as a tag to the requests inside it
+fn convert_items(
+    context: &Context,
+    items: &Value,
+    results: &mut Vec<ImportedTest>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let items = match items {
+        Value::Array(items) => items,
+        _ => return Ok(()),
+    };
+
+    for item in items.iter() {
+        let name = str_field(item, "name");
+
+        match item.get("item") {
+            Some(children) => {
+                let mut tags = context.tags.clone();
+                let folder = tag(name);
+                if !folder.is_empty() && !tags.contains(&folder) {
+                    tags.push(folder);
+                }
+
+                let child_context = Context {
+                    tags,
+                    auth: item.get("auth").or(context.auth),
+                    variables: context.variables,
+                };
+                convert_items(&child_context, children, results)?;
+            }
+            None => convert_request(context, name, item, results)?,
+        }
+    }
+
+    Ok(())
+}
+
+// generates one test per request in a Postman v2.0 or v2.1 collection
+pub fn convert(document: &Value) -> Result<Vec<ImportedTest>, Box<dyn Error + Send + Sync>> {
+    let items = match (document.get("info"), document.get("item")) {
+        (Some(_), Some(items)) => items,
+        _ => {
+            return Err(Box::new(GenericError {
+                reason: "document is not a Postman collection".to_string(),
+            }))
+        }
+    };
+
+    let variables: Vec<(String, Value)> = enabled(document.get("variable"))
+        .into_iter()
+        .map(|v| {
+            (
+                str_field(v, "key").to_string(),
+                v.get("value").cloned().unwrap_or(Value::Null),
+            )
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect();
+
+    let context = Context {
+        tags: Vec::new(),
+        auth: document.get("auth"),
+        variables: &variables,
+    };
+
+    let mut results = Vec::new();
+    convert_items(&context, items, &mut results)?;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn collection() -> Value {
+        json!({
+            "info": {"name": "Pets", "schema": "https://schema.getpostman.com/json/collection/v2.1.0/collection.json"},
+            "auth": {"type": "bearer", "bearer": [{"key": "token", "value": "{{token}}"}]},
+            "variable": [
+                {"key": "baseUrl", "value": "https://api.example.com"},
+                {"key": "token", "value": "secret"},
+                {"key": "unused", "value": "x"}
+            ],
+            "item": [
+                {
+                    "name": "Pet Store",
+                    "item": [{
+                        "name": "Admin",
+                        "item": [{
+                            "name": "Create pet",
+                            "request": {
+                                "method": "POST",
+                                "url": {"raw": "{{baseUrl}}/pets?notify=true&owner={{owner}}"},
+                                "header": [
+                                    {"key": "Content-Type", "value": "application/json"},
+                                    {"key": "X-Debug", "value": "1", "disabled": true}
+                                ],
+                                "body": {"mode": "raw", "raw": "{\"name\": \"{{petName}}\"}"}
+                            },
+                            "response": [{
+                                "code": 201,
+                                "body": "{\"id\": 9, \"name\": \"Rex\", \"createdAt\": \"2024-01-01T00:00:00Z\"}"
+                            }]
+                        }]
+                    }]
+                },
+                {"name": "Remove pet", "request": {"method": "DELETE", "url": "{{baseUrl}}/pets/1"}}
+            ]
+        })
+    }
+
+    #[test]
+    fn folders_become_tags_and_variables_are_converted() {
+        let tests = convert(&collection()).unwrap();
+        assert_eq!(1, tests.len());
+
+        let imported = &tests[0];
+        assert_eq!("Create_pet", imported.name);
+        assert_eq!(Some("Pet-Store Admin".to_string()), imported.file.tags);
+
+        let request = imported.file.request.as_ref().unwrap();
+        assert_eq!("${baseUrl}/pets", request.url);
+
+        let params = request.params.as_ref().unwrap();
+        assert_eq!(
+            ("owner", "${owner}"),
+            (params[1].param.as_str(), params[1].value.as_str())
+        );
+
+        let headers = request.headers.as_ref().unwrap();
+        assert_eq!(2, headers.len());
+        assert_eq!("Bearer ${token}", headers[1].value);
+        assert_eq!(Some(json!({"name": "${petName}"})), request.body);
+
+        let names: Vec<&str> = imported
+            .file
+            .variables
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|v| v.name.as_str())
+            .collect();
+        assert_eq!(vec!["baseUrl", "token"], names);
+    }
+
+    #[test]
+    fn saved_responses_ignore_volatile_fields() {
+        let tests = convert(&collection()).unwrap();
+        let response = tests[0].file.response.as_ref().unwrap();
+
+        assert_eq!(Some(201), response.status);
+        assert_eq!(
+            Some(vec!["createdAt".to_string(), "id".to_string()]),
+            response.ignore
+        );
+    }
+}
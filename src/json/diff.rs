@@ -0,0 +1,216 @@
+use serde_json::Value;
+
+// a single mismatch between two JSON documents. a missing `actual` means the field was
+// expected but not returned, a missing `expected` means the field was not expected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub path: String,
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+}
+
+// how loosely two documents are compared. numbers within `tolerance` of each other are
+// equal, and with `unordered_arrays` array elements may appear in any order
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rules {
+    pub tolerance: f64,
+    pub unordered_arrays: bool,
+}
+
+fn child(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn collect(
+    path: &str,
+    expected: &Value,
+    actual: &Value,
+    rules: &Rules,
+    results: &mut Vec<Difference>,
+) {
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            for (key, value) in e.iter() {
+                match a.get(key) {
+                    Some(a_value) => collect(&child(path, key), value, a_value, rules, results),
+                    None => results.push(Difference {
+                        path: child(path, key),
+                        expected: Some(value.clone()),
+                        actual: None,
+                    }),
+                }
+            }
+
+            for (key, value) in a.iter().filter(|(k, _)| !e.contains_key(*k)) {
+                results.push(Difference {
+                    path: child(path, key),
+                    expected: None,
+                    actual: Some(value.clone()),
+                });
+            }
+        }
+        (Value::Array(e), Value::Array(a)) if rules.unordered_arrays => {
+            unordered(path, e, a, rules, results)
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            for i in 0..e.len().max(a.len()) {
+                let item_path = format!("{}[{}]", path, i);
+                match (e.get(i), a.get(i)) {
+                    (Some(e_value), Some(a_value)) => {
+                        collect(&item_path, e_value, a_value, rules, results)
+                    }
+                    (e_value, a_value) => results.push(Difference {
+                        path: item_path,
+                        expected: e_value.cloned(),
+                        actual: a_value.cloned(),
+                    }),
+                }
+            }
+        }
+        _ if expected == actual => {}
+        (Value::Number(e), Value::Number(a)) if within(e.as_f64(), a.as_f64(), rules.tolerance) => {
+        }
+        _ => results.push(Difference {
+            path: path.to_string(),
+            expected: Some(expected.clone()),
+            actual: Some(actual.clone()),
+        }),
+    }
+}
+
+fn within(expected: Option<f64>, actual: Option<f64>, tolerance: f64) -> bool {
+    match (expected, actual) {
+        (Some(e), Some(a)) => (e - a).abs() <= tolerance,
+        _ => false,
+    }
+}
+
+// pairs each expected element with the first unmatched actual element equal to it. the
+// leftovers are reported at their own positions
+fn unordered(
+    path: &str,
+    expected: &[Value],
+    actual: &[Value],
+    rules: &Rules,
+    results: &mut Vec<Difference>,
+) {
+    let mut matched = vec![false; actual.len()];
+
+    for (i, e_value) in expected.iter().enumerate() {
+        let found = actual.iter().enumerate().position(|(j, a_value)| {
+            !matched[j] && {
+                let mut item_results = Vec::new();
+                collect("", e_value, a_value, rules, &mut item_results);
+                item_results.is_empty()
+            }
+        });
+
+        match found {
+            Some(j) => matched[j] = true,
+            None => results.push(Difference {
+                path: format!("{}[{}]", path, i),
+                expected: Some(e_value.clone()),
+                actual: None,
+            }),
+        }
+    }
+
+    for (j, a_value) in actual.iter().enumerate().filter(|(j, _)| !matched[*j]) {
+        results.push(Difference {
+            path: format!("{}[{}]", path, j),
+            expected: None,
+            actual: Some(a_value.clone()),
+        });
+    }
+}
+
+// lists the paths where `actual` differs from `expected`, e.g. `items[2].name`. an empty
+// path refers to the whole document
+pub fn differences(expected: &Value, actual: &Value, rules: &Rules) -> Vec<Difference> {
+    let mut results = Vec::new();
+    collect("", expected, actual, rules, &mut results);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn differences_report_changed_missing_and_unexpected_paths() {
+        let expected = json!({"id": 1, "items": [{"name": "a"}, {"name": "b"}], "owner": "x"});
+        let actual =
+            json!({"id": 2, "items": [{"name": "a"}, {"name": "c"}, {"name": "d"}], "extra": true});
+
+        assert_eq!(
+            vec![
+                Difference {
+                    path: "id".to_string(),
+                    expected: Some(json!(1)),
+                    actual: Some(json!(2)),
+                },
+                Difference {
+                    path: "items[1].name".to_string(),
+                    expected: Some(json!("b")),
+                    actual: Some(json!("c")),
+                },
+                Difference {
+                    path: "items[2]".to_string(),
+                    expected: None,
+                    actual: Some(json!({"name": "d"})),
+                },
+                Difference {
+                    path: "owner".to_string(),
+                    expected: Some(json!("x")),
+                    actual: None,
+                },
+                Difference {
+                    path: "extra".to_string(),
+                    expected: None,
+                    actual: Some(json!(true)),
+                },
+            ],
+            differences(&expected, &actual, &Rules::default())
+        );
+        assert!(differences(&json!([1, 2]), &json!([1, 2]), &Rules::default()).is_empty());
+    }
+
+    #[test]
+    fn rules_allow_numeric_tolerance_and_unordered_arrays() {
+        let rules = Rules {
+            tolerance: 0.01,
+            unordered_arrays: true,
+        };
+
+        assert!(differences(
+            &json!({"total": 10.0, "items": [{"id": 1}, {"id": 2}]}),
+            &json!({"total": 10.005, "items": [{"id": 2}, {"id": 1}]}),
+            &rules
+        )
+        .is_empty());
+        assert_eq!(
+            vec![
+                Difference {
+                    path: "[1]".to_string(),
+                    expected: Some(json!(2)),
+                    actual: None,
+                },
+                Difference {
+                    path: "[0]".to_string(),
+                    expected: None,
+                    actual: Some(json!(3)),
+                },
+            ],
+            differences(&json!([1, 2]), &json!([3, 1]), &rules)
+        );
+        assert_eq!(
+            2,
+            differences(&json!([1, 2]), &json!([2, 1]), &Rules::default()).len()
+        );
+    }
+}
@@ -1,4 +1,31 @@
 use log::{Level, Log, Metadata, Record};
+use std::sync::Mutex;
+
+// while capturing nothing is printed, warnings and errors are kept so the interactive
+// runner, which owns the terminal, can show them alongside the test that logged them
+static CAPTURED: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+pub fn start_capture() {
+    if let Ok(mut captured) = CAPTURED.lock() {
+        *captured = Some(Vec::new());
+    }
+}
+
+pub fn stop_capture() -> Vec<String> {
+    CAPTURED
+        .lock()
+        .ok()
+        .and_then(|mut c| c.take())
+        .unwrap_or_default()
+}
+
+pub fn take_captured() -> Vec<String> {
+    CAPTURED
+        .lock()
+        .ok()
+        .and_then(|mut c| c.as_mut().map(std::mem::take))
+        .unwrap_or_default()
+}
 
 pub struct SimpleLogger {
     pub level: Level,
@@ -19,6 +46,15 @@ impl Log for SimpleLogger {
             return;
         }
 
+        if let Ok(mut captured) = CAPTURED.lock() {
+            if let Some(lines) = captured.as_mut() {
+                if record.level() <= Level::Warn {
+                    lines.push(record.args().to_string());
+                }
+                return;
+            }
+        }
+
         match record.level() {
             Level::Info => {
                 print!("{}", record.args());
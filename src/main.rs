@@ -1,17 +1,22 @@
 mod config;
 mod errors;
 mod executor;
+mod export;
+mod import;
 mod json;
 mod logger;
 mod machine;
+mod mock;
 mod new;
 mod telemetry;
 mod test;
+mod tui;
 mod updater;
+mod watch;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use glob::{glob_with, MatchOptions};
-use log::{debug, error, info, Level, LevelFilter};
+use log::{debug, error, info, warn, Level, LevelFilter};
 use logger::SimpleLogger;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
@@ -19,6 +24,7 @@ use tokio::fs;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+#[derive(Clone, Copy)]
 pub enum TagMode {
     AND,
     OR,
@@ -48,6 +54,42 @@ pub struct Cli {
     trace: bool,
 }
 
+#[derive(Args, Serialize, Deserialize)]
+pub struct Selection {
+    /// Select tests to run based on tags or tag expressions, e.g. `smoke and not slow`
+    /// {n}By default, tests must match all given tags to be selected
+    #[arg(short, long = "tag", name = "tag", value_parser = executor::select::parse_tag_expression)]
+    tags: Vec<String>,
+
+    /// Toggle tag matching logic to select tests matching any of the given tags
+    #[arg(long, default_value_t = false)]
+    tags_or: bool,
+
+    /// Leave out tests having this tag
+    #[arg(long = "exclude-tag", name = "exclude-tag")]
+    exclude_tags: Vec<String>,
+
+    /// Select tests whose name matches this regular expression
+    #[arg(long, value_parser = executor::select::parse_name_pattern)]
+    name: Option<String>,
+
+    /// Select the test with this id
+    #[arg(long = "id", name = "id")]
+    ids: Vec<String>,
+}
+
+impl Selection {
+    fn filter(&self) -> Result<executor::select::Filter, Box<dyn Error + Send + Sync>> {
+        Ok(executor::select::Filter::new(
+            &self.tags,
+            self.tags_or,
+            &self.exclude_tags,
+            self.name.as_deref(),
+            &self.ids,
+        )?)
+    }
+}
+
 #[derive(Subcommand, Serialize, Deserialize)]
 pub enum Commands {
     /// Execute tests
@@ -61,14 +103,141 @@ pub enum Commands {
         #[arg(short)]
         recursive: bool,
 
-        /// Select tests to run based on tags
-        /// {n}By default, tests must match all given tags to be selected
-        #[arg(short, long = "tag", name = "tag")]
-        tags: Vec<String>,
+        #[command(flatten)]
+        #[serde(flatten)]
+        selection: Selection,
+
+        /// Print a curl command for each request made by a failed stage
+        #[arg(long, default_value_t = false)]
+        show_curl: bool,
+
+        /// Write the actual status and body of each stage into its `response` block
+        #[arg(long, default_value_t = false, conflicts_with = "update_snapshots")]
+        record: bool,
+
+        /// Write the actual status and body into the `response` block of failed stages only
+        #[arg(long, default_value_t = false)]
+        update_snapshots: bool,
+
+        /// A response header to record along with any already in the `response` block
+        #[arg(long = "record-header", name = "record-header")]
+        record_headers: Vec<String>,
+
+        /// Send every request to this base url instead, keeping its path and query
+        /// {n}Useful for running tests against `jk mock`
+        #[arg(long)]
+        base_url: Option<String>,
+
+        /// Keep running, re-running affected tests when test files, variable files or the config change
+        #[arg(short, long, default_value_t = false, conflicts_with_all = ["record", "update_snapshots"])]
+        watch: bool,
+
+        /// Show an interactive view of the run, with stage details and re-running of failed tests
+        #[arg(long, default_value_t = false, conflicts_with = "watch")]
+        tui: bool,
+
+        /// Stop at the first failed test, overriding the `continueOnFailure` setting
+        #[arg(long, default_value_t = false, conflicts_with = "continue_on_failure")]
+        fail_fast: bool,
 
-        /// Toggle tag matching logic to select tests matching any of the given tags
+        /// Keep running after a test fails, overriding the `continueOnFailure` setting
         #[arg(long, default_value_t = false)]
-        tags_or: bool,
+        continue_on_failure: bool,
+
+        /// Print the selected tests, including the tests they require, without running them
+        #[arg(long, default_value_t = false, conflicts_with_all = ["watch", "tui", "record", "update_snapshots"])]
+        list: bool,
+    },
+
+    /// Run tests against a baseline and a candidate environment, reporting endpoints whose responses differ
+    /// {n}Environments are base urls, or names from the `environments` table of the config
+    Compare {
+        /// The path(s) to search for test files
+        /// {n}By default, the current path is used
+        #[arg(name = "path")]
+        paths: Vec<String>,
+
+        /// Recursively search for test files
+        #[arg(short)]
+        recursive: bool,
+
+        #[command(flatten)]
+        #[serde(flatten)]
+        selection: Selection,
+
+        /// The environment whose responses are expected
+        #[arg(long)]
+        baseline: String,
+
+        /// The environment being verified against the baseline
+        #[arg(long)]
+        candidate: String,
+
+        /// A body path to leave out of every comparison, along with each stage's `ignore` list
+        #[arg(long = "ignore", name = "ignore")]
+        ignore: Vec<String>,
+
+        /// A response header to compare, by default every header except `Date` is compared
+        #[arg(long = "header", name = "header")]
+        headers: Vec<String>,
+
+        /// A response header to leave out of the comparison
+        #[arg(long = "ignore-header", name = "ignore-header")]
+        ignore_headers: Vec<String>,
+    },
+
+    /// Replay tests with many workers for a while, reporting latency, throughput, errors and statuses per stage
+    /// {n}Exits with an error when a result is past one of the given thresholds
+    Load {
+        /// The path(s) to search for test files
+        /// {n}By default, the current path is used
+        #[arg(name = "path")]
+        paths: Vec<String>,
+
+        /// Recursively search for test files
+        #[arg(short)]
+        recursive: bool,
+
+        #[command(flatten)]
+        #[serde(flatten)]
+        selection: Selection,
+
+        /// The number of workers running the tests at the same time
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        concurrency: u32,
+
+        /// The most requests per second to send across every worker
+        /// {n}By default, requests are sent as fast as the workers can make them
+        #[arg(long, value_parser = executor::load::parse_rate)]
+        rate: Option<f64>,
+
+        /// How long to keep the tests running, e.g. 30s, 5m or 500ms
+        #[arg(short, long, default_value = "10s", value_parser = executor::load::parse_duration)]
+        duration: std::time::Duration,
+
+        /// Send every request to this base url instead, keeping its path and query
+        #[arg(long)]
+        base_url: Option<String>,
+
+        /// The highest median latency, in milliseconds, allowed for any stage
+        #[arg(long)]
+        max_p50: Option<u32>,
+
+        /// The highest 90th percentile latency, in milliseconds, allowed for any stage
+        #[arg(long)]
+        max_p90: Option<u32>,
+
+        /// The highest 99th percentile latency, in milliseconds, allowed for any stage
+        #[arg(long)]
+        max_p99: Option<u32>,
+
+        /// The highest percentage of failed requests allowed for any stage
+        #[arg(long)]
+        max_error_rate: Option<f64>,
+
+        /// The fewest requests per second the whole run must reach
+        #[arg(long)]
+        min_throughput: Option<f64>,
     },
 
     /// Process tests without calling API endpoints
@@ -83,14 +252,59 @@ pub enum Commands {
         #[arg(short)]
         recursive: bool,
 
-        /// Select tests to run based on tags
-        /// {n}By default, tests must match all given tags to be selected
-        #[arg(short, long = "tag", name = "tag")]
-        tags: Vec<String>,
+        #[command(flatten)]
+        #[serde(flatten)]
+        selection: Selection,
+    },
 
-        /// Toggle tag matching logic to select tests matching any of the given tags
-        #[arg(long, default_value_t = false)]
-        tags_or: bool,
+    /// Validate tests without calling API endpoints
+    /// {n}Reports problems with their file, line and column, exiting with an error if any are found
+    Validate {
+        /// The path(s) to search for test files
+        /// {n}By default, the current path is used
+        #[arg(name = "path")]
+        paths: Vec<String>,
+
+        /// Recursively search for test files
+        #[arg(short)]
+        recursive: bool,
+    },
+
+    /// Print the requests a test makes as commands that can be replayed by hand
+    /// {n}Variables extracted from responses at runtime are left as `${name}`
+    Export {
+        /// The output format
+        #[arg(value_enum)]
+        format: export::Format,
+
+        /// The test file(s) to export
+        #[arg(name = "file", required = true)]
+        files: Vec<String>,
+
+        /// The iteration to resolve variables for
+        #[arg(short, long, default_value_t = 1)]
+        iteration: u32,
+    },
+
+    /// Serve the recorded responses in test files from a local stand-in server
+    /// {n}Requests are matched on method, path, query and body
+    Mock {
+        /// The path(s) to search for test files
+        /// {n}By default, the current path is used
+        #[arg(name = "path")]
+        paths: Vec<String>,
+
+        /// Recursively search for test files
+        #[arg(short)]
+        recursive: bool,
+
+        /// A HAR file whose captured responses should also be served
+        #[arg(long = "cassette", name = "cassette")]
+        cassettes: Vec<String>,
+
+        /// The address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        address: std::net::SocketAddr,
     },
 
     /// Create a new test
@@ -111,34 +325,99 @@ pub enum Commands {
         output: bool,
     },
 
+    /// Generate tests from an API description
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+
+    /// Print the JSON Schema for test files
+    /// {n}Editors, like those using the YAML language server, can use it for autocompletion and inline errors
+    Schema,
+
     /// Update Jikken, if a newer version exists
     Update,
 }
 
-fn glob_walk(glob_string: &String) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+#[derive(Subcommand, Serialize, Deserialize)]
+pub enum ImportSource {
+    /// Generate a test for each operation in an OpenAPI 3 or Swagger 2 specification
+    Openapi {
+        /// The specification file, in YAML or JSON
+        spec: String,
+
+        /// The directory to save generated tests in
+        /// {n}By default, the current path is used
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Generate a test for each request in a Postman collection
+    /// {n}Folders become tags and collection variables become test variables
+    Postman {
+        /// The exported collection file (v2.0 or v2.1)
+        collection: String,
+
+        /// The directory to save generated tests in
+        /// {n}By default, the current path is used
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Generate a test for each API request captured in a HAR file
+    Har {
+        /// The HAR file
+        file: String,
+
+        /// The directory to save generated tests in
+        /// {n}By default, the current path is used
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+async fn import_tests(source: ImportSource) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match source {
+        ImportSource::Openapi { spec, output } => {
+            let document = import::load_document(&spec)?;
+            let tests = import::openapi::convert(&document)?;
+            import::write_tests(tests, output, &spec).await
+        }
+        ImportSource::Postman { collection, output } => {
+            let document = import::load_document(&collection)?;
+            let tests = import::postman::convert(&document)?;
+            import::write_tests(tests, output, &collection).await
+        }
+        ImportSource::Har { file, output } => {
+            let document = import::load_document(&file)?;
+            let tests = import::har::convert(&document)?;
+            import::write_tests(tests, output, &file).await
+        }
+    }
+}
+
+fn glob_walk(glob_string: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
     let mut ret: Vec<String> = Vec::new();
 
-    for entry in glob_with(glob_string.as_str(), MatchOptions::default()).unwrap() {
-        if let Ok(path) = entry {
-            match path.to_str() {
-                Some(s) => {
-                    if s.ends_with(".jkt") {
-                        ret.push(String::from(s))
-                    }
-                }
-                None => {}
+    for path in glob_with(glob_string, MatchOptions::default())
+        .unwrap()
+        .flatten()
+    {
+        if let Some(s) = path.to_str() {
+            if s.ends_with(".jkt") {
+                ret.push(String::from(s))
             }
         }
     }
 
-    return Ok(ret);
+    Ok(ret)
 }
 
 fn satisfies_potential_glob_filter(glob_pattern: &Option<glob::Pattern>, file_name: &str) -> bool {
-    return match &glob_pattern {
+    match &glob_pattern {
         Some(p) => p.matches_with(file_name, MatchOptions::default()),
         None => true,
-    };
+    }
 }
 
 // Consider how to approach feedback to user when supplied pattern
@@ -151,17 +430,17 @@ fn create_top_level_filter(glob_pattern: &Option<String>) -> impl Fn(&walkdir::D
             .unwrap_or(None)
     };
     let pattern = extract_pattern(glob_pattern);
-    return move |e: &walkdir::DirEntry| -> bool {
+    move |e: &walkdir::DirEntry| -> bool {
         e.file_name()
             .to_str()
             .map(|s| {
                 (e.file_type().is_file()
                     && s.ends_with(".jkt")
-                    && satisfies_potential_glob_filter(&pattern, &s))
+                    && satisfies_potential_glob_filter(&pattern, s))
                     || e.file_type().is_dir()
             })
             .unwrap_or(false)
-    };
+    }
 }
 
 async fn search_directory(
@@ -172,18 +451,19 @@ async fn search_directory(
     let mut ret: Vec<String> = Vec::new();
     let entry_is_file = |e: &walkdir::DirEntry| e.metadata().map(|e| e.is_file()).unwrap_or(false);
 
-    walkdir::WalkDir::new(&path)
-        .max_depth(if recursive { ::std::usize::MAX } else { 1 })
+    walkdir::WalkDir::new(path)
+        .max_depth(if recursive { usize::MAX } else { 1 })
         .into_iter()
         .filter_entry(create_top_level_filter(&glob_pattern))
         .filter_map(|e| e.ok())
         .filter(entry_is_file)
-        .for_each(|e| match e.path().to_str() {
-            Some(s) => ret.push(String::from(s)),
-            None => {}
+        .for_each(|e| {
+            if let Some(s) = e.path().to_str() {
+                ret.push(String::from(s))
+            }
         });
 
-    return Ok(ret);
+    Ok(ret)
 }
 
 async fn get_files(
@@ -204,7 +484,7 @@ async fn get_files(
         if is_file {
             results.push(path);
         } else if !exists && !recursive {
-            results.append(glob_walk(&path).unwrap_or(Vec::new()).as_mut());
+            results.append(glob_walk(&path).unwrap_or_default().as_mut());
         } else {
             results.append(
                 search_directory(
@@ -219,6 +499,9 @@ async fn get_files(
         }
     }
 
+    // suite files hold shared setup and teardown rather than tests
+    results.retain(|r| !executor::suite::is_suite_file(r));
+
     for r in results.clone() {
         debug!("file: {}", r);
     }
@@ -228,20 +511,19 @@ async fn get_files(
 
 async fn run_tests(
     paths: Vec<String>,
-    tags: Vec<String>,
-    tags_or: bool,
+    filter: executor::select::Filter,
     dryrun_mode: bool,
     recursive: bool,
     cli_args: Box<serde_json::Value>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+    options: executor::RunOptions,
+) -> Result<executor::Report, Box<dyn Error + Send + Sync>> {
     let mut cli_paths = paths;
 
     if cli_paths.is_empty() {
         cli_paths.push(".".to_string())
     }
 
-    let cli_tag_mode = if tags_or { TagMode::OR } else { TagMode::AND };
-    let config = config::get_config().await;
+    let config = config::try_get_config().await?;
     let files = get_files(cli_paths, recursive).await?;
     let test_plurality = if files.len() != 1 { "s" } else { "" };
 
@@ -252,14 +534,281 @@ async fn run_tests(
     );
 
     let report =
-        executor::execute_tests(config, files, dryrun_mode, tags, cli_tag_mode, cli_args).await;
+        executor::execute_tests(config, files, dryrun_mode, filter, cli_args, options).await;
+
+    print_summary(&report);
+
+    Ok(report)
+}
+
+async fn compare_tests(
+    paths: Vec<String>,
+    recursive: bool,
+    filter: executor::select::Filter,
+    baseline: &str,
+    candidate: &str,
+    rules: executor::shadow::Rules,
+) -> Result<executor::shadow::Report, Box<dyn Error + Send + Sync>> {
+    let paths = if paths.is_empty() {
+        vec![".".to_string()]
+    } else {
+        paths
+    };
+    let config = config::try_get_config().await?;
+    let baseline_url = executor::shadow::base_url(&config, baseline)?;
+    let candidate_url = executor::shadow::base_url(&config, candidate)?;
+    let files = get_files(paths, recursive).await?;
 
     info!(
-        "Jikken executed {} test{} with {} passed and {} failed.\n",
-        report.run, test_plurality, report.passed, report.failed
+        "Jikken found {} test file{}, comparing {} with {}.\n",
+        files.len(),
+        if files.len() != 1 { "s" } else { "" },
+        candidate_url,
+        baseline_url
     );
 
-    Ok(())
+    let report = executor::shadow::compare_tests(
+        config,
+        files,
+        filter,
+        &baseline_url,
+        &candidate_url,
+        rules,
+    )
+    .await;
+
+    info!(
+        "Jikken compared {} test{} with {} matched, {} diverged and {} errored.\n",
+        report.compared,
+        if report.compared != 1 { "s" } else { "" },
+        report.matched,
+        report.diverged,
+        report.errored + report.invalid
+    );
+
+    if report.interrupted {
+        info!("Jikken was interrupted before every test ran.\n");
+    }
+
+    Ok(report)
+}
+
+async fn load_tests(
+    paths: Vec<String>,
+    recursive: bool,
+    filter: executor::select::Filter,
+    base_url: Option<String>,
+    options: executor::load::Options,
+) -> Result<executor::load::Report, Box<dyn Error + Send + Sync>> {
+    let paths = if paths.is_empty() {
+        vec![".".to_string()]
+    } else {
+        paths
+    };
+    let config = config::try_get_config().await?;
+    let files = get_files(paths, recursive).await?;
+
+    info!(
+        "Jikken found {} test file{}.\n",
+        files.len(),
+        if files.len() != 1 { "s" } else { "" }
+    );
+
+    let report = executor::load::load_tests(config, files, filter, base_url, options).await;
+
+    info!(
+        "Jikken sent {} request{} with {} error{} and {} threshold{} breached.\n",
+        report.requests,
+        if report.requests != 1 { "s" } else { "" },
+        report.errors,
+        if report.errors != 1 { "s" } else { "" },
+        report.breaches,
+        if report.breaches != 1 { "s" } else { "" }
+    );
+
+    if report.interrupted {
+        info!("Jikken was interrupted before the load finished.\n");
+    }
+
+    Ok(report)
+}
+
+// prints the tests a run would select, in execution order. returns false when any test file
+// couldn't be loaded or failed validation
+async fn list_tests(
+    paths: Vec<String>,
+    recursive: bool,
+    filter: &executor::select::Filter,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let paths = if paths.is_empty() {
+        vec![".".to_string()]
+    } else {
+        paths
+    };
+    let config = config::try_get_config().await?;
+    let files = get_files(paths, recursive).await?;
+    let (tests, invalid) =
+        executor::plan_tests(&files, &config.generate_global_variables(), filter);
+
+    for (i, td) in tests.iter().enumerate() {
+        let tags = if td.tags.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", td.tags.join(", "))
+        };
+
+        info!(
+            "{}) `{}` ({}){}\n",
+            i + 1,
+            td.name.clone().unwrap_or(format!("Test {}", i + 1)),
+            td.filename,
+            tags
+        );
+    }
+
+    let test_plurality = if tests.len() != 1 { "s" } else { "" };
+    info!("Jikken selected {} test{}.\n", tests.len(), test_plurality);
+
+    Ok(invalid == 0)
+}
+
+// errors that stop a run altogether, such as an unreadable config file, exit as runtime errors
+fn exit_with_report(result: Result<executor::Report, Box<dyn Error + Send + Sync>>) -> ! {
+    match result {
+        Ok(report) => std::process::exit(report.exit_code()),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(executor::EXIT_ERROR);
+        }
+    }
+}
+
+fn print_summary(report: &executor::Report) {
+    let mut counts = vec![
+        format!("{} passed", report.passed),
+        format!("{} failed", report.failed),
+        format!("{} skipped", report.skipped),
+        format!("{} errored", report.errored + report.invalid),
+    ];
+
+    if report.xfailed > 0 || report.xpassed > 0 {
+        counts.push(format!("{} xfailed", report.xfailed));
+        counts.push(format!("{} xpassed", report.xpassed));
+    }
+
+    let last = counts.pop().unwrap_or_default();
+    info!(
+        "Jikken executed {} test{} with {} and {}.\n",
+        report.run,
+        if report.run != 1 { "s" } else { "" },
+        counts.join(", "),
+        last
+    );
+
+    if report.interrupted {
+        info!("Jikken was interrupted before every test ran.\n");
+    }
+}
+
+async fn export_tests(
+    format: export::Format,
+    files: Vec<String>,
+    iteration: u32,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let config = config::get_config().await;
+    let global_variables = config.generate_global_variables();
+    let mut success = true;
+
+    for filename in files.iter() {
+        let td = match test::file::load(filename).and_then(|f| {
+            test::validation::validate_file(f, &global_variables).map_err(|e| e.into())
+        }) {
+            Ok(td) => td,
+            Err(e) => {
+                error!("unable to export test file ({}): {}", filename, e);
+                success = false;
+                continue;
+            }
+        };
+
+        if iteration == 0 || iteration > td.iterate {
+            error!(
+                "test file ({}) has {} iteration{}, unable to export iteration {}",
+                filename,
+                td.iterate,
+                if td.iterate != 1 { "s" } else { "" },
+                iteration
+            );
+            success = false;
+            continue;
+        }
+
+        for stage in td.resolve_requests(iteration - 1) {
+            info!(
+                "# {} `{}` {}\n{}\n\n",
+                filename,
+                td.name.clone().unwrap_or_default(),
+                stage.label,
+                match stage.grpc {
+                    Some(method) => export::grpcurl(method, &stage.request),
+                    None => export::render(format, &stage.request),
+                }
+            );
+        }
+    }
+
+    Ok(success)
+}
+
+async fn validate_tests(
+    paths: Vec<String>,
+    recursive: bool,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let mut cli_paths = paths;
+
+    if cli_paths.is_empty() {
+        cli_paths.push(".".to_string())
+    }
+
+    let config = config::get_config().await;
+    let files = get_files(cli_paths, recursive).await?;
+    let file_plurality = if files.len() != 1 { "s" } else { "" };
+
+    info!(
+        "Jikken found {} test file{}.\n",
+        files.len(),
+        file_plurality
+    );
+
+    let diagnostics =
+        test::validation::validate_sources(&files, &config.generate_global_variables());
+    let mut error_count = 0;
+    let mut warning_count = 0;
+
+    for diagnostic in diagnostics.iter() {
+        match diagnostic.severity {
+            test::validation::Severity::Error => {
+                error_count += 1;
+                error!("{}", diagnostic);
+            }
+            test::validation::Severity::Warning => {
+                warning_count += 1;
+                warn!("{}", diagnostic);
+            }
+        }
+    }
+
+    info!(
+        "Jikken validated {} test file{} with {} error{} and {} warning{}.\n",
+        files.len(),
+        file_plurality,
+        error_count,
+        if error_count != 1 { "s" } else { "" },
+        warning_count,
+        if warning_count != 1 { "s" } else { "" },
+    );
+
+    Ok(error_count == 0)
 }
 
 #[tokio::main]
@@ -290,8 +839,18 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     match cli.command {
         Commands::Update => {
             updater::try_updating().await;
-            std::process::exit(0);
+            std::process::exit(executor::EXIT_PASSED);
         }
+        Commands::Schema => match test::schema::generate() {
+            Ok(schema) => {
+                info!("{}\n", schema);
+                std::process::exit(executor::EXIT_PASSED);
+            }
+            Err(e) => {
+                error!("unable to generate schema: {}", e);
+                std::process::exit(executor::EXIT_ERROR);
+            }
+        },
         Commands::New {
             full,
             multistage,
@@ -302,38 +861,220 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             let created = new::create_test_template(full, multistage, output, name).await;
             match created {
                 Ok(_) => {
-                    std::process::exit(0);
+                    std::process::exit(executor::EXIT_PASSED);
                 }
                 Err(_) => {
-                    std::process::exit(1);
+                    std::process::exit(executor::EXIT_ERROR);
                 }
             }
         }
-        Commands::DryRun {
-            tags,
-            tags_or,
+        Commands::Import { source } => {
+            updater::check_for_updates().await;
+            match import_tests(source).await {
+                Ok(_) => std::process::exit(executor::EXIT_PASSED),
+                Err(e) => {
+                    error!("unable to import tests: {}", e);
+                    std::process::exit(executor::EXIT_INVALID);
+                }
+            }
+        }
+        Commands::Export {
+            format,
+            files,
+            iteration,
+        } => {
+            let exported = export_tests(format, files, iteration).await?;
+            std::process::exit(if exported {
+                executor::EXIT_PASSED
+            } else {
+                executor::EXIT_INVALID
+            });
+        }
+        Commands::Mock {
+            paths,
+            recursive,
+            cassettes,
+            address,
+        } => {
+            let files = if paths.is_empty() && !cassettes.is_empty() {
+                Vec::new()
+            } else {
+                get_files(
+                    if paths.is_empty() {
+                        vec![".".to_string()]
+                    } else {
+                        paths
+                    },
+                    recursive,
+                )
+                .await?
+            };
+
+            if let Err(e) = mock::run_mock_server(files, cassettes, address).await {
+                error!("unable to run mock server: {}", e);
+                std::process::exit(executor::EXIT_ERROR);
+            }
+        }
+        Commands::Validate { recursive, paths } => {
+            updater::check_for_updates().await;
+            let valid = validate_tests(paths, recursive).await?;
+            std::process::exit(if valid {
+                executor::EXIT_PASSED
+            } else {
+                executor::EXIT_INVALID
+            });
+        }
+        Commands::Compare {
+            selection,
             recursive,
             paths,
+            baseline,
+            candidate,
+            ignore,
+            headers,
+            ignore_headers,
         } => {
             updater::check_for_updates().await;
-            run_tests(
+            let rules = executor::shadow::Rules {
+                ignore,
+                headers,
+                ignore_headers,
+            };
+
+            match compare_tests(
                 paths,
-                tags,
-                tags_or,
-                true,
                 recursive,
-                Box::new(serde_json::Value::Null),
+                selection.filter()?,
+                &baseline,
+                &candidate,
+                rules,
             )
-            .await?;
+            .await
+            {
+                Ok(report) => std::process::exit(report.exit_code()),
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(executor::EXIT_ERROR);
+                }
+            }
+        }
+        Commands::Load {
+            selection,
+            recursive,
+            paths,
+            concurrency,
+            rate,
+            duration,
+            base_url,
+            max_p50,
+            max_p90,
+            max_p99,
+            max_error_rate,
+            min_throughput,
+        } => {
+            updater::check_for_updates().await;
+            let options = executor::load::Options {
+                concurrency,
+                rate,
+                duration,
+                thresholds: executor::load::Thresholds {
+                    p50: max_p50,
+                    p90: max_p90,
+                    p99: max_p99,
+                    error_rate: max_error_rate,
+                    throughput: min_throughput,
+                },
+            };
+
+            match load_tests(paths, recursive, selection.filter()?, base_url, options).await {
+                Ok(report) => std::process::exit(report.exit_code()),
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(executor::EXIT_ERROR);
+                }
+            }
+        }
+        Commands::DryRun {
+            selection,
+            recursive,
+            paths,
+        } => {
+            updater::check_for_updates().await;
+            exit_with_report(
+                run_tests(
+                    paths,
+                    selection.filter()?,
+                    true,
+                    recursive,
+                    Box::new(serde_json::Value::Null),
+                    executor::RunOptions::default(),
+                )
+                .await,
+            );
         }
         Commands::Run {
-            tags,
-            tags_or,
+            selection,
             recursive,
             paths,
+            show_curl,
+            record,
+            update_snapshots,
+            record_headers,
+            base_url,
+            watch,
+            tui,
+            fail_fast,
+            continue_on_failure,
+            list,
         } => {
             updater::check_for_updates().await;
-            run_tests(paths, tags, tags_or, false, recursive, cli_args).await?;
+            let filter = selection.filter()?;
+
+            if list {
+                let listed = list_tests(paths, recursive, &filter).await?;
+                std::process::exit(if listed {
+                    executor::EXIT_PASSED
+                } else {
+                    executor::EXIT_INVALID
+                });
+            }
+
+            let options = executor::RunOptions {
+                show_curl,
+                record: if record {
+                    Some(executor::record::Mode::All)
+                } else if update_snapshots {
+                    Some(executor::record::Mode::Failed)
+                } else {
+                    None
+                },
+                record_headers,
+                base_url,
+                progress: None,
+                continue_on_failure: if fail_fast {
+                    Some(false)
+                } else if continue_on_failure {
+                    Some(true)
+                } else {
+                    None
+                },
+            };
+
+            if watch {
+                watch::watch_tests(paths, filter, recursive, cli_args, options).await?;
+            } else if tui {
+                let paths = if paths.is_empty() {
+                    vec![".".to_string()]
+                } else {
+                    paths
+                };
+                let files = get_files(paths, recursive).await?;
+                exit_with_report(tui::run_tui(files, filter, cli_args, options).await);
+            } else {
+                exit_with_report(
+                    run_tests(paths, filter, false, recursive, cli_args, options).await,
+                );
+            }
         }
     }
 
@@ -356,7 +1097,7 @@ mod tests {
         let tmp_path_str = tmp_path.to_str().unwrap();
         {
             //Begin Scope
-            let _: Vec<std::fs::File> = vec![
+            let _: Vec<std::fs::File> = [
                 "random_file",
                 "my_test.jkt",
                 "something_else",
@@ -378,7 +1119,7 @@ mod tests {
         let tmp_path_str = tmp_path.to_str().unwrap();
         {
             //Begin Scope
-            let _: Vec<std::fs::File> = vec![
+            let _: Vec<std::fs::File> = [
                 "random_file",
                 "my_test.jkt",
                 "something_else",
@@ -402,7 +1143,7 @@ mod tests {
 
         {
             //Begin Scope
-            let _: Vec<std::fs::File> = vec![
+            let _: Vec<std::fs::File> = [
                 "random_file",
                 "my_test.jkt",
                 "something_else",
@@ -425,7 +1166,7 @@ mod tests {
         _ = std::env::set_current_dir(tmp_path);
         {
             //Begin Scope
-            let _: Vec<std::fs::File> = vec![
+            let _: Vec<std::fs::File> = [
                 "random_file",
                 "my_test.jkt",
                 "something_else",
@@ -0,0 +1,485 @@
+use crate::config;
+use crate::import;
+use crate::test;
+use crate::test::definition::ResolvedRequest;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{body, Body, Request, Response, Server};
+use log::{error, info, warn};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+
+// a recorded request and the response to serve for it. unresolved `${name}` variables in
+// the path, query or body match any value
+#[derive(Debug, Clone)]
+pub struct Route {
+    method: String,
+    path: Vec<String>,
+    query: Vec<(String, String)>,
+    body: Option<Value>,
+    status: u16,
+    headers: Vec<(String, String)>,
+    response_body: Option<String>,
+    source: String,
+}
+
+fn is_wildcard(value: &str) -> bool {
+    value.contains("${")
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// paths are compared decoded, url parsing encodes the braces of `${name}` variables
+fn segments(path: &str) -> Vec<String> {
+    path.trim_matches('/')
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect()
+}
+
+// urls whose origin is still a variable, such as `${baseUrl}/pets`, are matched on the rest
+fn split_url(url: &str) -> Option<(String, Vec<(String, String)>)> {
+    match url::Url::parse(url) {
+        Ok(u) => Some((
+            u.path().to_string(),
+            u.query_pairs()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )),
+        Err(_) if url.starts_with("${") => {
+            let rest = &url[url.find('}')? + 1..];
+            let parsed = url::Url::parse(&format!("http://localhost{}", rest)).ok()?;
+            split_url(parsed.as_str())
+        }
+        Err(_) => None,
+    }
+}
+
+fn json_matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::String(e), _) if is_wildcard(e) => true,
+        (Value::Object(e), Value::Object(a)) => {
+            e.len() == a.len()
+                && e.iter()
+                    .all(|(k, v)| a.get(k).map(|av| json_matches(v, av)).unwrap_or(false))
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            e.len() == a.len() && e.iter().zip(a.iter()).all(|(ev, av)| json_matches(ev, av))
+        }
+        _ => expected == actual,
+    }
+}
+
+impl Route {
+    fn new(
+        source: &str,
+        request: &ResolvedRequest,
+        status: u16,
+        headers: Vec<(String, String)>,
+        response_body: Option<String>,
+    ) -> Option<Route> {
+        let (path, query) = split_url(&request.url)?;
+
+        Some(Route {
+            method: request.method.to_string(),
+            path: segments(&path),
+            query,
+            body: request.body.clone(),
+            status,
+            headers,
+            response_body,
+            source: source.to_string(),
+        })
+    }
+
+    // literal path segments, query parameters and bodies make a route more specific
+    fn specificity(&self) -> (usize, usize, bool) {
+        (
+            self.path.iter().filter(|s| !is_wildcard(s)).count(),
+            self.query.len(),
+            self.body.is_some(),
+        )
+    }
+
+    fn matches(
+        &self,
+        method: &str,
+        path: &[String],
+        query: &[(String, String)],
+        body: &Option<Value>,
+    ) -> bool {
+        let path_match = self.path.len() == path.len()
+            && self
+                .path
+                .iter()
+                .zip(path.iter())
+                .all(|(r, p)| is_wildcard(r) || r == p);
+
+        let query_match = self.query.iter().all(|(k, v)| {
+            query
+                .iter()
+                .any(|(qk, qv)| qk == k && (is_wildcard(v) || qv == v))
+        });
+
+        let body_match = match (&self.body, body) {
+            (None, _) => true,
+            (Some(e), Some(a)) => json_matches(e, a),
+            (Some(_), None) => false,
+        };
+
+        self.method.eq_ignore_ascii_case(method) && path_match && query_match && body_match
+    }
+}
+
+// builds routes from the request and response pairs of test files
+pub fn routes_from_tests(files: &[String], global_variables: &[test::Variable]) -> Vec<Route> {
+    let mut routes = Vec::new();
+
+    for filename in files.iter() {
+        let td = match test::file::load(filename).and_then(|f| {
+            test::validation::validate_file(f, global_variables).map_err(|e| e.into())
+        }) {
+            Ok(td) => td,
+            Err(e) => {
+                error!("unable to load test file ({}): {}", filename, e);
+                continue;
+            }
+        };
+
+        for stage in td.resolve_requests(0) {
+            let response = match stage.response {
+                Some(r) => r,
+                None => continue,
+            };
+
+            if stage.grpc.is_some() {
+                warn!(
+                    "skipping {} ({}), grpc calls aren't mocked",
+                    filename, stage.label
+                );
+                continue;
+            }
+
+            let mut headers: Vec<(String, String)> = response
+                .headers
+                .iter()
+                .map(|h| (h.header.clone(), h.value.clone()))
+                .collect();
+            let body = response
+                .body
+                .as_ref()
+                .map(|b| {
+                    if stage.graphql {
+                        serde_json::json!({ "data": b.data })
+                    } else {
+                        b.data.clone()
+                    }
+                })
+                .and_then(|b| serde_json::to_string(&b).ok());
+
+            if body.is_some()
+                && !headers
+                    .iter()
+                    .any(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+            {
+                headers.push(("Content-Type".to_string(), "application/json".to_string()));
+            }
+
+            let source = format!("{} ({})", filename, stage.label);
+            match Route::new(
+                &source,
+                &stage.request,
+                response.status.unwrap_or(200),
+                headers,
+                body,
+            ) {
+                Some(route) => routes.push(route),
+                None => warn!("skipping {}, the url could not be parsed", source),
+            }
+        }
+    }
+
+    routes
+}
+
+// builds routes from the entries of a HAR file, serving the captured responses as recorded
+pub fn routes_from_cassette(filename: &str) -> Result<Vec<Route>, Box<dyn Error + Send + Sync>> {
+    let document = import::load_document(filename)?;
+    let entries = match document.get("log").and_then(|l| l.get("entries")) {
+        Some(Value::Array(entries)) => entries,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut routes = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let request = entry.get("request").unwrap_or(&Value::Null);
+        let response = entry.get("response").unwrap_or(&Value::Null);
+        let content = response.get("content").unwrap_or(&Value::Null);
+
+        let method = match hyper::Method::from_bytes(
+            import::str_field(request, "method")
+                .to_uppercase()
+                .as_bytes(),
+        ) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+
+        let body = request
+            .get("postData")
+            .map(|p| import::str_field(p, "text"))
+            .and_then(|t| serde_json::from_str(t).ok());
+
+        let resolved = ResolvedRequest {
+            url: import::str_field(request, "url").to_string(),
+            method: test::http::Method::from_hyper(method),
+            headers: Vec::new(),
+            body,
+        };
+
+        let mime_type = import::str_field(content, "mimeType");
+        let headers = if mime_type.is_empty() {
+            Vec::new()
+        } else {
+            vec![("Content-Type".to_string(), mime_type.to_string())]
+        };
+
+        // binary content is recorded base64 encoded and isn't replayed
+        let response_body = match content.get("encoding") {
+            Some(_) => None,
+            None => content
+                .get("text")
+                .and_then(|t| t.as_str())
+                .map(|t| t.to_string()),
+        };
+
+        let status = response.get("status").and_then(|s| s.as_u64()).unwrap_or(0) as u16;
+        if status == 0 {
+            continue;
+        }
+
+        let source = format!("{} (entry {})", filename, index + 1);
+        if let Some(route) = Route::new(&source, &resolved, status, headers, response_body) {
+            routes.push(route);
+        }
+    }
+
+    Ok(routes)
+}
+
+fn find_route<'a>(
+    routes: &'a [Route],
+    method: &str,
+    path: &str,
+    query: &[(String, String)],
+    body: &Option<Value>,
+) -> Option<&'a Route> {
+    let path = segments(path);
+    routes
+        .iter()
+        .filter(|r| r.matches(method, &path, query, body))
+        .max_by_key(|r| r.specificity())
+}
+
+async fn handle(routes: Arc<Vec<Route>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let query: Vec<(String, String)> =
+        url::form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+    let bytes = body::to_bytes(req.into_body()).await.unwrap_or_default();
+    let body: Option<Value> = serde_json::from_slice(&bytes).ok();
+
+    let response = match find_route(&routes, &method, &path, &query, &body) {
+        Some(route) => {
+            info!(
+                "{} {} -> {} from {}\n",
+                method, path, route.status, route.source
+            );
+
+            let mut builder = Response::builder().status(route.status);
+            for (name, value) in route.headers.iter() {
+                builder = builder.header(name, value);
+            }
+
+            builder.body(Body::from(route.response_body.clone().unwrap_or_default()))
+        }
+        None => {
+            warn!("{} {} -> no recorded response matches", method, path);
+            let message = serde_json::json!({
+                "error": format!("no recorded response matches {} {}", method, path)
+            });
+
+            Response::builder()
+                .status(404)
+                .header("Content-Type", "application/json")
+                .body(Body::from(message.to_string()))
+        }
+    };
+
+    Ok(response.unwrap_or_else(|e| {
+        let mut r = Response::new(Body::from(e.to_string()));
+        *r.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+        r
+    }))
+}
+
+// serves on a listener that's already bound, so the address is known before serving starts
+pub async fn serve(
+    routes: Vec<Route>,
+    listener: TcpListener,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let route_count = routes.len();
+    let routes = Arc::new(routes);
+
+    let make_service = make_service_fn(move |_| {
+        let routes = routes.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(routes.clone(), req))) }
+    });
+
+    let server = Server::from_tcp(listener)?.serve(make_service);
+
+    info!(
+        "Jikken is serving {} recorded response{} at http://{}\n",
+        route_count,
+        if route_count != 1 { "s" } else { "" },
+        server.local_addr()
+    );
+
+    server
+        .with_graceful_shutdown(async {
+            _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+
+    Ok(())
+}
+
+pub async fn run_mock_server(
+    files: Vec<String>,
+    cassettes: Vec<String>,
+    address: SocketAddr,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let config = config::get_config().await;
+    let mut routes = routes_from_tests(&files, &config.generate_global_variables());
+
+    for cassette in cassettes.iter() {
+        routes.append(&mut routes_from_cassette(cassette)?);
+    }
+
+    serve(routes, TcpListener::bind(address)?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::http::Verb;
+    use serde_json::json;
+
+    fn route(url: &str, verb: Verb, body: Option<Value>, status: u16) -> Route {
+        let request = ResolvedRequest::new(url.to_string(), verb.as_method(), Vec::new(), body);
+        Route::new("test", &request, status, Vec::new(), None).unwrap()
+    }
+
+    fn query(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn routes_match_method_path_query_and_body() {
+        let routes = vec![
+            route("https://api.jikken.io/v1/pets/${id}", Verb::Get, None, 200),
+            route("${baseUrl}/v1/pets/7?full=true", Verb::Get, None, 201),
+            route(
+                "https://api.jikken.io/v1/pets",
+                Verb::Post,
+                Some(json!({"name": "${name}", "age": 2})),
+                202,
+            ),
+        ];
+
+        let status = |method: &str, path: &str, q: &[(&str, &str)], body: Option<Value>| {
+            find_route(&routes, method, path, &query(q), &body).map(|r| r.status)
+        };
+
+        assert_eq!(Some(200), status("GET", "/v1/pets/7", &[], None));
+        assert_eq!(
+            Some(201),
+            status("GET", "/v1/pets/7", &[("full", "true")], None)
+        );
+        assert_eq!(None, status("PUT", "/v1/pets/7", &[], None));
+        assert_eq!(
+            Some(202),
+            status(
+                "POST",
+                "/v1/pets",
+                &[],
+                Some(json!({"name": "Rex", "age": 2}))
+            )
+        );
+        assert_eq!(
+            None,
+            status(
+                "POST",
+                "/v1/pets",
+                &[],
+                Some(json!({"name": "Rex", "age": 3}))
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn server_replays_recorded_responses() {
+        let routes = vec![Route {
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            response_body: Some("{\"ok\":true}".to_string()),
+            ..route("https://api.jikken.io/status", Verb::Get, None, 200)
+        }];
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(routes, listener));
+
+        let uri = format!("http://{}/status", address).parse().unwrap();
+        let response = hyper::Client::new().get(uri).await.unwrap();
+        assert_eq!(200, response.status().as_u16());
+        let bytes = body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            json!({"ok": true}),
+            serde_json::from_slice::<Value>(&bytes).unwrap()
+        );
+    }
+}
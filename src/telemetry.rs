@@ -15,11 +15,15 @@ use uuid::Uuid;
 
 const TELEMETRY_BASE_URL: &str = "https://ingestion.jikken.io/v1";
 
+// how a session ended, sent when it's completed
+pub const SESSION_COMPLETED: u32 = 1;
+pub const SESSION_STOPPED: u32 = 2;
+pub const SESSION_ABORTED: u32 = 3;
+
 #[derive(Clone)]
 pub struct Session {
     pub token: Uuid,
     pub session_id: Uuid,
-    pub start_time: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Serialize)]
@@ -51,7 +55,6 @@ struct SessionResponse {
 pub struct Test {
     pub test_id: Uuid,
     pub session: Session,
-    pub start_time: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Serialize)]
@@ -140,11 +143,7 @@ pub async fn create_session(
         let response: SessionResponse = serde_json::from_slice(response_bytes.as_ref())?;
         let session_id = uuid::Uuid::parse_str(&response.session_id)?;
 
-        return Ok(Session {
-            token,
-            session_id,
-            start_time: chrono::Utc::now(),
-        });
+        return Ok(Session { token, session_id });
     }
 
     Err(Box::from(TelemetryError {
@@ -205,7 +204,6 @@ pub async fn create_test(
         return Ok(Test {
             test_id,
             session: session.clone(),
-            start_time: chrono::Utc::now(),
         });
     }
 
@@ -1,19 +1,25 @@
+pub mod condition;
+pub mod dataset;
 pub mod definition;
 pub mod file;
+pub mod graphql;
+pub mod grpc;
 pub mod http;
+pub mod schema;
 pub mod template;
 pub mod validation;
 pub mod variable;
 
 use chrono::{offset::TimeZone, Days, Local, Months, NaiveDate};
 use log::{debug, error, trace};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
-use std::path::Path;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, JsonSchema)]
+#[schemars(deny_unknown_fields, title = "Jikken Test Definition")]
 pub struct File {
     pub name: Option<String>,
     pub id: Option<String>,
@@ -21,9 +27,14 @@ pub struct File {
     pub tags: Option<String>,
     pub requires: Option<String>,
     pub iterate: Option<u32>,
-    pub setup: Option<file::UnvalidatedRequestResponse>,
+    pub dataset: Option<file::UnvalidatedDataset>,
+    pub skip: Option<String>,
+    pub only: Option<bool>,
+    pub xfail: Option<String>,
+    pub setup: Option<file::UnvalidatedSetup>,
     pub request: Option<file::UnvalidatedRequest>,
     pub compare: Option<file::UnvalidatedCompareRequest>,
+    pub stream: Option<file::UnvalidatedStream>,
     pub response: Option<file::UnvalidatedResponse>,
     pub stages: Option<Vec<file::UnvalidatedStage>>,
     pub cleanup: Option<file::UnvalidatedCleanup>,
@@ -39,6 +50,35 @@ impl File {
         self.hash(&mut s);
         format!("{}", s.finish())
     }
+
+    // every request in the file, from setup through the stages to cleanup
+    pub fn requests(&self) -> Vec<&file::UnvalidatedRequest> {
+        let setup: Vec<&file::UnvalidatedRequest> = match &self.setup {
+            Some(file::UnvalidatedSetup::Step(step)) => vec![&step.request],
+            Some(file::UnvalidatedSetup::Steps(steps)) => {
+                steps.iter().map(|s| &s.request).collect()
+            }
+            None => Vec::new(),
+        };
+        let cleanup = self.cleanup.iter().flat_map(|c| {
+            [&c.onsuccess, &c.onfailure, &c.always]
+                .into_iter()
+                .flatten()
+                .flat_map(|steps| match steps {
+                    file::UnvalidatedCleanupSteps::Request(request) => vec![request.as_ref()],
+                    file::UnvalidatedCleanupSteps::Steps(steps) => {
+                        steps.iter().map(|s| &s.request).collect()
+                    }
+                })
+        });
+
+        setup
+            .into_iter()
+            .chain(self.request.iter())
+            .chain(self.stages.iter().flatten().map(|s| &s.request))
+            .chain(cleanup)
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -81,24 +121,10 @@ impl Variable {
     ) -> Result<Vec<Variable>, validation::Error> {
         match variables {
             None => Ok(Vec::new()),
-            Some(vars) => {
-                let count = vars.len();
-                let results = vars
-                    .into_iter()
-                    .map(|f| Variable::new(f, source_path))
-                    .filter_map(|v| match v {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    })
-                    .collect::<Vec<Variable>>();
-                if results.len() != count {
-                    Err(validation::Error {
-                        reason: "blah".to_string(),
-                    })
-                } else {
-                    Ok(results)
-                }
-            }
+            Some(vars) => vars
+                .into_iter()
+                .map(|f| Variable::new(f, source_path))
+                .collect::<Result<Vec<Variable>, validation::Error>>(),
         }
     }
 
@@ -115,6 +141,26 @@ impl Variable {
         result
     }
 
+    // a sequence holds a value per iteration, iterations past its end have no value
+    fn sequence_value<'a>(
+        &self,
+        seq: &'a [serde_yaml::Value],
+        iteration: u32,
+    ) -> &'a serde_yaml::Value {
+        static NONE: serde_yaml::Value = serde_yaml::Value::Null;
+
+        seq.get(iteration as usize).unwrap_or_else(|| {
+            error!(
+                "variable `{}` has {} value{}, none for iteration {}",
+                self.name,
+                seq.len(),
+                if seq.len() != 1 { "s" } else { "" },
+                iteration + 1
+            );
+            &NONE
+        })
+    }
+
     fn generate_int_value(&self, iteration: u32) -> String {
         match &self.value {
             serde_yaml::Value::Number(v) => {
@@ -123,7 +169,7 @@ impl Variable {
             }
             serde_yaml::Value::Sequence(seq) => {
                 debug!("sequence expression: {:?}", seq);
-                let test = &seq[iteration as usize];
+                let test = self.sequence_value(seq, iteration);
                 let test_string = match test {
                     serde_yaml::Value::Number(st) => st.as_i64().unwrap_or(0),
                     _ => 0,
@@ -141,11 +187,7 @@ impl Variable {
     fn generate_string_value(&self, iteration: u32, global_variables: Vec<Variable>) -> String {
         match &self.file {
             Some(f) => {
-                let file = if Path::new(f).exists() {
-                    f.clone()
-                } else {
-                    format!("{}{}", self.source_path, f)
-                };
+                let file = variable::resolve_file_path(f, &self.source_path);
 
                 match std::fs::read_to_string(&file) {
                     Ok(file_data) => file_data.trim().to_string(),
@@ -180,7 +222,7 @@ impl Variable {
                 }
                 serde_yaml::Value::Sequence(seq) => {
                     debug!("sequence expression: {:?}", seq);
-                    let test = &seq[iteration as usize];
+                    let test = self.sequence_value(seq, iteration);
                     let test_string = match test {
                         serde_yaml::Value::String(st) => st.to_string(),
                         _ => "".to_string(),
@@ -292,7 +334,7 @@ impl Variable {
             }
             serde_yaml::Value::Sequence(seq) => {
                 debug!("sequence expression: {:?}", seq);
-                let test = &seq[iteration as usize];
+                let test = self.sequence_value(seq, iteration);
 
                 let test_string: &str = match test {
                     serde_yaml::Value::String(st) => st,
@@ -353,11 +395,22 @@ pub struct Definition {
     pub requires: Option<String>,
     pub tags: Vec<String>,
     pub iterate: u32,
+    // a label for each iteration, taken from the rows of the dataset
+    pub labels: Vec<String>,
+    // why the test is not run
+    pub skip: Option<String>,
+    // when any test is focused, only focused tests run
+    pub only: bool,
+    // why the test is expected to fail, failures don't fail the run
+    pub xfail: Option<String>,
     pub variables: Vec<Variable>,
     pub global_variables: Vec<Variable>,
     pub stages: Vec<definition::StageDescriptor>,
-    pub setup: Option<definition::RequestResponseDescriptor>,
+    pub setup: Vec<definition::RequestResponseDescriptor>,
     pub cleanup: definition::CleanupDescriptor,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub filename: String,
 }
 
 // TODO: add validation logic to verify the descriptor is valid
@@ -387,10 +440,7 @@ impl Definition {
         }
 
         if let Some(body) = request.body.as_ref() {
-            let body_data = match serde_json::to_string(&body.data) {
-                Ok(s) => Some(s),
-                Err(_) => None,
-            };
+            let body_data = serde_json::to_string(&body.data).ok();
 
             if let Some(b) = &body_data {
                 if b.contains(var_pattern) {
@@ -425,10 +475,7 @@ impl Definition {
         }
 
         if let Some(body) = &compare.body {
-            let body_data = match serde_json::to_string(&body.data) {
-                Ok(s) => Some(s),
-                Err(_) => None,
-            };
+            let body_data = serde_json::to_string(&body.data).ok();
 
             if let Some(b) = &body_data {
                 if b.contains(var_pattern) {
@@ -439,6 +486,22 @@ impl Definition {
         }
     }
 
+    fn update_stream_variables(stream: &definition::StreamDescriptor, var_pattern: &str) {
+        for message in stream.messages.iter() {
+            let body = match message {
+                definition::StreamMessage::Send(body) => body,
+                definition::StreamMessage::Receive { body, .. } => body,
+            };
+
+            if let Ok(b) = serde_json::to_string(&body.data) {
+                if b.contains(var_pattern) {
+                    body.matches_variable.set(true);
+                    debug!("stream message match true: {}", var_pattern);
+                }
+            }
+        }
+    }
+
     fn update_response_variables(response: &definition::ResponseDescriptor, var_pattern: &str) {
         for header in response.headers.iter() {
             if header.matches_variable.get() {
@@ -452,10 +515,7 @@ impl Definition {
         }
 
         if let Some(body) = response.body.as_ref() {
-            let body_data = match serde_json::to_string(&body.data) {
-                Ok(s) => Some(s),
-                Err(_) => None,
-            };
+            let body_data = serde_json::to_string(&body.data).ok();
 
             if let Some(b) = &body_data {
                 if b.contains(var_pattern) {
@@ -473,7 +533,7 @@ impl Definition {
             let var_pattern = format!("${{{}}}", variable.name.trim());
             // debug!("pattern: {}", var_pattern);
 
-            if let Some(setup) = self.setup.as_ref() {
+            for setup in self.setup.iter() {
                 Definition::update_request_variables(&setup.request, var_pattern.as_str());
 
                 if let Some(response) = &setup.response {
@@ -481,16 +541,18 @@ impl Definition {
                 }
             }
 
-            if let Some(always) = &self.cleanup.always {
-                Definition::update_request_variables(always, var_pattern.as_str());
-            }
-
-            if let Some(onsuccess) = &self.cleanup.onsuccess {
-                Definition::update_request_variables(onsuccess, var_pattern.as_str());
-            }
+            for step in self
+                .cleanup
+                .always
+                .iter()
+                .chain(self.cleanup.onsuccess.iter())
+                .chain(self.cleanup.onfailure.iter())
+            {
+                Definition::update_request_variables(&step.request, var_pattern.as_str());
 
-            if let Some(onfailure) = &self.cleanup.onfailure {
-                Definition::update_request_variables(onfailure, var_pattern.as_str());
+                if let Some(response) = &step.response {
+                    Definition::update_response_variables(response, var_pattern.as_str());
+                }
             }
         }
 
@@ -509,6 +571,10 @@ impl Definition {
                     Definition::update_compare_variables(compare, var_pattern.as_str());
                 }
 
+                if let Some(stream) = &stage.stream {
+                    Definition::update_stream_variables(stream, var_pattern.as_str());
+                }
+
                 if let Some(response) = &stage.response {
                     Definition::update_response_variables(response, var_pattern.as_str());
                 }
@@ -601,25 +667,6 @@ impl Definition {
         (String::from(""), String::from(""))
     }
 
-    pub fn get_setup_request_headers(&self, iteration: u32) -> Vec<(String, String)> {
-        match self.setup.as_ref() {
-            Some(setup) => setup
-                .request
-                .headers
-                .iter()
-                .map(|h| {
-                    if h.matches_variable.get() {
-                        let header = self.get_processed_header(h, iteration);
-                        (header.0, header.1)
-                    } else {
-                        (h.header.clone(), h.value.clone())
-                    }
-                })
-                .collect(),
-            None => Vec::new(),
-        }
-    }
-
     pub fn get_headers(&self, headers: &[http::Header], iteration: u32) -> Vec<(String, String)> {
         headers
             .iter()
@@ -634,13 +681,6 @@ impl Definition {
             .collect()
     }
 
-    pub fn get_cleanup_request_headers(&self, iteration: u32) -> Vec<(String, String)> {
-        match &self.cleanup.always {
-            Some(request) => self.get_headers(&request.headers, iteration),
-            None => Vec::new(),
-        }
-    }
-
     pub fn get_stage_compare_headers(
         &self,
         stage_index: usize,
@@ -762,12 +802,150 @@ impl Definition {
                 body_str = body_str.replace(var_pattern.as_str(), replacement.as_str());
             }
 
-            return match serde_json::from_str(body_str.as_str()) {
-                Ok(result) => Some(result),
-                Err(_) => None,
-            };
+            return serde_json::from_str(body_str.as_str()).ok();
         }
 
         None
     }
+
+    // a message sent or expected on a stream, with the stage's variables replaced
+    pub fn get_stream_body(
+        &self,
+        body: &definition::RequestBody,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> serde_json::Value {
+        if !body.matches_variable.get() {
+            return body.data.clone();
+        }
+
+        let mut body_str = serde_json::to_string(&body.data).unwrap_or_default();
+
+        for variable in variables.iter().chain(self.global_variables.iter()) {
+            let var_pattern = format!("${{{}}}", variable.name);
+
+            if !body_str.contains(var_pattern.as_str()) {
+                continue;
+            }
+
+            let replacement = variable.generate_value(iteration, self.global_variables.clone());
+            body_str = body_str.replace(var_pattern.as_str(), replacement.as_str());
+        }
+
+        serde_json::from_str(body_str.as_str()).unwrap_or_else(|_| body.data.clone())
+    }
+
+    fn resolve_request(
+        &self,
+        request: &definition::RequestDescriptor,
+        headers: Vec<(String, String)>,
+        variables: &[Variable],
+        iteration: u32,
+    ) -> definition::ResolvedRequest {
+        definition::ResolvedRequest::new(
+            self.get_url(iteration, &request.url, &request.params, variables),
+            request.method.as_method(),
+            headers,
+            self.get_body(request, variables, iteration),
+        )
+    }
+
+    // the label of the dataset row an iteration runs, formatted for output
+    pub fn iteration_label(&self, iteration: u32) -> String {
+        self.labels
+            .get(iteration as usize)
+            .map(|l| format!(" [{}]", l))
+            .unwrap_or_default()
+    }
+
+    // every request the test can make for an iteration, labelled by the stage it belongs to.
+    // variables extracted from responses at runtime are left as `${name}`
+    pub fn resolve_requests(&self, iteration: u32) -> Vec<definition::ResolvedStage<'_>> {
+        let mut results = Vec::new();
+
+        for (i, setup) in self.setup.iter().enumerate() {
+            results.push(definition::ResolvedStage {
+                label: if self.setup.len() > 1 {
+                    format!("setup {}", i + 1)
+                } else {
+                    "setup".to_string()
+                },
+                request: self.resolve_request(
+                    &setup.request,
+                    self.get_headers(&setup.request.headers, iteration),
+                    &self.variables,
+                    iteration,
+                ),
+                response: setup.response.as_ref(),
+                graphql: setup.request.graphql,
+                grpc: setup.request.grpc.as_ref(),
+            });
+        }
+
+        for (stage_index, stage) in self.stages.iter().enumerate() {
+            let variables = [&stage.variables[..], &self.variables[..]].concat();
+
+            results.push(definition::ResolvedStage {
+                label: format!("stage {}", stage_index + 1),
+                request: self.resolve_request(
+                    &stage.request,
+                    self.get_headers(&stage.request.headers, iteration),
+                    &variables,
+                    iteration,
+                ),
+                response: stage.response.as_ref(),
+                graphql: stage.request.graphql,
+                grpc: stage.request.grpc.as_ref(),
+            });
+
+            if let Some(compare) = &stage.compare {
+                results.push(definition::ResolvedStage {
+                    label: format!("stage {} compare", stage_index + 1),
+                    request: definition::ResolvedRequest::new(
+                        self.get_url(
+                            iteration,
+                            &compare.url,
+                            &stage.get_compare_parameters(),
+                            &variables,
+                        ),
+                        compare.method.as_method(),
+                        self.get_stage_compare_headers(stage_index, iteration),
+                        self.get_compare_body(compare, &variables, iteration),
+                    ),
+                    response: None,
+                    graphql: false,
+                    grpc: None,
+                });
+            }
+        }
+
+        let cleanup = [
+            ("cleanup onsuccess", &self.cleanup.onsuccess),
+            ("cleanup onfailure", &self.cleanup.onfailure),
+            ("cleanup always", &self.cleanup.always),
+        ];
+
+        for (label, steps) in cleanup.iter() {
+            for (i, step) in steps.iter().enumerate() {
+                results.push(definition::ResolvedStage {
+                    label: if steps.len() > 1 {
+                        format!("{} {}", label, i + 1)
+                    } else {
+                        label.to_string()
+                    },
+                    request: self.resolve_request(
+                        &step.request,
+                        self.get_headers(&step.request.headers, iteration),
+                        &self.variables,
+                        iteration,
+                    ),
+                    response: step.response.as_ref(),
+                    graphql: step.request.graphql,
+                    grpc: step.request.grpc.as_ref(),
+                });
+            }
+        }
+
+        results
+    }
 }
@@ -0,0 +1,137 @@
+use crate::test::validation;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const OPERATORS: &[&str] = &["==", "!=", "<=", ">=", "<", ">"];
+
+// a check against the variables extracted so far, such as `next != null` or `count > 0`.
+// a bare variable name is true when the variable is set to something other than an empty
+// value, `null`, `false` or `0`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Condition {
+    pub variable: String,
+    pub operator: Option<String>,
+    pub value: String,
+}
+
+fn variable_name(text: &str) -> &str {
+    let trimmed = text.trim();
+    trimmed
+        .strip_prefix("${")
+        .and_then(|t| t.strip_suffix('}'))
+        .unwrap_or(trimmed)
+        .trim()
+}
+
+fn unquote(text: &str) -> &str {
+    let trimmed = text.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = trimmed
+            .strip_prefix(quote)
+            .and_then(|t| t.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+
+    trimmed
+}
+
+fn is_null(value: Option<&String>) -> bool {
+    matches!(value.map(|v| v.as_str()), None | Some("") | Some("null"))
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, validation::Error> {
+        let found = OPERATORS
+            .iter()
+            .filter_map(|op| text.find(op).map(|i| (i, *op)))
+            .min_by_key(|(i, op)| (*i, std::cmp::Reverse(op.len())));
+
+        let (variable, operator, value) = match found {
+            Some((i, op)) => (
+                variable_name(&text[..i]),
+                Some(op.to_string()),
+                unquote(&text[i + op.len()..]).to_string(),
+            ),
+            None => (variable_name(text), None, String::new()),
+        };
+
+        if variable.is_empty() || variable.contains(char::is_whitespace) {
+            return Err(validation::Error {
+                reason: format!(
+                    "invalid condition `{}`, expected a variable, optionally compared with {}",
+                    text,
+                    OPERATORS.join(", ")
+                ),
+            });
+        }
+
+        Ok(Condition {
+            variable: variable.to_string(),
+            operator,
+            value,
+        })
+    }
+
+    // `null` matches unset variables, numbers are compared numerically and anything else
+    // as text
+    pub fn evaluate(&self, variables: &HashMap<String, String>) -> bool {
+        let actual = variables.get(&self.variable);
+        let operator = match &self.operator {
+            Some(o) => o.as_str(),
+            None => {
+                return !is_null(actual)
+                    && !matches!(actual.map(|v| v.as_str()), Some("false") | Some("0"))
+            }
+        };
+
+        if self.value == "null" {
+            return match operator {
+                "==" => is_null(actual),
+                "!=" => !is_null(actual),
+                _ => false,
+            };
+        }
+
+        let actual = actual.map(|v| v.as_str()).unwrap_or_default();
+        let ordering = match (actual.parse::<f64>(), self.value.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b),
+            _ => Some(actual.cmp(self.value.as_str())),
+        };
+
+        match (operator, ordering) {
+            ("==", Some(o)) => o.is_eq(),
+            ("!=", Some(o)) => o.is_ne(),
+            ("<", Some(o)) => o.is_lt(),
+            ("<=", Some(o)) => o.is_le(),
+            (">", Some(o)) => o.is_gt(),
+            (">=", Some(o)) => o.is_ge(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions_compare_numbers_text_and_null() {
+        let variables = HashMap::from([
+            ("count".to_string(), "10".to_string()),
+            ("status".to_string(), "active".to_string()),
+            ("next".to_string(), "".to_string()),
+        ]);
+        let check = |text: &str| Condition::parse(text).unwrap().evaluate(&variables);
+
+        assert!(check("count > 9"));
+        assert!(check("${count} <= 10"));
+        assert!(check("status == 'active'"));
+        assert!(check("next == null"));
+        assert!(check("missing == null"));
+        assert!(!check("next"));
+        assert!(check("status"));
+        assert!(Condition::parse("== 1").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::test::{file, validation, variable};
+use std::path::Path;
+
+// a row of a dataset, run as its own iteration with each column bound as a variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub label: String,
+    pub values: Vec<(String, String)>,
+}
+
+fn error(reason: String) -> validation::Error {
+    validation::Error { reason }
+}
+
+// the first record holds the column names. quoted fields may contain commas, newlines
+// and `""` escapes, and short rows leave the remaining columns empty
+fn parse_csv(text: &str) -> Result<Vec<Vec<(String, String)>>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::Headers)
+        .from_reader(text.as_bytes());
+    let columns: Vec<String> = reader
+        .headers()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+
+    reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|e| e.to_string())?;
+            let mut values = record.iter();
+            Ok(columns
+                .iter()
+                .map(|c| (c.clone(), values.next().unwrap_or_default().to_string()))
+                .collect())
+        })
+        .collect()
+}
+
+// JSON is valid YAML, so both are read as a list of objects. nested values are kept as JSON
+fn parse_documents(text: &str) -> Result<Vec<Vec<(String, String)>>, String> {
+    let rows: Vec<serde_json::Map<String, serde_json::Value>> =
+        serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|(k, v)| {
+                    let value = match v {
+                        serde_json::Value::String(s) => s,
+                        serde_json::Value::Null => String::new(),
+                        other => other.to_string(),
+                    };
+                    (k, value)
+                })
+                .collect()
+        })
+        .collect())
+}
+
+// dataset files are relative to the test file unless absolute
+pub fn resolve_path(file: &str, test_filename: &str) -> String {
+    if Path::new(file).is_absolute() {
+        file.to_string()
+    } else {
+        format!("{}{}", variable::parse_source_path(test_filename), file)
+    }
+}
+
+// loads the rows of a dataset file, labelling each by its `label` column or its position
+pub fn load(
+    dataset: &file::UnvalidatedDataset,
+    test_filename: &str,
+) -> Result<Vec<Row>, validation::Error> {
+    let path = resolve_path(&dataset.file, test_filename);
+
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| error(format!("unable to read dataset `{}`: {}", path, e)))?;
+    let extension = Path::new(&path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let rows = match extension.as_str() {
+        "csv" => parse_csv(&text),
+        "json" | "yaml" | "yml" => parse_documents(&text),
+        _ => {
+            return Err(error(format!(
+                "unsupported dataset `{}`, expected a .csv, .json, .yaml or .yml file",
+                path
+            )))
+        }
+    }
+    .map_err(|e| error(format!("invalid dataset `{}`: {}", path, e)))?;
+
+    if rows.is_empty() {
+        return Err(error(format!("dataset `{}` has no rows", path)));
+    }
+
+    rows.into_iter()
+        .enumerate()
+        .map(|(i, values)| {
+            let label = match &dataset.label {
+                Some(column) => values
+                    .iter()
+                    .find(|(k, _)| k == column)
+                    .map(|(_, v)| v.clone())
+                    .ok_or_else(|| {
+                        error(format!(
+                            "row {} of dataset `{}` has no `{}` column",
+                            i + 1,
+                            path,
+                            column
+                        ))
+                    })?,
+                None => format!("row {}", i + 1),
+            };
+
+            Ok(Row { label, values })
+        })
+        .collect()
+}
+
+// the names of every column, in the order they first appear
+pub fn columns(rows: &[Row]) -> Vec<String> {
+    let mut results: Vec<String> = Vec::new();
+
+    for (name, _) in rows.iter().flat_map(|r| r.values.iter()) {
+        if !results.contains(name) {
+            results.push(name.clone());
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_keep_quoted_commas_and_fill_missing_columns() {
+        let rows = parse_csv("name, city\n\"Smith, Jo\",\"the \"\"big\"\" one\"\nAl\n").unwrap();
+
+        assert_eq!(
+            vec![
+                vec![
+                    ("name".to_string(), "Smith, Jo".to_string()),
+                    ("city".to_string(), "the \"big\" one".to_string()),
+                ],
+                vec![
+                    ("name".to_string(), "Al".to_string()),
+                    ("city".to_string(), "".to_string()),
+                ],
+            ],
+            rows
+        );
+    }
+
+    #[test]
+    fn csv_fields_may_span_lines() {
+        let rows = parse_csv("name,address\nJo,\"1 Main St\nSpringfield\"\n\nAl,\"\"\n").unwrap();
+
+        assert_eq!(2, rows.len());
+        assert_eq!(
+            ("address".to_string(), "1 Main St\nSpringfield".to_string()),
+            rows[0][1]
+        );
+        assert_eq!(("name".to_string(), "Al".to_string()), rows[1][0]);
+    }
+}
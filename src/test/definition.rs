@@ -1,8 +1,12 @@
+use crate::executor::load;
+use crate::json::diff;
 use crate::test;
-use crate::test::{file, http, validation};
+use crate::test::{condition, file, graphql, grpc, http, validation};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::cell::Cell;
 use std::collections::HashSet;
+use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RequestBody {
@@ -19,11 +23,19 @@ pub struct RequestDescriptor {
     pub params: Vec<http::Parameter>,
     pub headers: Vec<http::Header>,
     pub body: Option<RequestBody>,
+    // the body is a GraphQL query, whose response is checked for `errors` and validated
+    // relative to its `data`
+    pub graphql: bool,
+    // the body is the message of a unary gRPC call, whose response is mapped to JSON
+    pub grpc: Option<grpc::Method>,
 }
 
 // TODO: add validation logic to verify the descriptor is valid
 impl RequestDescriptor {
-    pub fn new(request: file::UnvalidatedRequest) -> Result<RequestDescriptor, validation::Error> {
+    pub fn new(
+        request: file::UnvalidatedRequest,
+        source_path: &str,
+    ) -> Result<RequestDescriptor, validation::Error> {
         let validated_params = match request.params {
             Some(params) => params
                 .iter()
@@ -48,28 +60,57 @@ impl RequestDescriptor {
             None => Vec::new(),
         };
 
-        let request_body = request.body.map(|b| RequestBody {
+        let graphql = request.graphql.is_some();
+        let body = match (request.body, request.graphql) {
+            (Some(_), Some(_)) => {
+                return Err(validation::Error {
+                    reason: "a request takes either a `body` or `graphql`, not both".to_string(),
+                })
+            }
+            (body, None) => body,
+            (None, Some(g)) => Some(graphql::body(&g, source_path)?),
+        };
+
+        let grpc = match request.grpc {
+            Some(_) if graphql => {
+                return Err(validation::Error {
+                    reason: "a request takes either `graphql` or `grpc`, not both".to_string(),
+                })
+            }
+            Some(g) => Some(grpc::Method::new(&g, source_path)?),
+            None => None,
+        };
+
+        let method = match request.method {
+            Some(http::Verb::Post) | None if graphql || grpc.is_some() => http::Verb::Post,
+            Some(_) if graphql => {
+                return Err(validation::Error {
+                    reason: "graphql requests are sent with the POST method".to_string(),
+                })
+            }
+            Some(_) if grpc.is_some() => {
+                return Err(validation::Error {
+                    reason: "grpc calls are sent with the POST method".to_string(),
+                })
+            }
+            method => method.unwrap_or(http::Verb::Get),
+        };
+
+        let request_body = body.map(|b| RequestBody {
             data: b,
             matches_variable: Cell::from(false),
         });
 
         Ok(RequestDescriptor {
-            method: request.method.unwrap_or(http::Verb::Get),
+            method,
             url: request.url,
             params: validated_params,
             headers: validated_headers,
             body: request_body,
+            graphql,
+            grpc,
         })
     }
-
-    pub fn new_opt(
-        request_opt: Option<file::UnvalidatedRequest>,
-    ) -> Result<Option<RequestDescriptor>, validation::Error> {
-        match request_opt {
-            Some(request) => Ok(Some(RequestDescriptor::new(request)?)),
-            None => Ok(None),
-        }
-    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,6 +124,59 @@ pub struct CompareDescriptor {
     pub add_headers: Vec<http::Header>,
     pub ignore_headers: Vec<String>,
     pub body: Option<RequestBody>,
+    pub response: CompareResponseDescriptor,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompareResponseDescriptor {
+    // body paths left out of the comparison, the stage's `ignore` list when not given
+    pub ignore: Option<Vec<String>>,
+    // headers are compared when either list is given: only those in `headers` if it's
+    // given, otherwise every header, less those in `ignore_headers`
+    pub headers: Vec<String>,
+    pub ignore_headers: Vec<String>,
+    pub tolerance: f64,
+    pub unordered_arrays: bool,
+}
+
+impl CompareResponseDescriptor {
+    pub fn new(
+        response_opt: Option<file::UnvalidatedCompareResponse>,
+    ) -> Result<CompareResponseDescriptor, validation::Error> {
+        let response = match response_opt {
+            Some(r) => r,
+            None => return Ok(CompareResponseDescriptor::default()),
+        };
+
+        let tolerance = response.tolerance.unwrap_or(0.0);
+        if !(tolerance >= 0.0 && tolerance.is_finite()) {
+            return Err(validation::Error {
+                reason: format!(
+                    "compare tolerance must be a positive number, got {}",
+                    tolerance
+                ),
+            });
+        }
+
+        Ok(CompareResponseDescriptor {
+            ignore: response.ignore,
+            headers: response.headers.unwrap_or_default(),
+            ignore_headers: response.ignore_headers.unwrap_or_default(),
+            tolerance,
+            unordered_arrays: response.unordered_arrays.unwrap_or(false),
+        })
+    }
+
+    pub fn compares_headers(&self) -> bool {
+        !self.headers.is_empty() || !self.ignore_headers.is_empty()
+    }
+
+    pub fn rules(&self) -> diff::Rules {
+        diff::Rules {
+            tolerance: self.tolerance,
+            unordered_arrays: self.unordered_arrays,
+        }
+    }
 }
 
 impl CompareDescriptor {
@@ -174,6 +268,7 @@ impl CompareDescriptor {
                     add_headers: validated_add_headers,
                     ignore_headers: validated_ignore_headers,
                     body: compare_body,
+                    response: CompareResponseDescriptor::new(request.response)?,
                 }))
             }
             None => Ok(None),
@@ -181,7 +276,8 @@ impl CompareDescriptor {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ResponseExtraction {
     pub name: String,
     pub field: String,
@@ -224,15 +320,9 @@ impl ResponseDescriptor {
                     None => Vec::new(),
                 };
 
-                let validated_ignore = match res.ignore {
-                    Some(ignore) => ignore,
-                    None => Vec::new(),
-                };
+                let validated_ignore = res.ignore.unwrap_or_default();
 
-                let validated_extraction = match res.extract {
-                    Some(extract) => extract,
-                    None => Vec::new(),
-                };
+                let validated_extraction = res.extract.unwrap_or_default();
 
                 let response_body = res.body.map(|b| RequestBody {
                     data: b,
@@ -252,15 +342,197 @@ impl ResponseDescriptor {
     }
 }
 
+pub const DEFAULT_MAX_ITERATIONS: u32 = 100;
+
+// how a stage is run more than once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Repeat {
+    // once for each element of the list held by `variable`, bound to `item`
+    ForEach { variable: String, item: String },
+    // checked before each run
+    While(condition::Condition),
+    // checked after each run
+    Until(condition::Condition),
+}
+
+impl Repeat {
+    fn new(stage: &file::UnvalidatedStage) -> Result<Option<Repeat>, validation::Error> {
+        let repeat = match (
+            &stage.for_each,
+            &stage.while_condition,
+            &stage.until_condition,
+        ) {
+            (None, None, None) => None,
+            (Some(variable), None, None) => Some(Repeat::ForEach {
+                variable: variable.trim().to_string(),
+                item: stage.item.clone().unwrap_or("item".to_string()),
+            }),
+            (None, Some(c), None) => Some(Repeat::While(condition::Condition::parse(c)?)),
+            (None, None, Some(c)) => Some(Repeat::Until(condition::Condition::parse(c)?)),
+            _ => {
+                return Err(validation::Error {
+                    reason: "only one of `forEach`, `while` and `until` can be used".to_string(),
+                })
+            }
+        };
+
+        if stage.item.is_some() && stage.for_each.is_none() {
+            return Err(validation::Error {
+                reason: "`as` requires `forEach`".to_string(),
+            });
+        }
+
+        if stage.max_iterations == Some(0) {
+            return Err(validation::Error {
+                reason: "`maxIterations` must be at least 1".to_string(),
+            });
+        }
+
+        Ok(repeat)
+    }
+}
+
+// how long a stream waits for a message when no timeout is given
+pub const DEFAULT_STREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StreamMessage {
+    Send(RequestBody),
+    // waits for the first message which matches the body, less the ignored paths
+    Receive {
+        body: RequestBody,
+        ignore: Vec<String>,
+        extract: Vec<ResponseExtraction>,
+        timeout: Duration,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamDescriptor {
+    // how long opening the connection may take
+    pub timeout: Duration,
+    pub messages: Vec<StreamMessage>,
+}
+
+fn stream_timeout(timeout: &Option<String>) -> Result<Option<Duration>, validation::Error> {
+    timeout
+        .as_deref()
+        .map(load::parse_duration)
+        .transpose()
+        .map_err(|e| validation::Error {
+            reason: format!("stream timeout: {}", e),
+        })
+}
+
+fn stream_body(data: serde_json::Value) -> RequestBody {
+    RequestBody {
+        data,
+        matches_variable: Cell::from(false),
+    }
+}
+
+impl StreamDescriptor {
+    // the connection is opened with the request's url, params and headers, and messages are
+    // checked here rather than by a response
+    pub fn new_opt(
+        stream_opt: Option<file::UnvalidatedStream>,
+        request: &file::UnvalidatedRequest,
+        compare: &Option<file::UnvalidatedCompareRequest>,
+        response: &Option<file::UnvalidatedResponse>,
+    ) -> Result<Option<StreamDescriptor>, validation::Error> {
+        let stream = match stream_opt {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        let invalid = |reason: &str| {
+            Err(validation::Error {
+                reason: reason.to_string(),
+            })
+        };
+
+        if request.graphql.is_some() || request.grpc.is_some() || request.body.is_some() {
+            return invalid("a stream's request takes no `body`, `graphql` or `grpc`");
+        }
+
+        if !matches!(request.method, None | Some(http::Verb::Get)) {
+            return invalid("streams are opened with the GET method");
+        }
+
+        if compare.is_some() || response.is_some() {
+            return invalid(
+                "a stream takes no `compare` or `response`, its messages are checked instead",
+            );
+        }
+
+        let timeout = stream_timeout(&stream.timeout)?.unwrap_or(DEFAULT_STREAM_TIMEOUT);
+        let mut messages = Vec::new();
+
+        for (index, message) in stream.messages.into_iter().enumerate() {
+            let receiving =
+                message.ignore.is_some() || message.extract.is_some() || message.timeout.is_some();
+
+            messages.push(match (message.send, message.receive) {
+                (Some(send), None) if !receiving => StreamMessage::Send(stream_body(send)),
+                (None, Some(receive)) => StreamMessage::Receive {
+                    body: stream_body(receive),
+                    ignore: message.ignore.unwrap_or_default(),
+                    extract: message.extract.unwrap_or_default(),
+                    timeout: stream_timeout(&message.timeout)?.unwrap_or(timeout),
+                },
+                (Some(_), None) => {
+                    return Err(validation::Error {
+                        reason: format!(
+                            "stream message {}: `ignore`, `extract` and `timeout` apply to received messages",
+                            index + 1
+                        ),
+                    })
+                }
+                _ => {
+                    return Err(validation::Error {
+                        reason: format!(
+                            "stream message {} takes either `send` or `receive`",
+                            index + 1
+                        ),
+                    })
+                }
+            });
+        }
+
+        Ok(Some(StreamDescriptor { timeout, messages }))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StageDescriptor {
     pub request: RequestDescriptor,
     pub compare: Option<CompareDescriptor>,
+    // messages exchanged over a WebSocket or server-sent event stream, instead of a request
+    // and response
+    pub stream: Option<StreamDescriptor>,
     pub response: Option<ResponseDescriptor>,
     pub variables: Vec<test::Variable>,
+    pub skip: Option<String>,
+    pub only: bool,
+    pub xfail: Option<String>,
+    // the stage only runs when this holds
+    pub condition: Option<condition::Condition>,
+    pub repeat: Option<Repeat>,
+    // the most times a repeated stage may run before it fails
+    pub max_iterations: u32,
+}
 
-    #[serde(skip_serializing)]
-    pub source_path: String,
+// compare requests are plain HTTP, so there's nothing to compare a gRPC call with
+fn no_grpc_compare(
+    request: &file::UnvalidatedRequest,
+    compare: &Option<file::UnvalidatedCompareRequest>,
+) -> Result<(), validation::Error> {
+    match (&request.grpc, compare) {
+        (Some(_), Some(_)) => Err(validation::Error {
+            reason: "grpc calls can't have a `compare` request".to_string(),
+        }),
+        _ => Ok(()),
+    }
 }
 
 impl StageDescriptor {
@@ -268,59 +540,74 @@ impl StageDescriptor {
         stage: file::UnvalidatedStage,
         source_path: &str,
     ) -> Result<StageDescriptor, validation::Error> {
+        let repeat = Repeat::new(&stage)?;
+        no_grpc_compare(&stage.request, &stage.compare)?;
+        let stream = StreamDescriptor::new_opt(
+            stage.stream,
+            &stage.request,
+            &stage.compare,
+            &stage.response,
+        )?;
+
         Ok(StageDescriptor {
-            request: RequestDescriptor::new(stage.request)?,
+            condition: stage
+                .if_condition
+                .as_deref()
+                .map(condition::Condition::parse)
+                .transpose()?,
+            repeat,
+            max_iterations: stage.max_iterations.unwrap_or(DEFAULT_MAX_ITERATIONS),
+            request: RequestDescriptor::new(stage.request, source_path)?,
             compare: CompareDescriptor::new_opt(stage.compare)?,
+            stream,
             response: ResponseDescriptor::new_opt(stage.response)?,
             variables: test::Variable::validate_variables_opt(stage.variables, source_path)?,
-            source_path: source_path.to_string(),
+            skip: stage.skip,
+            only: stage.only.unwrap_or(false),
+            xfail: stage.xfail,
         })
     }
 
     pub fn validate_stages_opt(
         request_opt: Option<file::UnvalidatedRequest>,
         compare_opt: Option<file::UnvalidatedCompareRequest>,
+        stream_opt: Option<file::UnvalidatedStream>,
         response_opt: Option<file::UnvalidatedResponse>,
         stages_opt: Option<Vec<file::UnvalidatedStage>>,
         source_path: &str,
     ) -> Result<Vec<StageDescriptor>, validation::Error> {
         let mut results = Vec::new();
-        let mut count = 0;
 
         if let Some(request) = request_opt {
+            no_grpc_compare(&request, &compare_opt)?;
+            let stream =
+                StreamDescriptor::new_opt(stream_opt, &request, &compare_opt, &response_opt)?;
             results.push(StageDescriptor {
-                request: RequestDescriptor::new(request)?,
+                request: RequestDescriptor::new(request, source_path)?,
                 compare: CompareDescriptor::new_opt(compare_opt)?,
+                stream,
                 response: ResponseDescriptor::new_opt(response_opt)?,
                 variables: Vec::new(),
-                source_path: source_path.to_string(),
+                skip: None,
+                only: false,
+                xfail: None,
+                condition: None,
+                repeat: None,
+                max_iterations: DEFAULT_MAX_ITERATIONS,
             });
-            count += 1;
         }
 
-        match stages_opt {
-            None => Ok(results),
-            Some(stages) => {
-                count += stages.len();
-                results.append(
-                    &mut stages
-                        .into_iter()
-                        .map(|s| StageDescriptor::new(s, source_path))
-                        .filter_map(|v| match v {
-                            Ok(x) => Some(x),
-                            Err(_) => None,
-                        })
-                        .collect::<Vec<StageDescriptor>>(),
-                );
-                if results.len() != count {
-                    Err(validation::Error {
-                        reason: "blah".to_string(),
-                    })
-                } else {
-                    Ok(results)
-                }
+        if let Some(stages) = stages_opt {
+            for (index, stage) in stages.into_iter().enumerate() {
+                let validated =
+                    StageDescriptor::new(stage, source_path).map_err(|e| validation::Error {
+                        reason: format!("stage {}: {}", index + 1, e),
+                    })?;
+                results.push(validated);
             }
         }
+
+        Ok(results)
     }
 
     pub fn get_compare_parameters(&self) -> Vec<http::Parameter> {
@@ -351,19 +638,34 @@ pub struct RequestResponseDescriptor {
 }
 
 impl RequestResponseDescriptor {
-    pub fn new_opt(
-        reqresp_opt: Option<file::UnvalidatedRequestResponse>,
-    ) -> Result<Option<RequestResponseDescriptor>, validation::Error> {
-        match reqresp_opt {
-            Some(reqresp) => Ok(Some(RequestResponseDescriptor {
-                request: RequestDescriptor::new(reqresp.request)?,
-                response: ResponseDescriptor::new_opt(reqresp.response)?,
-            })),
-            None => Ok(None),
+    pub fn new(
+        reqresp: file::UnvalidatedRequestResponse,
+        source_path: &str,
+    ) -> Result<RequestResponseDescriptor, validation::Error> {
+        Ok(RequestResponseDescriptor {
+            request: RequestDescriptor::new(reqresp.request, source_path)?,
+            response: ResponseDescriptor::new_opt(reqresp.response)?,
+        })
+    }
+
+    pub fn new_setup(
+        setup_opt: Option<file::UnvalidatedSetup>,
+        source_path: &str,
+    ) -> Result<Vec<RequestResponseDescriptor>, validation::Error> {
+        match setup_opt {
+            Some(file::UnvalidatedSetup::Step(step)) => {
+                Ok(vec![RequestResponseDescriptor::new(*step, source_path)?])
+            }
+            Some(file::UnvalidatedSetup::Steps(steps)) => steps
+                .into_iter()
+                .map(|s| RequestResponseDescriptor::new(s, source_path))
+                .collect(),
+            None => Ok(Vec::new()),
         }
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct ResolvedRequest {
     // pub req_resp: RequestResponseDescriptor,
     pub url: String,
@@ -388,27 +690,59 @@ impl ResolvedRequest {
     }
 }
 
+// a request resolved for an iteration, along with the stage it belongs to and the
+// response that stage expects
+pub struct ResolvedStage<'a> {
+    pub label: String,
+    pub request: ResolvedRequest,
+    pub response: Option<&'a ResponseDescriptor>,
+    // the expected body is the `data` of a GraphQL response
+    pub graphql: bool,
+    pub grpc: Option<&'a grpc::Method>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CleanupDescriptor {
-    pub onsuccess: Option<RequestDescriptor>,
-    pub onfailure: Option<RequestDescriptor>,
-    pub always: Option<RequestDescriptor>,
+    pub onsuccess: Vec<RequestResponseDescriptor>,
+    pub onfailure: Vec<RequestResponseDescriptor>,
+    pub always: Vec<RequestResponseDescriptor>,
+}
+
+// a lone request is a step which expects nothing of its response
+fn cleanup_steps(
+    steps_opt: Option<file::UnvalidatedCleanupSteps>,
+    source_path: &str,
+) -> Result<Vec<RequestResponseDescriptor>, validation::Error> {
+    match steps_opt {
+        Some(file::UnvalidatedCleanupSteps::Request(request)) => {
+            Ok(vec![RequestResponseDescriptor {
+                request: RequestDescriptor::new(*request, source_path)?,
+                response: None,
+            }])
+        }
+        Some(file::UnvalidatedCleanupSteps::Steps(steps)) => steps
+            .into_iter()
+            .map(|s| RequestResponseDescriptor::new(s, source_path))
+            .collect(),
+        None => Ok(Vec::new()),
+    }
 }
 
 impl CleanupDescriptor {
     pub fn new(
         cleanup_opt: Option<file::UnvalidatedCleanup>,
+        source_path: &str,
     ) -> Result<CleanupDescriptor, validation::Error> {
         match cleanup_opt {
             Some(cleanup) => Ok(CleanupDescriptor {
-                onsuccess: RequestDescriptor::new_opt(cleanup.onsuccess)?,
-                onfailure: RequestDescriptor::new_opt(cleanup.onfailure)?,
-                always: RequestDescriptor::new_opt(cleanup.always)?,
+                onsuccess: cleanup_steps(cleanup.onsuccess, source_path)?,
+                onfailure: cleanup_steps(cleanup.onfailure, source_path)?,
+                always: cleanup_steps(cleanup.always, source_path)?,
             }),
             None => Ok(CleanupDescriptor {
-                onsuccess: None,
-                onfailure: None,
-                always: None,
+                onsuccess: Vec::new(),
+                onfailure: Vec::new(),
+                always: Vec::new(),
             }),
         }
     }
@@ -1,18 +1,22 @@
 use crate::test;
 use crate::test::{definition, http, variable};
 use log::error;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fs;
 use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct UnvalidatedRequest {
     pub method: Option<http::Verb>,
     pub url: String,
     pub params: Option<Vec<http::Parameter>>,
     pub headers: Option<Vec<http::Header>>,
     pub body: Option<serde_json::Value>,
+    pub graphql: Option<UnvalidatedGraphql>,
+    pub grpc: Option<UnvalidatedGrpc>,
 }
 
 impl Hash for UnvalidatedRequest {
@@ -21,10 +25,52 @@ impl Hash for UnvalidatedRequest {
         self.url.hash(state);
         self.params.hash(state);
         self.headers.hash(state);
+        self.graphql.hash(state);
+        self.grpc.hash(state);
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// sent as the JSON body of a POST, the query is given inline or as a `.graphql` file
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct UnvalidatedGraphql {
+    pub query: Option<String>,
+    pub file: Option<String>,
+    pub variables: Option<serde_json::Value>,
+    pub operation_name: Option<String>,
+}
+
+// requests to one endpoint differ by their query, so it's part of the generated id
+impl Hash for UnvalidatedGraphql {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.query.hash(state);
+        self.file.hash(state);
+        self.operation_name.hash(state);
+    }
+}
+
+// a unary call whose message is the request's `body`, encoded with the descriptors of a
+// `.proto` file or a descriptor set
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct UnvalidatedGrpc {
+    pub proto: Option<String>,
+    pub descriptor_set: Option<String>,
+    pub import_paths: Option<Vec<String>>,
+    pub method: String,
+}
+
+// calls to one server differ by their method, so it's part of the generated id
+impl Hash for UnvalidatedGrpc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.method.hash(state);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 #[serde(rename_all = "camelCase")]
 pub struct UnvalidatedCompareRequest {
     pub method: Option<http::Verb>,
@@ -36,6 +82,19 @@ pub struct UnvalidatedCompareRequest {
     pub add_headers: Option<Vec<http::Header>>,
     pub ignore_headers: Option<Vec<String>>,
     pub body: Option<serde_json::Value>,
+    pub response: Option<UnvalidatedCompareResponse>,
+}
+
+// how the stage's response is checked against the compare request's response
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct UnvalidatedCompareResponse {
+    pub ignore: Option<Vec<String>>,
+    pub headers: Option<Vec<String>>,
+    pub ignore_headers: Option<Vec<String>>,
+    pub tolerance: Option<f64>,
+    pub unordered_arrays: Option<bool>,
 }
 
 impl Hash for UnvalidatedCompareRequest {
@@ -51,7 +110,8 @@ impl Hash for UnvalidatedCompareRequest {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct UnvalidatedResponse {
     pub status: Option<u16>,
     pub headers: Option<Vec<http::Header>>,
@@ -68,36 +128,108 @@ impl Hash for UnvalidatedResponse {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct UnvalidatedDataset {
+    pub file: String,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 #[serde(rename_all = "camelCase")]
 pub struct UnvalidatedVariable {
     pub name: String,
     pub data_type: Option<variable::Type>,
+    #[schemars(with = "Option<serde_json::Value>")]
     pub value: Option<serde_yaml::Value>,
     pub modifier: Option<variable::Modifier>,
     pub format: Option<String>,
     pub file: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+// messages sent to and expected from a WebSocket, or the events of a server-sent event stream,
+// over a connection to the request's url
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct UnvalidatedStream {
+    pub timeout: Option<String>,
+    pub messages: Vec<UnvalidatedStreamMessage>,
+}
+
+// like request bodies, the messages aren't part of the generated id
+impl Hash for UnvalidatedStream {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.timeout.hash(state);
+        self.messages.len().hash(state);
+    }
+}
+
+// a message to `send`, or one to wait for which is `receive`d once a message matches it
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct UnvalidatedStreamMessage {
+    pub send: Option<serde_json::Value>,
+    pub receive: Option<serde_json::Value>,
+    pub ignore: Option<Vec<String>>,
+    pub extract: Option<Vec<definition::ResponseExtraction>>,
+    pub timeout: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct UnvalidatedStage {
     pub request: UnvalidatedRequest,
     pub compare: Option<UnvalidatedCompareRequest>,
+    pub stream: Option<UnvalidatedStream>,
     pub response: Option<UnvalidatedResponse>,
     pub variables: Option<Vec<UnvalidatedVariable>>,
+    pub skip: Option<String>,
+    pub only: Option<bool>,
+    pub xfail: Option<String>,
+    #[serde(rename = "if")]
+    pub if_condition: Option<String>,
+    #[serde(rename = "forEach")]
+    pub for_each: Option<String>,
+    #[serde(rename = "as")]
+    pub item: Option<String>,
+    #[serde(rename = "while")]
+    pub while_condition: Option<String>,
+    #[serde(rename = "until")]
+    pub until_condition: Option<String>,
+    #[serde(rename = "maxIterations")]
+    pub max_iterations: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct UnvalidatedRequestResponse {
     pub request: UnvalidatedRequest,
     pub response: Option<UnvalidatedResponse>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+// a single setup step, or a list of steps which run in order before the test's stages
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, JsonSchema)]
+#[serde(untagged)]
+pub enum UnvalidatedSetup {
+    Step(Box<UnvalidatedRequestResponse>),
+    Steps(Vec<UnvalidatedRequestResponse>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct UnvalidatedCleanup {
-    pub onsuccess: Option<UnvalidatedRequest>,
-    pub onfailure: Option<UnvalidatedRequest>,
-    pub always: Option<UnvalidatedRequest>,
+    pub onsuccess: Option<UnvalidatedCleanupSteps>,
+    pub onfailure: Option<UnvalidatedCleanupSteps>,
+    pub always: Option<UnvalidatedCleanupSteps>,
+}
+
+// a single request, or a list of requests which may each expect and extract from a response
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, JsonSchema)]
+#[serde(untagged)]
+pub enum UnvalidatedCleanupSteps {
+    Request(Box<UnvalidatedRequest>),
+    Steps(Vec<UnvalidatedRequestResponse>),
 }
 
 pub fn load(filename: &str) -> Result<test::File, Box<dyn Error + Send + Sync>> {
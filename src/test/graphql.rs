@@ -0,0 +1,120 @@
+use crate::test::{file, validation, variable};
+use serde_json::{json, Value};
+use std::fs;
+
+// the JSON body GraphQL servers expect, with the query read from its file when given one
+pub fn body(
+    graphql: &file::UnvalidatedGraphql,
+    source_path: &str,
+) -> Result<Value, validation::Error> {
+    let query = match (&graphql.query, &graphql.file) {
+        (Some(query), None) => query.clone(),
+        (None, Some(f)) => {
+            let path = variable::resolve_file_path(f, source_path);
+            fs::read_to_string(&path).map_err(|e| validation::Error {
+                reason: format!("unable to read graphql file ({}): {}", path, e),
+            })?
+        }
+        (Some(_), Some(_)) => {
+            return Err(validation::Error {
+                reason: "graphql takes either a `query` or a `file`, not both".to_string(),
+            })
+        }
+        (None, None) => {
+            return Err(validation::Error {
+                reason: "graphql requires a `query` or a `file`".to_string(),
+            })
+        }
+    };
+
+    let mut body = json!({ "query": query });
+
+    if let Some(variables) = &graphql.variables {
+        body["variables"] = variables.clone();
+    }
+
+    if let Some(operation_name) = &graphql.operation_name {
+        body["operationName"] = json!(operation_name);
+    }
+
+    Ok(body)
+}
+
+// the messages in a response's `errors`, which GraphQL servers send with a 200 status
+pub fn errors(body: &Value) -> Vec<String> {
+    match body.get("errors") {
+        Some(Value::Array(errors)) => errors
+            .iter()
+            .map(|e| match e.get("message").and_then(|m| m.as_str()) {
+                Some(message) => message.to_string(),
+                None => e.to_string(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// expected bodies, ignore paths and extractions are relative to `data`
+pub fn data(body: &Value) -> Value {
+    body.get("data").cloned().unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn queries_are_posted_inline_or_from_files_and_responses_unwrapped() {
+        let tmp_dir = tempdir().unwrap();
+        let source_path = format!("{}/", tmp_dir.path().to_str().unwrap());
+        fs::write(
+            tmp_dir.path().join("pet.graphql"),
+            "query Pet($id: ID!) { pet(id: $id) { name } }",
+        )
+        .unwrap();
+
+        let graphql = file::UnvalidatedGraphql {
+            query: None,
+            file: Some("pet.graphql".to_string()),
+            variables: Some(json!({ "id": "${petId}" })),
+            operation_name: Some("Pet".to_string()),
+        };
+        assert_eq!(
+            json!({
+                "query": "query Pet($id: ID!) { pet(id: $id) { name } }",
+                "variables": { "id": "${petId}" },
+                "operationName": "Pet",
+            }),
+            body(&graphql, &source_path).unwrap()
+        );
+
+        let inline = file::UnvalidatedGraphql {
+            query: Some("{ pets { name } }".to_string()),
+            file: None,
+            variables: None,
+            operation_name: None,
+        };
+        assert_eq!(
+            json!({ "query": "{ pets { name } }" }),
+            body(&inline, &source_path).unwrap()
+        );
+
+        let both = file::UnvalidatedGraphql {
+            query: inline.query.clone(),
+            ..graphql
+        };
+        assert!(body(&both, &source_path).is_err());
+
+        let response = json!({
+            "data": { "pet": null },
+            "errors": [{ "message": "pet not found" }, { "path": ["pet"] }],
+        });
+        assert_eq!(
+            vec!["pet not found", "{\"path\":[\"pet\"]}"],
+            errors(&response)
+        );
+        assert_eq!(json!({ "pet": null }), data(&response));
+        assert!(errors(&json!({ "data": {} })).is_empty());
+    }
+}
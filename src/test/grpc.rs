@@ -0,0 +1,345 @@
+use crate::errors::GenericError;
+use crate::test::{file, http, validation, variable};
+use percent_encoding::percent_decode_str;
+use prost::Message as _;
+use prost_reflect::{DescriptorPool, DynamicMessage, MethodDescriptor, SerializeOptions};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+// https://grpc.github.io/grpc/core/md_doc_statuscodes.html
+const CODES: &[&str] = &[
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+// a status code along with its name, for messages
+pub fn code_name(code: u16) -> String {
+    match CODES.get(code as usize) {
+        Some(name) => format!("{} ({})", name, code),
+        None => code.to_string(),
+    }
+}
+
+// a unary method resolved from its descriptors when the test file is loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Method {
+    pub proto: Option<String>,
+    pub descriptor_set: Option<String>,
+    pub import_paths: Vec<String>,
+    // the service's full name and the method, as `package.Service/Method`
+    pub name: String,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    descriptor: Option<MethodDescriptor>,
+}
+
+fn invalid(reason: String) -> validation::Error {
+    validation::Error { reason }
+}
+
+// imports are searched for in the import paths, then next to the proto file as protoc
+// would with `-I` set to its directory
+fn parse_proto(path: &str, import_paths: &[String]) -> Result<DescriptorPool, validation::Error> {
+    let directory = match Path::new(path).parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+
+    let includes = import_paths
+        .iter()
+        .map(Path::new)
+        .chain(std::iter::once(directory));
+
+    protox::Compiler::new(includes)
+        .and_then(|mut compiler| {
+            compiler.include_imports(true).open_file(path)?;
+            Ok(compiler.descriptor_pool())
+        })
+        .map_err(|e| invalid(format!("unable to parse proto file ({}): {}", path, e)))
+}
+
+fn read_descriptor_set(path: &str) -> Result<DescriptorPool, validation::Error> {
+    let bytes = fs::read(path)
+        .map_err(|e| invalid(format!("unable to read descriptor set ({}): {}", path, e)))?;
+
+    DescriptorPool::decode(bytes.as_slice())
+        .map_err(|e| invalid(format!("invalid descriptor set ({}): {}", path, e)))
+}
+
+// methods are named `package.Service/Method`, or `package.Service.Method` as they are in
+// the descriptors
+fn find_method(pool: &DescriptorPool, name: &str) -> Result<MethodDescriptor, validation::Error> {
+    let (service_name, method_name) = name
+        .trim_start_matches('/')
+        .rsplit_once('/')
+        .or_else(|| name.rsplit_once('.'))
+        .ok_or_else(|| {
+            invalid(format!(
+                "grpc method `{}` should be given as `package.Service/Method`",
+                name
+            ))
+        })?;
+
+    let service = pool.get_service_by_name(service_name).ok_or_else(|| {
+        let services: Vec<String> = pool.services().map(|s| s.full_name().to_string()).collect();
+        invalid(format!(
+            "unknown grpc service `{}`, expected one of: {}",
+            service_name,
+            services.join(", ")
+        ))
+    })?;
+
+    let method = service
+        .methods()
+        .find(|m| m.name() == method_name)
+        .ok_or_else(|| {
+            invalid(format!(
+                "grpc service `{}` has no method `{}`",
+                service_name, method_name
+            ))
+        })?;
+
+    if method.is_client_streaming() || method.is_server_streaming() {
+        return Err(invalid(format!(
+            "grpc method `{}` streams, only unary methods are supported",
+            name
+        )));
+    }
+
+    Ok(method)
+}
+
+impl Method {
+    pub fn new(
+        grpc: &file::UnvalidatedGrpc,
+        source_path: &str,
+    ) -> Result<Method, validation::Error> {
+        let import_paths: Vec<String> = grpc
+            .import_paths
+            .iter()
+            .flatten()
+            .map(|p| variable::resolve_file_path(p, source_path))
+            .collect();
+
+        let (proto, descriptor_set, pool) = match (&grpc.proto, &grpc.descriptor_set) {
+            (Some(p), None) => {
+                let path = variable::resolve_file_path(p, source_path);
+                let pool = parse_proto(&path, &import_paths)?;
+                (Some(path), None, pool)
+            }
+            (None, Some(d)) => {
+                let path = variable::resolve_file_path(d, source_path);
+                let pool = read_descriptor_set(&path)?;
+                (None, Some(path), pool)
+            }
+            (Some(_), Some(_)) => {
+                return Err(invalid(
+                    "grpc takes either a `proto` or a `descriptorSet`, not both".to_string(),
+                ))
+            }
+            (None, None) => {
+                return Err(invalid(
+                    "grpc requires a `proto` or a `descriptorSet`".to_string(),
+                ))
+            }
+        };
+
+        let descriptor = find_method(&pool, &grpc.method)?;
+
+        Ok(Method {
+            proto,
+            descriptor_set,
+            import_paths,
+            name: format!(
+                "{}/{}",
+                descriptor.parent_service().full_name(),
+                descriptor.name()
+            ),
+            descriptor: Some(descriptor),
+        })
+    }
+
+    fn descriptor(&self) -> Result<&MethodDescriptor, GenericError> {
+        self.descriptor.as_ref().ok_or_else(|| GenericError {
+            reason: format!("descriptors for grpc method `{}` aren't loaded", self.name),
+        })
+    }
+
+    // calls are posted to the method's path on the server
+    pub fn url(&self, server: &str) -> String {
+        format!("{}/{}", server.trim_end_matches('/'), self.name)
+    }
+
+    // the JSON message encoded as protobuf and framed with a compression flag and its length
+    pub fn encode(&self, message: &Value) -> Result<Vec<u8>, GenericError> {
+        let message =
+            DynamicMessage::deserialize(self.descriptor()?.input(), message).map_err(|e| {
+                GenericError {
+                    reason: format!("unable to encode grpc message for `{}`: {}", self.name, e),
+                }
+            })?;
+        let bytes = message.encode_to_vec();
+
+        let mut frame = Vec::with_capacity(bytes.len() + 5);
+        frame.push(0);
+        frame.extend((bytes.len() as u32).to_be_bytes());
+        frame.extend(bytes);
+        Ok(frame)
+    }
+
+    // the response message mapped to JSON with every field present, so fields holding their
+    // default value can be asserted on too. a response with an error status has no message
+    pub fn decode(&self, frame: &[u8]) -> Result<Value, GenericError> {
+        if frame.is_empty() {
+            return Ok(Value::Null);
+        }
+
+        let truncated = || GenericError {
+            reason: format!("truncated grpc message from `{}`", self.name),
+        };
+
+        if frame[0] != 0 {
+            return Err(GenericError {
+                reason: format!(
+                    "grpc message from `{}` is compressed, which isn't supported",
+                    self.name
+                ),
+            });
+        }
+
+        let length = frame
+            .get(1..5)
+            .map(|l| u32::from_be_bytes([l[0], l[1], l[2], l[3]]) as usize)
+            .ok_or_else(truncated)?;
+        let bytes = frame.get(5..5 + length).ok_or_else(truncated)?;
+
+        let message = DynamicMessage::decode(self.descriptor()?.output(), bytes).map_err(|e| {
+            GenericError {
+                reason: format!("unable to decode grpc message from `{}`: {}", self.name, e),
+            }
+        })?;
+
+        message
+            .serialize_with_options(
+                serde_json::value::Serializer,
+                &SerializeOptions::new().skip_default_fields(false),
+            )
+            .map_err(|e| GenericError {
+                reason: format!("unable to decode grpc message from `{}`: {}", self.name, e),
+            })
+    }
+}
+
+// the status is sent in the trailers, or the headers of a response without a message.
+// when it's missing it's derived from the HTTP status, as the gRPC spec describes
+pub fn status(metadata: &[http::Header], http_status: u16) -> u16 {
+    match metadata
+        .iter()
+        .find(|h| h.header.eq_ignore_ascii_case("grpc-status"))
+        .and_then(|h| h.value.parse().ok())
+    {
+        Some(status) => status,
+        None => match http_status {
+            400 => 13,
+            401 => 16,
+            403 => 7,
+            404 => 12,
+            429 | 502 | 503 | 504 => 14,
+            _ => 2,
+        },
+    }
+}
+
+// the error message sent along with the status, percent encoded
+pub fn message(metadata: &[http::Header]) -> String {
+    metadata
+        .iter()
+        .find(|h| h.header.eq_ignore_ascii_case("grpc-message"))
+        .map(|h| percent_decode_str(&h.value).decode_utf8_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn methods_are_loaded_from_protos_and_messages_round_trip() {
+        let tmp_dir = tempdir().unwrap();
+        let source_path = format!("{}/", tmp_dir.path().to_str().unwrap());
+        fs::create_dir(tmp_dir.path().join("common")).unwrap();
+        fs::write(
+            tmp_dir.path().join("common/pet.proto"),
+            "syntax = \"proto3\";\npackage common;\nmessage Pet { int32 id = 1; string name = 2; bool adopted = 3; }\n",
+        )
+        .unwrap();
+        fs::write(
+            tmp_dir.path().join("pets.proto"),
+            "syntax = \"proto3\";\npackage pets.v1;\nimport \"common/pet.proto\";\n\
+             service PetService {\n  rpc Update(common.Pet) returns (common.Pet);\n  rpc Watch(common.Pet) returns (stream common.Pet);\n}\n",
+        )
+        .unwrap();
+
+        let mut grpc = file::UnvalidatedGrpc {
+            proto: Some("pets.proto".to_string()),
+            descriptor_set: None,
+            import_paths: None,
+            method: "pets.v1.PetService.Update".to_string(),
+        };
+        let method = Method::new(&grpc, &source_path).unwrap();
+        assert_eq!("pets.v1.PetService/Update", method.name);
+        assert_eq!(
+            "http://localhost:50051/pets.v1.PetService/Update",
+            method.url("http://localhost:50051/")
+        );
+
+        let frame = method.encode(&json!({ "id": 7, "name": "Rex" })).unwrap();
+        assert_eq!(&[0, 0, 0, 0, 7], &frame[..5]);
+        assert_eq!(
+            json!({ "id": 7, "name": "Rex", "adopted": false }),
+            method.decode(&frame).unwrap()
+        );
+        assert_eq!(Value::Null, method.decode(&[]).unwrap());
+        assert!(method.decode(&frame[..8]).is_err());
+
+        grpc.method = "pets.v1.PetService/Watch".to_string();
+        assert!(Method::new(&grpc, &source_path)
+            .unwrap_err()
+            .reason
+            .contains("only unary methods"));
+
+        grpc.method = "pets.v1.Pets/Update".to_string();
+        assert!(Method::new(&grpc, &source_path)
+            .unwrap_err()
+            .reason
+            .contains("expected one of: pets.v1.PetService"));
+
+        let metadata = vec![
+            http::Header::new("grpc-status".to_string(), "5".to_string()),
+            http::Header::new("grpc-message".to_string(), "pet%207 not found".to_string()),
+        ];
+        assert_eq!(5, status(&metadata, 200));
+        assert_eq!("pet 7 not found", message(&metadata));
+        assert_eq!(12, status(&[], 404));
+        assert_eq!("NOT_FOUND (5)", code_name(5));
+    }
+}
@@ -1,10 +1,14 @@
 use hyper;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize, Serializer};
 use std::cell::Cell;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Header {
     pub header: String,
     pub value: String,
@@ -39,7 +43,8 @@ impl Hash for Header {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Parameter {
     pub param: String,
     pub value: String,
@@ -77,6 +82,27 @@ pub enum Verb {
     Undefined,
 }
 
+// the derived schema would only list the variant names, editors need every accepted spelling
+impl JsonSchema for Verb {
+    fn schema_name() -> String {
+        "Verb".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        let spellings = ["Get", "Post", "Put", "Patch"]
+            .iter()
+            .flat_map(|v| [v.to_string(), v.to_lowercase(), v.to_uppercase()])
+            .map(serde_json::Value::String)
+            .collect();
+
+        Schema::Object(SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            enum_values: Some(spellings),
+            ..Default::default()
+        })
+    }
+}
+
 impl Verb {
     pub fn as_method(&self) -> Method {
         match &self {
@@ -92,6 +118,10 @@ impl Verb {
 pub struct Method(hyper::Method);
 
 impl Method {
+    pub fn from_hyper(method: hyper::Method) -> Method {
+        Method(method)
+    }
+
     pub fn to_hyper(&self) -> hyper::Method {
         self.0.clone()
     }
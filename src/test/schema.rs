@@ -0,0 +1,23 @@
+use crate::test;
+use schemars::schema_for;
+
+// the schema is derived from the serde structures used to load test files, so it always
+// matches what Jikken accepts. a copy is kept in schema/jkt.schema.json for editors
+pub fn generate() -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(&schema_for!(test::File))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn published_schema_is_up_to_date() {
+        let published = include_str!("../../schema/jkt.schema.json");
+        assert_eq!(
+            generate().unwrap().trim(),
+            published.trim(),
+            "schema/jkt.schema.json is out of date, regenerate it with `jk schema`"
+        );
+    }
+}
@@ -13,9 +13,14 @@ pub fn template() -> Result<test::File, Box<dyn Error + Send + Sync>> {
         tags: None,
         requires: None,
         iterate: None,
+        dataset: None,
+        skip: None,
+        only: None,
+        xfail: None,
         setup: None,
         request: Some(new_request()),
         compare: None,
+        stream: None,
         response: Some(new_response()),
         stages: None,
         cleanup: None,
@@ -32,9 +37,14 @@ pub fn template_staged() -> Result<test::File, Box<dyn Error + Send + Sync>> {
         tags: None,
         requires: None,
         iterate: None,
+        dataset: None,
+        skip: None,
+        only: None,
+        xfail: None,
         setup: None,
         request: None,
         compare: None,
+        stream: None,
         response: None,
         stages: Some(vec![new_stage()]),
         cleanup: None,
@@ -51,9 +61,16 @@ pub fn template_full() -> Result<test::File, Box<dyn Error + Send + Sync>> {
         tags: Some("".to_string()),
         requires: Some("".to_string()),
         iterate: Some(1),
-        setup: Some(new_full_request_response()?),
+        dataset: None,
+        skip: None,
+        only: None,
+        xfail: None,
+        setup: Some(file::UnvalidatedSetup::Step(Box::new(
+            new_full_request_response()?,
+        ))),
         request: Some(new_full_request()?),
         compare: Some(new_full_compare()?),
+        stream: None,
         response: Some(new_full_response()?),
         stages: Some(vec![new_full_stage()?]),
         cleanup: Some(new_full_cleanup()?),
@@ -63,9 +80,15 @@ pub fn template_full() -> Result<test::File, Box<dyn Error + Send + Sync>> {
 
 fn new_full_cleanup() -> Result<file::UnvalidatedCleanup, Box<dyn Error + Send + Sync>> {
     Ok(file::UnvalidatedCleanup {
-        onsuccess: Some(new_full_request()?),
-        onfailure: Some(new_full_request()?),
-        always: Some(new_full_request()?),
+        onsuccess: Some(file::UnvalidatedCleanupSteps::Request(Box::new(
+            new_full_request()?,
+        ))),
+        onfailure: Some(file::UnvalidatedCleanupSteps::Request(Box::new(
+            new_full_request()?,
+        ))),
+        always: Some(file::UnvalidatedCleanupSteps::Steps(vec![
+            new_full_request_response()?,
+        ])),
     })
 }
 
@@ -81,8 +104,18 @@ fn new_stage() -> file::UnvalidatedStage {
     file::UnvalidatedStage {
         request: new_request(),
         compare: None,
+        stream: None,
         response: Some(new_response()),
         variables: None,
+        skip: None,
+        only: None,
+        xfail: None,
+        if_condition: None,
+        for_each: None,
+        item: None,
+        while_condition: None,
+        until_condition: None,
+        max_iterations: None,
     }
 }
 
@@ -90,8 +123,18 @@ fn new_full_stage() -> Result<file::UnvalidatedStage, Box<dyn Error + Send + Syn
     Ok(file::UnvalidatedStage {
         request: new_full_request()?,
         compare: Some(new_full_compare()?),
+        stream: None,
         response: Some(new_full_response()?),
         variables: Some(vec![new_full_variable()?]),
+        skip: None,
+        only: None,
+        xfail: None,
+        if_condition: None,
+        for_each: None,
+        item: None,
+        while_condition: None,
+        until_condition: None,
+        max_iterations: None,
     })
 }
 
@@ -137,6 +180,13 @@ fn new_full_compare() -> Result<file::UnvalidatedCompareRequest, Box<dyn Error +
         add_headers: Some(vec![new_header()]),
         ignore_headers: Some(vec!["".to_string()]),
         body: Some(serde_json::from_str("{}")?),
+        response: Some(file::UnvalidatedCompareResponse {
+            ignore: Some(vec!["".to_string()]),
+            headers: Some(vec!["".to_string()]),
+            ignore_headers: Some(vec!["".to_string()]),
+            tolerance: Some(0.0),
+            unordered_arrays: Some(false),
+        }),
     })
 }
 
@@ -147,6 +197,8 @@ fn new_request() -> file::UnvalidatedRequest {
         params: None,
         headers: None,
         body: None,
+        graphql: None,
+        grpc: None,
     }
 }
 
@@ -157,6 +209,8 @@ fn new_full_request() -> Result<file::UnvalidatedRequest, Box<dyn Error + Send +
         params: Some(vec![new_parameter()]),
         headers: Some(vec![new_header()]),
         body: Some(serde_json::from_str("{}")?),
+        graphql: None,
+        grpc: None,
     })
 }
 
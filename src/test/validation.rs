@@ -1,7 +1,17 @@
+pub mod document;
+
+use crate::executor::load;
+use crate::executor::suite;
 use crate::test;
+use crate::test::condition;
+use crate::test::dataset;
 use crate::test::definition;
 use crate::test::variable;
+use document::{Node, NodeKind};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::path::Path;
+use url::Url;
 
 #[derive(Debug, Clone)]
 pub struct Error {
@@ -20,10 +30,67 @@ impl fmt::Display for Error {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub filename: String,
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.filename, self.line, self.column, severity, self.message
+        )
+    }
+}
+
 fn validate_test_file(
-    _file: &test::File,
+    file: &test::File,
     _global_variables: &[test::Variable],
 ) -> Result<bool, Error> {
+    if file.request.is_none() {
+        if file.compare.is_some() {
+            return Err(Error {
+                reason: "`compare` requires a top-level `request`".to_string(),
+            });
+        }
+
+        if file.response.is_some() {
+            return Err(Error {
+                reason: "`response` requires a top-level `request`".to_string(),
+            });
+        }
+    }
+
+    if file.iterate.is_some() && file.dataset.is_some() {
+        return Err(Error {
+            reason: "`iterate` can't be used with `dataset`, which runs an iteration per row"
+                .to_string(),
+        });
+    }
+
+    if file.iterate == Some(0) {
+        return Err(Error {
+            reason: "`iterate` must be at least 1".to_string(),
+        });
+    }
+
     Ok(true)
 }
 
@@ -44,35 +111,1435 @@ pub fn validate_file(
     };
 
     let generated_id = file.generate_id();
+    let source_path = variable::parse_source_path(&file.filename);
+    let rows = match &file.dataset {
+        Some(d) => dataset::load(d, &file.filename)?,
+        None => Vec::new(),
+    };
+
+    // each column becomes a variable holding a value per row, ahead of the test's own
+    let mut variables: Vec<test::Variable> = dataset::columns(&rows)
+        .into_iter()
+        .map(|column| test::Variable {
+            value: serde_yaml::Value::Sequence(
+                rows.iter()
+                    .map(|r| {
+                        let value = r.values.iter().find(|(k, _)| *k == column);
+                        serde_yaml::Value::String(value.map(|(_, v)| v.clone()).unwrap_or_default())
+                    })
+                    .collect(),
+            ),
+            name: column,
+            data_type: variable::Type::String,
+            modifier: None,
+            format: None,
+            file: None,
+            source_path: source_path.clone(),
+        })
+        .collect();
+    variables.extend(test::Variable::validate_variables_opt(
+        file.variables,
+        &source_path,
+    )?);
 
     let td = test::Definition {
         name: file.name,
         id: file.id.unwrap_or(generated_id).to_lowercase(),
         environment: file.env,
-        requires: file.requires,
+        requires: file.requires.map(|r| r.to_lowercase()),
         tags: new_tags,
-        iterate: file.iterate.unwrap_or(1),
-        variables: test::Variable::validate_variables_opt(
-            file.variables,
-            &variable::parse_source_path(&file.filename),
-        )?,
+        iterate: if rows.is_empty() {
+            file.iterate.unwrap_or(1)
+        } else {
+            rows.len() as u32
+        },
+        labels: rows.into_iter().map(|r| r.label).collect(),
+        skip: file.skip,
+        only: file.only.unwrap_or(false),
+        xfail: file.xfail,
+        variables,
         global_variables: global_variables.to_vec(),
         stages: definition::StageDescriptor::validate_stages_opt(
             file.request,
             file.compare,
+            file.stream,
             file.response,
             file.stages,
-            &variable::parse_source_path(&file.filename),
+            &source_path,
         )?,
-        setup: definition::RequestResponseDescriptor::new_opt(file.setup)?,
-        cleanup: definition::CleanupDescriptor::new(file.cleanup)?,
+        setup: definition::RequestResponseDescriptor::new_setup(file.setup, &source_path)?,
+        cleanup: definition::CleanupDescriptor::new(file.cleanup, &source_path)?,
+        filename: file.filename,
     };
 
     td.update_variable_matching();
     Ok(td)
 }
 
-// this method is intended to do a thorough validation of rules and logic in the resolved test definition
-pub fn _validate_definition(_test_definition: &test::Definition) -> Result<bool, Error> {
-    Ok(true)
+#[derive(Clone, Copy)]
+enum Shape {
+    File,
+    Suite,
+    Request,
+    Graphql,
+    Grpc,
+    Compare,
+    CompareResponse,
+    Stream,
+    StreamMessage,
+    Response,
+    Stage,
+    RequestResponse,
+    Cleanup,
+    CleanupStep,
+    Variable,
+    Dataset,
+    Modifier,
+    Header,
+    Parameter,
+    Extraction,
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Scalar,
+    Strings,
+    Any,
+    One(Shape),
+    List(Shape),
+    // a single mapping of the first shape or a list of the second
+    OneOrList(Shape, Shape),
+}
+
+impl Shape {
+    fn name(&self) -> &'static str {
+        match self {
+            Shape::File => "test file",
+            Shape::Suite => "suite file",
+            Shape::Request => "request",
+            Shape::Graphql => "graphql",
+            Shape::Grpc => "grpc",
+            Shape::Compare => "compare",
+            Shape::CompareResponse => "compare response",
+            Shape::Stream => "stream",
+            Shape::StreamMessage => "stream message",
+            Shape::Response => "response",
+            Shape::Stage => "stage",
+            Shape::RequestResponse => "setup",
+            Shape::Cleanup => "cleanup",
+            Shape::CleanupStep => "cleanup step",
+            Shape::Variable => "variable",
+            Shape::Dataset => "dataset",
+            Shape::Modifier => "modifier",
+            Shape::Header => "header",
+            Shape::Parameter => "parameter",
+            Shape::Extraction => "extract",
+        }
+    }
+
+    // keys must stay in sync with the serde field names in test::File and test::file
+    fn fields(&self) -> &'static [(&'static str, Field)] {
+        match self {
+            Shape::File => &[
+                ("name", Field::Scalar),
+                ("id", Field::Scalar),
+                ("env", Field::Scalar),
+                ("tags", Field::Scalar),
+                ("requires", Field::Scalar),
+                ("iterate", Field::Scalar),
+                ("dataset", Field::One(Shape::Dataset)),
+                ("skip", Field::Scalar),
+                ("only", Field::Scalar),
+                ("xfail", Field::Scalar),
+                (
+                    "setup",
+                    Field::OneOrList(Shape::RequestResponse, Shape::RequestResponse),
+                ),
+                ("request", Field::One(Shape::Request)),
+                ("compare", Field::One(Shape::Compare)),
+                ("stream", Field::One(Shape::Stream)),
+                ("response", Field::One(Shape::Response)),
+                ("stages", Field::List(Shape::Stage)),
+                ("cleanup", Field::One(Shape::Cleanup)),
+                ("variables", Field::List(Shape::Variable)),
+            ],
+            // keys must stay in sync with the serde field names in executor::suite
+            Shape::Suite => &[
+                ("setup", Field::List(Shape::Stage)),
+                ("teardown", Field::List(Shape::Stage)),
+                ("variables", Field::List(Shape::Variable)),
+            ],
+            Shape::Request => &[
+                ("method", Field::Scalar),
+                ("url", Field::Scalar),
+                ("params", Field::List(Shape::Parameter)),
+                ("headers", Field::List(Shape::Header)),
+                ("body", Field::Any),
+                ("graphql", Field::One(Shape::Graphql)),
+                ("grpc", Field::One(Shape::Grpc)),
+            ],
+            Shape::Graphql => &[
+                ("query", Field::Scalar),
+                ("file", Field::Scalar),
+                ("variables", Field::Any),
+                ("operationName", Field::Scalar),
+            ],
+            Shape::Grpc => &[
+                ("proto", Field::Scalar),
+                ("descriptorSet", Field::Scalar),
+                ("importPaths", Field::Strings),
+                ("method", Field::Scalar),
+            ],
+            Shape::Compare => &[
+                ("method", Field::Scalar),
+                ("url", Field::Scalar),
+                ("params", Field::List(Shape::Parameter)),
+                ("addParams", Field::List(Shape::Parameter)),
+                ("ignoreParams", Field::Strings),
+                ("headers", Field::List(Shape::Header)),
+                ("addHeaders", Field::List(Shape::Header)),
+                ("ignoreHeaders", Field::Strings),
+                ("body", Field::Any),
+                ("response", Field::One(Shape::CompareResponse)),
+            ],
+            Shape::CompareResponse => &[
+                ("ignore", Field::Strings),
+                ("headers", Field::Strings),
+                ("ignoreHeaders", Field::Strings),
+                ("tolerance", Field::Scalar),
+                ("unorderedArrays", Field::Scalar),
+            ],
+            Shape::Stream => &[
+                ("timeout", Field::Scalar),
+                ("messages", Field::List(Shape::StreamMessage)),
+            ],
+            Shape::StreamMessage => &[
+                ("send", Field::Any),
+                ("receive", Field::Any),
+                ("ignore", Field::Strings),
+                ("extract", Field::List(Shape::Extraction)),
+                ("timeout", Field::Scalar),
+            ],
+            Shape::Response => &[
+                ("status", Field::Scalar),
+                ("headers", Field::List(Shape::Header)),
+                ("body", Field::Any),
+                ("ignore", Field::Strings),
+                ("extract", Field::List(Shape::Extraction)),
+            ],
+            Shape::Stage => &[
+                ("request", Field::One(Shape::Request)),
+                ("compare", Field::One(Shape::Compare)),
+                ("stream", Field::One(Shape::Stream)),
+                ("response", Field::One(Shape::Response)),
+                ("variables", Field::List(Shape::Variable)),
+                ("skip", Field::Scalar),
+                ("only", Field::Scalar),
+                ("xfail", Field::Scalar),
+                ("if", Field::Scalar),
+                ("forEach", Field::Scalar),
+                ("as", Field::Scalar),
+                ("while", Field::Scalar),
+                ("until", Field::Scalar),
+                ("maxIterations", Field::Scalar),
+            ],
+            Shape::RequestResponse => &[
+                ("request", Field::One(Shape::Request)),
+                ("response", Field::One(Shape::Response)),
+            ],
+            Shape::Cleanup => &[
+                (
+                    "onsuccess",
+                    Field::OneOrList(Shape::Request, Shape::CleanupStep),
+                ),
+                (
+                    "onfailure",
+                    Field::OneOrList(Shape::Request, Shape::CleanupStep),
+                ),
+                (
+                    "always",
+                    Field::OneOrList(Shape::Request, Shape::CleanupStep),
+                ),
+            ],
+            Shape::CleanupStep => &[
+                ("request", Field::One(Shape::Request)),
+                ("response", Field::One(Shape::Response)),
+            ],
+            Shape::Variable => &[
+                ("name", Field::Scalar),
+                ("dataType", Field::Scalar),
+                ("value", Field::Any),
+                ("modifier", Field::One(Shape::Modifier)),
+                ("format", Field::Scalar),
+                ("file", Field::Scalar),
+            ],
+            Shape::Dataset => &[("file", Field::Scalar), ("label", Field::Scalar)],
+            Shape::Modifier => &[
+                ("operation", Field::Scalar),
+                ("value", Field::Scalar),
+                ("unit", Field::Scalar),
+            ],
+            Shape::Header => &[("header", Field::Scalar), ("value", Field::Scalar)],
+            Shape::Parameter => &[("param", Field::Scalar), ("value", Field::Scalar)],
+            Shape::Extraction => &[("name", Field::Scalar), ("field", Field::Scalar)],
+        }
+    }
+
+    fn required(&self) -> &'static [&'static str] {
+        match self {
+            Shape::Request | Shape::Compare => &["url"],
+            Shape::Stage | Shape::RequestResponse | Shape::CleanupStep => &["request"],
+            Shape::Grpc => &["method"],
+            Shape::Stream => &["messages"],
+            Shape::Variable => &["name"],
+            Shape::Dataset => &["file"],
+            Shape::Modifier => &["operation", "value", "unit"],
+            Shape::Header => &["header", "value"],
+            Shape::Parameter => &["param", "value"],
+            Shape::Extraction => &["name", "field"],
+            _ => &[],
+        }
+    }
+}
+
+const VERBS: &[&str] = &["Get", "Post", "Put", "Patch"];
+const DATA_TYPES: &[&str] = &["Int", "String", "Date", "Datetime"];
+
+struct Source {
+    filename: String,
+    text: String,
+    root: Option<Node>,
+}
+
+struct Linter<'a> {
+    source: &'a Source,
+    defined_variables: &'a HashSet<String>,
+    diagnostics: Vec<Diagnostic>,
+    // set while checking a stage or step whose request is a grpc call, as its response
+    // status is a grpc status
+    grpc: bool,
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, node: &Node, severity: Severity, message: String) {
+        self.report_at(node.line, node.column, severity, message);
+    }
+
+    fn report_at(&mut self, line: usize, column: usize, severity: Severity, message: String) {
+        self.diagnostics.push(Diagnostic {
+            filename: self.source.filename.clone(),
+            line,
+            column,
+            severity,
+            message,
+        });
+    }
+
+    // serde may still reject the file for type mismatches, only surface that when our
+    // own checks found nothing, as they point more precisely at the problem
+    fn check_deserializes<T>(&mut self, parsed: Result<T, serde_yaml::Error>) {
+        let has_errors = self
+            .diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error);
+
+        if let (false, Err(e)) = (has_errors, parsed) {
+            let (line, column) = e
+                .location()
+                .map(|l| (l.line(), l.column()))
+                .unwrap_or((1, 1));
+            self.report_at(line, column, Severity::Error, e.to_string());
+        }
+    }
+
+    fn check_shape(&mut self, node: &Node, shape: Shape) {
+        let entries = match &node.kind {
+            NodeKind::Mapping(entries) => entries,
+            _ => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!("expected a mapping for {}", shape.name()),
+                );
+                return;
+            }
+        };
+
+        let fields = shape.fields();
+        let outer_grpc = self.grpc;
+
+        if matches!(
+            shape,
+            Shape::File | Shape::Stage | Shape::RequestResponse | Shape::CleanupStep
+        ) {
+            self.grpc = node.get("request").and_then(|r| r.get("grpc")).is_some();
+        }
+
+        for (key, value) in entries.iter() {
+            let key_name = key.as_str().unwrap_or_default();
+            match fields.iter().find(|(f, _)| *f == key_name) {
+                Some((_, field)) => {
+                    self.check_field(shape, key_name, value, *field);
+                }
+                None => {
+                    let expected: Vec<&str> = fields.iter().map(|(f, _)| *f).collect();
+                    self.report(
+                        key,
+                        Severity::Error,
+                        format!(
+                            "unknown key `{}` in {}, expected one of: {}",
+                            key_name,
+                            shape.name(),
+                            expected.join(", ")
+                        ),
+                    );
+                }
+            }
+        }
+
+        self.grpc = outer_grpc;
+
+        for required in shape.required() {
+            if node.get(required).is_none() {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!("{} is missing required key `{}`", shape.name(), required),
+                );
+            }
+        }
+
+        match shape {
+            Shape::File => {
+                self.check_top_level(node);
+                self.check_grpc_compare(node);
+                self.check_stream(node);
+            }
+            Shape::Stage => {
+                self.check_repeat(node);
+                self.check_grpc_compare(node);
+                self.check_stream(node);
+            }
+            Shape::Compare => self.check_compare(node),
+            Shape::Request => self.check_request(node),
+            Shape::Graphql => self.check_graphql(node),
+            Shape::Grpc => self.check_grpc(node),
+            Shape::StreamMessage => self.check_stream_message(node),
+            _ => {}
+        }
+    }
+
+    fn check_field(&mut self, shape: Shape, key: &str, value: &Node, field: Field) {
+        match field {
+            Field::One(shape) => self.check_shape(value, shape),
+            Field::OneOrList(one, item) => match &value.kind {
+                NodeKind::Sequence(_) => self.check_field(shape, key, value, Field::List(item)),
+                _ => self.check_shape(value, one),
+            },
+            Field::List(shape) => match &value.kind {
+                NodeKind::Sequence(items) => {
+                    for item in items.iter() {
+                        self.check_shape(item, shape);
+                    }
+                }
+                _ => self.report(
+                    value,
+                    Severity::Error,
+                    format!("expected a list for `{}`", key),
+                ),
+            },
+            Field::Strings => match &value.kind {
+                NodeKind::Sequence(items) => {
+                    for item in items.iter() {
+                        self.check_scalar(shape, key, item);
+                    }
+                }
+                _ => self.report(
+                    value,
+                    Severity::Error,
+                    format!("expected a list for `{}`", key),
+                ),
+            },
+            Field::Scalar => self.check_scalar(shape, key, value),
+            Field::Any => self.check_variables(value),
+        }
+    }
+
+    fn check_scalar(&mut self, shape: Shape, key: &str, node: &Node) {
+        let value = match node.as_str() {
+            Some(v) => v,
+            None => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!("expected a single value for `{}`", key),
+                );
+                return;
+            }
+        };
+
+        match key {
+            // a grpc method names an rpc rather than an http verb
+            "method"
+                if !matches!(shape, Shape::Grpc)
+                    && !VERBS.iter().any(|v| {
+                        *v == value || v.to_lowercase() == value || v.to_uppercase() == value
+                    }) =>
+            {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!(
+                        "unsupported http method `{}`, expected one of: {}",
+                        value,
+                        VERBS.join(", ")
+                    ),
+                );
+            }
+            "dataType" if !DATA_TYPES.contains(&value) => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!(
+                        "unknown data type `{}`, expected one of: {}",
+                        value,
+                        DATA_TYPES.join(", ")
+                    ),
+                );
+            }
+            "status" if self.grpc && !matches!(value.parse::<u16>(), Ok(0..=16)) => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!("invalid grpc status `{}`, expected 0 to 16", value),
+                );
+            }
+            "status" if !self.grpc && !matches!(value.parse::<u16>(), Ok(100..=599)) => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!("invalid http status `{}`", value),
+                );
+            }
+            "iterate" if !matches!(value.parse::<u32>(), Ok(1..)) => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!("`iterate` must be a positive number, found `{}`", value),
+                );
+            }
+            "only" | "unorderedArrays" if value != "true" && value != "false" => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!("`{}` must be true or false, found `{}`", key, value),
+                );
+            }
+            "tolerance" if !matches!(value.parse::<f64>(), Ok(t) if t >= 0.0 && t.is_finite()) => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!("`tolerance` must be a positive number, found `{}`", value),
+                );
+            }
+            "if" | "while" | "until" => {
+                if let Err(e) = condition::Condition::parse(value) {
+                    self.report(node, Severity::Error, e.reason);
+                }
+            }
+            "maxIterations" if !matches!(value.parse::<u32>(), Ok(1..)) => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    format!(
+                        "`maxIterations` must be a positive number, found `{}`",
+                        value
+                    ),
+                );
+            }
+            "timeout" => {
+                if let Err(e) = load::parse_duration(value) {
+                    self.report(node, Severity::Error, format!("invalid timeout: {}", e));
+                }
+            }
+            "url" => self.check_url(node, value),
+            _ => {}
+        }
+
+        self.check_variables(node);
+    }
+
+    fn check_url(&mut self, node: &Node, url: &str) {
+        // the scheme and host may be supplied entirely by a variable
+        if url.starts_with("${") {
+            return;
+        }
+
+        let substituted = replace_variables(url, "x");
+        if let Err(e) = Url::parse(&substituted) {
+            self.report(
+                node,
+                Severity::Error,
+                format!("invalid url `{}`: {}", url, e),
+            );
+        }
+    }
+
+    fn check_variables(&mut self, node: &Node) {
+        match &node.kind {
+            NodeKind::Scalar(value) => {
+                let mut search_from = node.index;
+
+                for name in variable_references(value) {
+                    if self.defined_variables.contains(name) {
+                        continue;
+                    }
+
+                    let pattern = format!("${{{}}}", name);
+                    let (line, column) = match find_from(&self.source.text, &pattern, search_from) {
+                        Some(index) => {
+                            search_from = index + 1;
+                            document::position(&self.source.text, index)
+                        }
+                        None => (node.line, node.column),
+                    };
+
+                    self.report_at(
+                        line,
+                        column,
+                        Severity::Error,
+                        format!("undefined variable `{}`", name),
+                    );
+                }
+            }
+            NodeKind::Sequence(items) => {
+                for item in items.iter() {
+                    self.check_variables(item);
+                }
+            }
+            NodeKind::Mapping(entries) => {
+                for (key, value) in entries.iter() {
+                    self.check_variables(key);
+                    self.check_variables(value);
+                }
+            }
+            NodeKind::Alias => {}
+        }
+    }
+
+    fn check_top_level(&mut self, node: &Node) {
+        if let (Some(_), Some((k, _))) = (node.get("dataset"), node.entry("iterate")) {
+            self.report(
+                k,
+                Severity::Error,
+                "`iterate` can't be used with `dataset`, which runs an iteration per row"
+                    .to_string(),
+            );
+        }
+
+        if node.get("request").is_some() {
+            return;
+        }
+
+        for key in ["compare", "stream", "response"] {
+            if let Some((k, _)) = node.entry(key) {
+                self.report(
+                    k,
+                    Severity::Error,
+                    format!("`{}` requires a top-level `request`", key),
+                );
+            }
+        }
+
+        if node.get("stages").is_none() {
+            self.report(
+                node,
+                Severity::Error,
+                "test file must define a `request` or `stages`".to_string(),
+            );
+        }
+    }
+
+    fn check_repeat(&mut self, node: &Node) {
+        let repeats: Vec<&Node> = ["forEach", "while", "until"]
+            .iter()
+            .filter_map(|k| node.entry(k).map(|(key, _)| key))
+            .collect();
+
+        if let Some(second) = repeats.get(1) {
+            self.report(
+                second,
+                Severity::Error,
+                "only one of `forEach`, `while` and `until` can be used".to_string(),
+            );
+        }
+
+        if let (Some((k, _)), None) = (node.entry("as"), node.get("forEach")) {
+            self.report(k, Severity::Error, "`as` requires `forEach`".to_string());
+        }
+    }
+
+    fn check_compare(&mut self, node: &Node) {
+        for (full, partial) in [
+            ("params", ["addParams", "ignoreParams"]),
+            ("headers", ["addHeaders", "ignoreHeaders"]),
+        ] {
+            if node.get(full).is_none() {
+                continue;
+            }
+
+            for key in partial {
+                if let Some((k, _)) = node.entry(key) {
+                    self.report(
+                        k,
+                        Severity::Warning,
+                        format!("`{}` is ignored because `{}` is set", key, full),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_request(&mut self, node: &Node) {
+        let kind = match (node.entry("graphql"), node.entry("grpc")) {
+            (Some(_), Some((grpc, _))) => {
+                self.report(
+                    grpc,
+                    Severity::Error,
+                    "a request takes either `graphql` or `grpc`, not both".to_string(),
+                );
+                return;
+            }
+            (Some((graphql, _)), None) => {
+                if node.get("body").is_some() {
+                    self.report(
+                        graphql,
+                        Severity::Error,
+                        "a request takes either a `body` or `graphql`, not both".to_string(),
+                    );
+                }
+                "graphql requests"
+            }
+            (None, Some(_)) => "grpc calls",
+            (None, None) => return,
+        };
+
+        if let Some(method) = node.get("method") {
+            if !method
+                .as_str()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case("post")
+            {
+                self.report(
+                    method,
+                    Severity::Error,
+                    format!("{} are sent with the POST method", kind),
+                );
+            }
+        }
+    }
+
+    fn check_graphql(&mut self, node: &Node) {
+        match (node.get("query"), node.get("file")) {
+            (Some(_), Some(_)) => self.report(
+                node,
+                Severity::Error,
+                "graphql takes either a `query` or a `file`, not both".to_string(),
+            ),
+            (None, None) => self.report(
+                node,
+                Severity::Error,
+                "graphql requires a `query` or a `file`".to_string(),
+            ),
+            (None, Some(file)) => {
+                let name = file.as_str().unwrap_or_default();
+                let source_path = variable::parse_source_path(&self.source.filename);
+                let path = variable::resolve_file_path(name, &source_path);
+
+                if !Path::new(&path).exists() {
+                    self.report(
+                        file,
+                        Severity::Error,
+                        format!("graphql file `{}` was not found", name),
+                    );
+                }
+            }
+            (Some(_), None) => {}
+        }
+    }
+
+    fn check_grpc(&mut self, node: &Node) {
+        let source_path = variable::parse_source_path(&self.source.filename);
+        let descriptors = match (node.get("proto"), node.get("descriptorSet")) {
+            (Some(_), Some(_)) => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    "grpc takes either a `proto` or a `descriptorSet`, not both".to_string(),
+                );
+                return;
+            }
+            (None, None) => {
+                self.report(
+                    node,
+                    Severity::Error,
+                    "grpc requires a `proto` or a `descriptorSet`".to_string(),
+                );
+                return;
+            }
+            (Some(file), None) | (None, Some(file)) => file,
+        };
+
+        let name = descriptors.as_str().unwrap_or_default();
+        if !Path::new(&variable::resolve_file_path(name, &source_path)).exists() {
+            self.report(
+                descriptors,
+                Severity::Error,
+                format!("grpc file `{}` was not found", name),
+            );
+            return;
+        }
+
+        // the method is looked up in the descriptors, as it is when the test is loaded
+        let method = match node.get("method") {
+            Some(m) => m,
+            None => return,
+        };
+        let scalar = |key: &str| node.get(key).and_then(|n| n.as_str()).map(String::from);
+        let grpc = test::file::UnvalidatedGrpc {
+            proto: scalar("proto"),
+            descriptor_set: scalar("descriptorSet"),
+            import_paths: node.get("importPaths").map(|paths| match &paths.kind {
+                NodeKind::Sequence(items) => items
+                    .iter()
+                    .filter_map(|i| i.as_str().map(String::from))
+                    .collect(),
+                _ => Vec::new(),
+            }),
+            method: method.as_str().unwrap_or_default().to_string(),
+        };
+
+        if let Err(e) = test::grpc::Method::new(&grpc, &source_path) {
+            self.report(method, Severity::Error, e.reason);
+        }
+    }
+
+    // compare requests are plain HTTP, so there's nothing to compare a gRPC call with
+    fn check_grpc_compare(&mut self, node: &Node) {
+        let grpc = node.get("request").and_then(|r| r.get("grpc")).is_some();
+
+        if let (true, Some((compare, _))) = (grpc, node.entry("compare")) {
+            self.report(
+                compare,
+                Severity::Error,
+                "grpc calls can't have a `compare` request".to_string(),
+            );
+        }
+    }
+
+    // a stream is opened by its request and checks its own messages
+    fn check_stream(&mut self, node: &Node) {
+        let stream = match node.get("stream") {
+            Some(s) => s,
+            None => return,
+        };
+
+        for key in ["compare", "response"] {
+            if let Some((k, _)) = node.entry(key) {
+                self.report(
+                    k,
+                    Severity::Error,
+                    format!(
+                        "a stream takes no `{}`, its messages are checked instead",
+                        key
+                    ),
+                );
+            }
+        }
+
+        let request = match node.get("request") {
+            Some(r) => r,
+            None => return,
+        };
+
+        for key in ["body", "graphql", "grpc"] {
+            if let Some((k, _)) = request.entry(key) {
+                self.report(
+                    k,
+                    Severity::Error,
+                    format!("a stream's request takes no `{}`", key),
+                );
+            }
+        }
+
+        if let Some(method) = request.get("method") {
+            if !method
+                .as_str()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case("get")
+            {
+                self.report(
+                    method,
+                    Severity::Error,
+                    "streams are opened with the GET method".to_string(),
+                );
+            }
+        }
+
+        // only WebSockets take messages, a url held by a variable could be either
+        let url = request
+            .get("url")
+            .and_then(|u| u.as_str())
+            .unwrap_or_default();
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return;
+        }
+
+        let messages = stream
+            .get("messages")
+            .map(|m| m.items())
+            .unwrap_or_default();
+        for message in messages.iter() {
+            if let Some((send, _)) = message.entry("send") {
+                self.report(
+                    send,
+                    Severity::Error,
+                    "server-sent event streams can only `receive` messages".to_string(),
+                );
+            }
+        }
+    }
+
+    fn check_stream_message(&mut self, node: &Node) {
+        match (node.entry("send"), node.entry("receive")) {
+            (Some(_), Some((receive, _))) => self.report(
+                receive,
+                Severity::Error,
+                "a stream message takes either `send` or `receive`, not both".to_string(),
+            ),
+            (None, None) => self.report(
+                node,
+                Severity::Error,
+                "a stream message requires `send` or `receive`".to_string(),
+            ),
+            (Some(_), None) => {
+                for key in ["ignore", "extract", "timeout"] {
+                    if let Some((k, _)) = node.entry(key) {
+                        self.report(
+                            k,
+                            Severity::Error,
+                            format!("`{}` only applies to received messages", key),
+                        );
+                    }
+                }
+            }
+            (None, Some(_)) => {}
+        }
+    }
+}
+
+fn replace_variables(value: &str, replacement: &str) -> String {
+    let mut result = value.to_string();
+
+    for name in variable_references(value) {
+        result = result.replace(&format!("${{{}}}", name), replacement);
+    }
+
+    result
+}
+
+fn variable_references(value: &str) -> Vec<&str> {
+    let mut results = Vec::new();
+    let mut remaining = value;
+
+    while let Some(start) = remaining.find("${") {
+        let after = &remaining[start + 2..];
+        match after.find('}') {
+            Some(end) => {
+                results.push(after[..end].trim());
+                remaining = &after[end + 1..];
+            }
+            None => break,
+        }
+    }
+
+    results
+}
+
+// node offsets are byte offsets into the source, so search the bytes after them
+fn find_from(text: &str, pattern: &str, index: usize) -> Option<usize> {
+    text.get(index..)?.find(pattern).map(|i| index + i)
+}
+
+fn collect_extractions(node: &Node, names: &mut HashSet<String>) {
+    match &node.kind {
+        NodeKind::Mapping(entries) => {
+            for (key, value) in entries.iter() {
+                if key.as_str() == Some("extract") {
+                    for item in value.items() {
+                        if let Some(name) = item.get("name").and_then(|n| n.as_str()) {
+                            names.insert(name.to_string());
+                        }
+                    }
+                } else if !matches!(key.as_str(), Some("body" | "send" | "receive")) {
+                    collect_extractions(value, names);
+                }
+            }
+        }
+        NodeKind::Sequence(items) => {
+            for item in items.iter() {
+                collect_extractions(item, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn collect_variables(root: &Node, names: &mut HashSet<String>) {
+    // suite files keep their stages under `setup` and `teardown`
+    let stages: Vec<&Node> = ["stages", "setup", "teardown"]
+        .iter()
+        .filter_map(|key| root.get(key))
+        .flat_map(|s| s.items())
+        .collect();
+    let stage_variables = stages.iter().filter_map(|s| s.get("variables"));
+
+    // stages run with `forEach` bind each element to a variable
+    for stage in stages.iter() {
+        if stage.get("forEach").is_some() {
+            let item = stage.get("as").and_then(|a| a.as_str()).unwrap_or("item");
+            names.insert(item.trim().to_string());
+        }
+    }
+
+    for variables in root.get("variables").into_iter().chain(stage_variables) {
+        for item in variables.items() {
+            if let Some(name) = item.get("name").and_then(|n| n.as_str()) {
+                names.insert(name.trim().to_string());
+            }
+        }
+    }
+}
+
+// the columns of a dataset are bound as variables, so they count as defined
+fn load_dataset_columns<'a>(
+    root: &'a Node,
+    filename: &str,
+    names: &mut HashSet<String>,
+) -> Option<(&'a Node, Error)> {
+    let node = root.get("dataset")?;
+    let dataset = test::file::UnvalidatedDataset {
+        file: node.get("file")?.as_str()?.to_string(),
+        label: node
+            .get("label")
+            .and_then(|l| l.as_str())
+            .map(|l| l.to_string()),
+    };
+
+    match dataset::load(&dataset, filename) {
+        Ok(rows) => {
+            names.extend(dataset::columns(&rows));
+            None
+        }
+        Err(e) => Some((node, e)),
+    }
+}
+
+fn load_source(filename: &str) -> Result<Source, Diagnostic> {
+    let error = |line: usize, column: usize, message: String| Diagnostic {
+        filename: filename.to_string(),
+        line,
+        column,
+        severity: Severity::Error,
+        message,
+    };
+
+    let text = std::fs::read_to_string(filename)
+        .map_err(|e| error(1, 1, format!("unable to read file: {}", e)))?;
+    let root = document::parse(&text)
+        .map_err(|e| error(e.line, e.column, format!("invalid yaml: {}", e.reason)))?;
+
+    Ok(Source {
+        filename: filename.to_string(),
+        text,
+        root,
+    })
+}
+
+// suite files in the directories of the tests and their parents, whose setup may extract
+// variables for the tests
+fn suite_sources(filenames: &[String]) -> Vec<Result<Source, Diagnostic>> {
+    let mut seen = HashSet::new();
+
+    filenames
+        .iter()
+        .filter_map(|f| Path::new(f).parent())
+        .flat_map(|p| p.ancestors())
+        .map(|d| d.join(suite::SUITE_FILE))
+        .filter(|p| p.is_file())
+        .filter(|p| seen.insert(p.canonicalize().unwrap_or(p.clone())))
+        .map(|p| load_source(&p.to_string_lossy()))
+        .collect()
+}
+
+// performs a thorough, source aware validation of a set of test files, reporting every
+// problem found along with its location rather than stopping at the first one
+pub fn validate_sources(
+    filenames: &[String],
+    global_variables: &[test::Variable],
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut sources = Vec::new();
+
+    for filename in filenames.iter() {
+        match load_source(filename) {
+            Ok(source) => sources.push(source),
+            Err(d) => diagnostics.push(d),
+        }
+    }
+
+    // extracted values are stored in shared state, so later tests may reference them
+    let mut extracted = HashSet::new();
+
+    let mut suites = Vec::new();
+
+    for suite in suite_sources(filenames) {
+        match suite {
+            Ok(source) => {
+                if let Some(root) = &source.root {
+                    collect_extractions(root, &mut extracted);
+                }
+                suites.push(source);
+            }
+            Err(d) => diagnostics.push(d),
+        }
+    }
+    let mut ids: HashMap<String, (String, usize)> = HashMap::new();
+
+    for source in sources.iter() {
+        let root = match &source.root {
+            Some(r) => r,
+            None => continue,
+        };
+
+        collect_extractions(root, &mut extracted);
+
+        if let Some(id_node) = root.get("id") {
+            let id = id_node.as_str().unwrap_or_default().to_lowercase();
+            match ids.get(&id) {
+                Some((first_file, first_line)) => diagnostics.push(Diagnostic {
+                    filename: source.filename.clone(),
+                    line: id_node.line,
+                    column: id_node.column,
+                    severity: Severity::Error,
+                    message: format!(
+                        "duplicate test id `{}`, first defined in {}:{}",
+                        id, first_file, first_line
+                    ),
+                }),
+                None => {
+                    ids.insert(id, (source.filename.clone(), id_node.line));
+                }
+            }
+        }
+    }
+
+    for source in sources.iter() {
+        let root = match &source.root {
+            Some(r) => r,
+            None => {
+                diagnostics.push(Diagnostic {
+                    filename: source.filename.clone(),
+                    line: 1,
+                    column: 1,
+                    severity: Severity::Error,
+                    message: "test file is empty".to_string(),
+                });
+                continue;
+            }
+        };
+
+        let mut defined_variables: HashSet<String> =
+            global_variables.iter().map(|v| v.name.clone()).collect();
+        defined_variables.extend(extracted.iter().cloned());
+        collect_variables(root, &mut defined_variables);
+        let dataset_error = load_dataset_columns(root, &source.filename, &mut defined_variables);
+
+        let mut linter = Linter {
+            source,
+            defined_variables: &defined_variables,
+            diagnostics: Vec::new(),
+            grpc: false,
+        };
+        linter.check_shape(root, Shape::File);
+
+        if let Some((node, e)) = dataset_error {
+            linter.report(node, Severity::Error, e.reason);
+        }
+
+        if let Some(requires) = root.get("requires") {
+            let required = requires.as_str().unwrap_or_default().to_lowercase();
+            if !ids.contains_key(&required) {
+                linter.report(
+                    requires,
+                    Severity::Error,
+                    format!("required test `{}` was not found", required),
+                );
+            }
+        }
+
+        linter.check_deserializes(serde_yaml::from_str::<test::File>(&source.text));
+        diagnostics.append(&mut linter.diagnostics);
+    }
+
+    // an empty suite file has nothing to run, so only suites with content are checked
+    for source in suites.iter() {
+        let root = match &source.root {
+            Some(r) => r,
+            None => continue,
+        };
+
+        let mut defined_variables: HashSet<String> =
+            global_variables.iter().map(|v| v.name.clone()).collect();
+        defined_variables.extend(extracted.iter().cloned());
+        collect_variables(root, &mut defined_variables);
+
+        let mut linter = Linter {
+            source,
+            defined_variables: &defined_variables,
+            diagnostics: Vec::new(),
+            grpc: false,
+        };
+        linter.check_shape(root, Shape::Suite);
+        linter.check_deserializes(suite::parse(&source.text));
+        diagnostics.append(&mut linter.diagnostics);
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn validate(files: &[(&str, &str)]) -> Vec<Diagnostic> {
+        let tmp_dir = tempdir().unwrap();
+        let filenames: Vec<String> = files
+            .iter()
+            .map(|(name, contents)| {
+                let path = tmp_dir.path().join(name);
+                let mut f = std::fs::File::create(&path).unwrap();
+                f.write_all(contents.as_bytes()).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        validate_sources(&filenames, &[])
+    }
+
+    fn locations(diagnostics: &[Diagnostic]) -> Vec<(usize, usize)> {
+        diagnostics.iter().map(|d| (d.line, d.column)).collect()
+    }
+
+    #[test]
+    fn valid_file_has_no_diagnostics() {
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "id: a\nrequest:\n  method: post\n  url: https://jikken.io/${path}\n  body: {\"a\": 1}\nresponse:\n  status: 200\nvariables:\n  - name: path\n    value: status\n",
+        )]);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn unknown_keys_and_bad_verbs_are_located() {
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "request:\n  method: Delete\n  url: https://jikken.io\n  header: []\n",
+        )]);
+        assert_eq!(vec![(2, 11), (4, 3)], locations(&diagnostics));
+        assert!(diagnostics[0].message.contains("unsupported http method"));
+        assert!(diagnostics[1].message.contains("unknown key `header`"));
+    }
+
+    #[test]
+    fn invalid_url_is_reported() {
+        let diagnostics = validate(&[("a.jkt", "request:\n  url: jikken.io/status\n")]);
+        assert_eq!(vec![(2, 8)], locations(&diagnostics));
+    }
+
+    #[test]
+    fn undefined_variables_point_at_usage() {
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "request:\n  url: https://jikken.io/${known}/${unknown}\nvariables:\n  - name: known\n    value: a\n",
+        )]);
+        assert_eq!(vec![(2, 35)], locations(&diagnostics));
+        assert!(diagnostics[0].message.contains("`unknown`"));
+    }
+
+    #[test]
+    fn locations_after_non_ascii_text_count_characters() {
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "name: 日本語のテスト\nrequest:\n  url: https://jikken.io/日本/${unknown}\n",
+        )]);
+        assert_eq!(vec![(3, 29)], locations(&diagnostics));
+    }
+
+    #[test]
+    fn suite_files_of_the_tests_are_validated() {
+        let tmp_dir = tempdir().unwrap();
+        std::fs::write(
+            tmp_dir.path().join(suite::SUITE_FILE),
+            "setup:\n  - request:\n      url: https://jikken.io/login\n    response:\n      extract:\n        - name: token\n          field: token\n    retry: 3\nteardown:\n  - request:\n      url: https://jikken.io/${missing}\n",
+        )
+        .unwrap();
+        let test_file = tmp_dir.path().join("a.jkt");
+        std::fs::write(&test_file, "request:\n  url: https://jikken.io/${token}\n").unwrap();
+
+        let diagnostics = validate_sources(&[test_file.to_str().unwrap().to_string()], &[]);
+
+        assert_eq!(vec![(8, 5), (11, 30)], locations(&diagnostics));
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.filename.ends_with(suite::SUITE_FILE)));
+        assert!(diagnostics[0]
+            .message
+            .contains("unknown key `retry` in stage"));
+        assert!(diagnostics[1].message.contains("`missing`"));
+    }
+
+    #[test]
+    fn cleanup_accepts_a_request_or_a_list_of_steps() {
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "request:\n  url: https://jikken.io\ncleanup:\n  onsuccess:\n    url: https://jikken.io\n  always:\n    - request:\n        url: https://jikken.io\n      response:\n        status: 204\n    - url: https://jikken.io\n",
+        )]);
+        assert_eq!(vec![(11, 7), (11, 7)], locations(&diagnostics));
+        assert!(diagnostics[0]
+            .message
+            .contains("unknown key `url` in cleanup step"));
+        assert!(diagnostics[1]
+            .message
+            .contains("missing required key `request`"));
+    }
+
+    #[test]
+    fn graphql_requests_are_checked() {
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "request:\n  method: Get\n  url: https://jikken.io/graphql\n  body: {}\n  graphql:\n    file: missing.graphql\n    operationName: Pet\n",
+        )]);
+        assert_eq!(vec![(6, 11), (5, 3), (2, 11)], locations(&diagnostics));
+        assert!(diagnostics[0]
+            .message
+            .contains("graphql file `missing.graphql` was not found"));
+        assert!(diagnostics[1]
+            .message
+            .contains("either a `body` or `graphql`"));
+        assert!(diagnostics[2].message.contains("sent with the POST method"));
+    }
+
+    #[test]
+    fn grpc_calls_are_checked() {
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "request:\n  method: Get\n  url: http://localhost:50051\n  grpc:\n    proto: missing.proto\n    method: pets.Pets/Get\ncompare:\n  url: http://localhost:50052\n",
+        )]);
+        assert_eq!(vec![(5, 12), (2, 11), (7, 1)], locations(&diagnostics));
+        assert!(diagnostics[0]
+            .message
+            .contains("grpc file `missing.proto` was not found"));
+        assert!(diagnostics[1]
+            .message
+            .contains("grpc calls are sent with the POST method"));
+        assert!(diagnostics[2]
+            .message
+            .contains("can't have a `compare` request"));
+    }
+
+    #[test]
+    fn streams_are_checked() {
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "request:\n  method: Post\n  url: wss://jikken.io/events\nstream:\n  timeout: soon\n  messages:\n    - send: {\"a\": 1}\n      timeout: 1s\n    - receive: {\"b\": 1}\n      send: {\"c\": 1}\nresponse:\n  status: 200\n",
+        )]);
+        assert_eq!(
+            vec![(5, 12), (8, 7), (9, 7), (11, 1), (2, 11)],
+            locations(&diagnostics)
+        );
+        assert!(diagnostics[0].message.contains("invalid timeout"));
+        assert!(diagnostics[1]
+            .message
+            .contains("`timeout` only applies to received messages"));
+        assert!(diagnostics[2]
+            .message
+            .contains("either `send` or `receive`"));
+        assert!(diagnostics[3]
+            .message
+            .contains("a stream takes no `response`"));
+        assert!(diagnostics[4]
+            .message
+            .contains("opened with the GET method"));
+
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "request:\n  url: https://jikken.io/events\nstream:\n  messages:\n    - send: ping\n",
+        )]);
+        assert_eq!(vec![(5, 7)], locations(&diagnostics));
+        assert!(diagnostics[0].message.contains("can only `receive`"));
+    }
+
+    #[test]
+    fn extracted_variables_are_visible_to_other_files() {
+        let diagnostics = validate(&[
+            (
+                "a.jkt",
+                "id: login\nrequest:\n  url: https://jikken.io\nresponse:\n  extract:\n    - name: token\n      field: auth.token\n",
+            ),
+            (
+                "b.jkt",
+                "requires: login\nrequest:\n  url: https://jikken.io\n  headers:\n    - header: Authorization\n      value: ${token}\n",
+            ),
+        ]);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn duplicate_ids_and_missing_requires_are_reported() {
+        let diagnostics = validate(&[
+            ("a.jkt", "id: same\nrequest:\n  url: https://jikken.io\n"),
+            (
+                "b.jkt",
+                "id: Same\nrequires: other\nrequest:\n  url: https://jikken.io\n",
+            ),
+        ]);
+        assert_eq!(2, diagnostics.len());
+        assert!(diagnostics[0].message.contains("duplicate test id `same`"));
+        assert!(diagnostics[1].message.contains("`other` was not found"));
+        assert_eq!((2, 11), (diagnostics[1].line, diagnostics[1].column));
+    }
+
+    #[test]
+    fn compare_misuse_is_reported() {
+        let diagnostics = validate(&[(
+            "a.jkt",
+            "compare:\n  url: https://jikken.io\n  params:\n    - param: a\n      value: b\n  addParams:\n    - param: c\n      value: d\n",
+        )]);
+        assert_eq!(3, diagnostics.len());
+        assert_eq!(Severity::Warning, diagnostics[0].severity);
+        assert_eq!((6, 3), (diagnostics[0].line, diagnostics[0].column));
+        assert!(diagnostics[1]
+            .message
+            .contains("`compare` requires a top-level `request`"));
+    }
+
+    #[test]
+    fn invalid_yaml_is_located() {
+        let diagnostics = validate(&[("a.jkt", "request:\n  url: [\n")]);
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(3, diagnostics[0].line);
+    }
 }
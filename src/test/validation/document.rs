@@ -0,0 +1,319 @@
+use std::mem::MaybeUninit;
+use std::slice;
+
+// a minimal YAML tree that remembers where each node was found in the source,
+// serde_yaml discards this information once a file has been deserialized
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub index: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    flow: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum NodeKind {
+    Scalar(String),
+    Sequence(Vec<Node>),
+    Mapping(Vec<(Node, Node)>),
+    Alias,
+}
+
+impl Node {
+    fn new(
+        kind: NodeKind,
+        mark: unsafe_libyaml::yaml_mark_t,
+        end: unsafe_libyaml::yaml_mark_t,
+    ) -> Node {
+        Node {
+            kind,
+            index: mark.index as usize,
+            end: end.index as usize,
+            line: mark.line as usize + 1,
+            column: mark.column as usize + 1,
+            flow: false,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.kind {
+            NodeKind::Scalar(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn entry(&self, key: &str) -> Option<(&Node, &Node)> {
+        match &self.kind {
+            NodeKind::Mapping(entries) => entries
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(k, v)| (k, v)),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        self.entry(key).map(|(_, v)| v)
+    }
+
+    // the byte index just past the last scalar or flow collection in this node, unlike
+    // `end` this never includes the comments and whitespace that close a block collection
+    pub fn content_end(&self) -> usize {
+        match &self.kind {
+            NodeKind::Mapping(entries) if !self.is_flow() => entries
+                .last()
+                .map(|(_, v)| v.content_end())
+                .unwrap_or(self.end),
+            NodeKind::Sequence(items) if !self.is_flow() => {
+                items.last().map(|v| v.content_end()).unwrap_or(self.end)
+            }
+            _ => self.end,
+        }
+    }
+
+    pub fn is_flow(&self) -> bool {
+        self.flow
+    }
+
+    pub fn items(&self) -> &[Node] {
+        match &self.kind {
+            NodeKind::Sequence(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+// collections remember where they started and whether they were written in flow style
+enum Frame {
+    Sequence(Vec<Node>, unsafe_libyaml::yaml_mark_t, bool),
+    Mapping(
+        Vec<(Node, Node)>,
+        Option<Node>,
+        unsafe_libyaml::yaml_mark_t,
+        bool,
+    ),
+}
+
+#[derive(Default)]
+struct Builder {
+    stack: Vec<Frame>,
+    root: Option<Node>,
+}
+
+impl Builder {
+    fn push(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            None => {
+                if self.root.is_none() {
+                    self.root = Some(node);
+                }
+            }
+            Some(Frame::Sequence(items, _, _)) => items.push(node),
+            Some(Frame::Mapping(entries, key, _, _)) => match key.take() {
+                Some(k) => entries.push((k, node)),
+                None => *key = Some(node),
+            },
+        }
+    }
+
+    // returns true once the end of the stream has been reached.
+    // the event must come from a successful yaml_parser_parse and not have been deleted yet
+    unsafe fn on_event(&mut self, event: &unsafe_libyaml::yaml_event_t) -> bool {
+        let mark = event.start_mark;
+        let end = event.end_mark;
+
+        match event.type_ {
+            unsafe_libyaml::YAML_SCALAR_EVENT => {
+                // SAFETY: scalar events carry `data.scalar`, whose value libyaml always
+                // allocates (even for empty scalars) and which holds `length` bytes
+                let scalar = event.data.scalar;
+                let bytes = slice::from_raw_parts(scalar.value, scalar.length as usize);
+                let value = String::from_utf8_lossy(bytes).to_string();
+                self.push(Node::new(NodeKind::Scalar(value), mark, end));
+            }
+            unsafe_libyaml::YAML_ALIAS_EVENT => self.push(Node::new(NodeKind::Alias, mark, end)),
+            unsafe_libyaml::YAML_SEQUENCE_START_EVENT => {
+                // SAFETY: sequence start events carry `data.sequence_start`
+                let flow =
+                    event.data.sequence_start.style == unsafe_libyaml::YAML_FLOW_SEQUENCE_STYLE;
+                self.stack.push(Frame::Sequence(Vec::new(), mark, flow))
+            }
+            unsafe_libyaml::YAML_MAPPING_START_EVENT => {
+                // SAFETY: mapping start events carry `data.mapping_start`
+                let flow =
+                    event.data.mapping_start.style == unsafe_libyaml::YAML_FLOW_MAPPING_STYLE;
+                self.stack
+                    .push(Frame::Mapping(Vec::new(), None, mark, flow))
+            }
+            unsafe_libyaml::YAML_SEQUENCE_END_EVENT | unsafe_libyaml::YAML_MAPPING_END_EVENT => {
+                let (kind, m, flow) = match self.stack.pop() {
+                    Some(Frame::Sequence(items, m, flow)) => (NodeKind::Sequence(items), m, flow),
+                    Some(Frame::Mapping(entries, _, m, flow)) => {
+                        (NodeKind::Mapping(entries), m, flow)
+                    }
+                    None => return false,
+                };
+                let mut node = Node::new(kind, m, end);
+                node.flow = flow;
+                self.push(node);
+            }
+            unsafe_libyaml::YAML_STREAM_END_EVENT => return true,
+            _ => {}
+        }
+
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub reason: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+// libyaml only exposes its error details internally, serde_yaml drives the same parser
+// over the same source so it fails in the same place and reports a readable message
+fn parse_error(source: &str) -> ParseError {
+    match serde_yaml::from_str::<serde_yaml::Value>(source) {
+        Err(e) => {
+            let (line, column) = e
+                .location()
+                .map(|l| (l.line(), l.column()))
+                .unwrap_or((1, 1));
+            ParseError {
+                reason: e.to_string(),
+                line,
+                column,
+            }
+        }
+        Ok(_) => ParseError {
+            reason: "unable to parse yaml".to_string(),
+            line: 1,
+            column: 1,
+        },
+    }
+}
+
+// parses the source with libyaml, the parser behind serde_yaml, so anything serde_yaml
+// accepts is accepted here too. returns the first document, or None if the source is empty
+pub fn parse(source: &str) -> Result<Option<Node>, ParseError> {
+    let mut builder = Builder::default();
+    let mut parser = Box::new(MaybeUninit::<unsafe_libyaml::yaml_parser_t>::uninit());
+    let p = parser.as_mut_ptr();
+
+    // SAFETY: yaml_parser_initialize only writes through `p`, and on success every field of
+    // the parser has been set. on failure it has released anything it allocated, so the
+    // parser is never read or deleted
+    if unsafe { unsafe_libyaml::yaml_parser_initialize(p) }.fail {
+        return Err(parse_error(source));
+    }
+
+    // SAFETY: the parser was initialized above, and `source` outlives it since the parser
+    // is deleted before this function returns
+    unsafe {
+        unsafe_libyaml::yaml_parser_set_encoding(p, unsafe_libyaml::YAML_UTF8_ENCODING);
+        unsafe_libyaml::yaml_parser_set_input_string(p, source.as_ptr(), source.len() as u64);
+    }
+
+    let mut failed = false;
+
+    loop {
+        let mut event = MaybeUninit::<unsafe_libyaml::yaml_event_t>::uninit();
+        let e = event.as_mut_ptr();
+
+        // SAFETY: yaml_parser_parse zeroes the event before doing anything else. when it
+        // fails the event is left zeroed and owns nothing, so there is nothing to delete
+        if unsafe { unsafe_libyaml::yaml_parser_parse(p, e) }.fail {
+            failed = true;
+            break;
+        }
+
+        // SAFETY: a successful parse fully initializes the event, on_event only reads the
+        // union field that matches its type, and the event is deleted exactly once after
+        // the builder has copied out what it needs
+        let done = unsafe {
+            let done = builder.on_event(&*e);
+            unsafe_libyaml::yaml_event_delete(e);
+            done
+        };
+
+        if done {
+            break;
+        }
+    }
+
+    // SAFETY: the parser was initialized and is not used again after this
+    unsafe { unsafe_libyaml::yaml_parser_delete(p) };
+
+    if failed {
+        return Err(parse_error(source));
+    }
+
+    Ok(builder.root)
+}
+
+// converts a byte index from a node into a 1-based (line, column) pair, counting columns
+// in characters
+pub fn position(source: &str, index: usize) -> (usize, usize) {
+    let index = index.min(source.len());
+    let line_start = source[..index].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = source[..line_start].matches('\n').count() + 1;
+
+    (line, source[line_start..index].chars().count() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nodes_record_source_positions() {
+        let source = "name: Test\nrequest:\n  method: Get\n  url: https://jikken.io\n";
+        let root = parse(source).ok().flatten().unwrap();
+
+        let (key, value) = root.get("request").unwrap().entry("url").unwrap();
+        assert_eq!((4, 3), (key.line, key.column));
+        assert_eq!((4, 8), (value.line, value.column));
+        assert_eq!(Some("https://jikken.io"), value.as_str());
+        assert_eq!((4, 8), position(source, value.index));
+    }
+
+    #[test]
+    fn scan_errors_have_positions() {
+        let result = parse("name: Test\nrequest: [\n");
+        match result {
+            Err(e) => assert_eq!(3, e.line),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn flow_mappings_may_close_at_key_indentation() {
+        let source = "request:\n  body: {\n    \"a\": 1\n  }\n";
+        let root = parse(source).ok().flatten().unwrap();
+        let body = root.get("request").unwrap().get("body").unwrap();
+        assert_eq!(Some("1"), body.get("a").and_then(|a| a.as_str()));
+    }
+
+    #[test]
+    fn content_ends_exclude_trailing_comments() {
+        let source = "response:\n  status: 200\n  # done\nbody: { \"a\": 1 }\n";
+        let root = parse(source).ok().flatten().unwrap();
+
+        let response = root.get("response").unwrap();
+        assert_eq!("200", &source[response.index + 8..response.content_end()]);
+        assert!(!response.is_flow());
+
+        let body = root.get("body").unwrap();
+        assert!(body.is_flow());
+        assert_eq!("{ \"a\": 1 }", &source[body.index..body.content_end()]);
+    }
+
+    #[test]
+    fn empty_source_has_no_document() {
+        assert!(parse("").ok().flatten().is_none());
+    }
+}
@@ -1,6 +1,8 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, JsonSchema)]
 pub enum Type {
     Int,
     String,
@@ -8,13 +10,8 @@ pub enum Type {
     Datetime,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct Range {
-    pub min: String,
-    pub max: String,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Modifier {
     pub operation: String,
     pub value: String,
@@ -31,7 +28,16 @@ impl Modifier {
     }
 }
 
-pub fn parse_source_path<'a>(path: &'a str) -> String {
+// variable files are relative to the working directory, or failing that, to the test file
+pub fn resolve_file_path(file: &str, source_path: &str) -> String {
+    if Path::new(file).exists() {
+        file.to_string()
+    } else {
+        format!("{}{}", source_path, file)
+    }
+}
+
+pub fn parse_source_path(path: &str) -> String {
     let index = path.rfind("/");
 
     let mut result = match index {
@@ -0,0 +1,695 @@
+use crate::errors::GenericError;
+use crate::executor::select::Filter;
+use crate::executor::{PlannedTest, Progress, Report, RunOptions, StageResult, TestStatus};
+use crate::logger;
+use log::error;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::error::Error;
+use std::io;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// indicatif can only draw progress bars under the regular output. picking a test from the
+// list, drilling into its stages and re-running failures needs a full screen view that
+// reads the keyboard, which is why this is drawn with ratatui
+const POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Running(u32),
+    Passed,
+    Failed,
+    // the run stopped before reaching the test
+    NotRun,
+    // marked `skip` or in a suite whose setup failed
+    Skipped,
+}
+
+struct IterationResult {
+    iteration: u32,
+    passed: bool,
+    results: Vec<StageResult>,
+    messages: Vec<String>,
+}
+
+struct Row {
+    test: PlannedTest,
+    status: Status,
+    iterations: Vec<IterationResult>,
+    skip_reason: Option<String>,
+}
+
+struct App {
+    rows: Vec<Row>,
+    // rows of the tests planned by the current run, in the order the executor reports them
+    plan: Vec<usize>,
+    list: ListState,
+    scroll: u16,
+    messages: Vec<String>,
+}
+
+impl App {
+    fn new() -> App {
+        App {
+            rows: Vec::new(),
+            plan: Vec::new(),
+            list: ListState::default(),
+            scroll: 0,
+            messages: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, progress: Progress) {
+        match progress {
+            Progress::Planned(tests) => {
+                self.plan = tests
+                    .into_iter()
+                    .map(|test| {
+                        let index = match self.rows.iter().position(|r| r.test.id == test.id) {
+                            Some(i) => i,
+                            None => {
+                                self.rows.push(Row {
+                                    test: test.clone(),
+                                    status: Status::Pending,
+                                    iterations: Vec::new(),
+                                    skip_reason: None,
+                                });
+                                self.rows.len() - 1
+                            }
+                        };
+
+                        let row = &mut self.rows[index];
+                        row.test = test;
+                        row.status = Status::Pending;
+                        row.iterations.clear();
+                        row.skip_reason = None;
+                        index
+                    })
+                    .collect();
+
+                if self.list.selected().is_none() && !self.rows.is_empty() {
+                    self.list.select(Some(0));
+                }
+            }
+            Progress::Started { test, iteration } => {
+                if let Some(row) = self.plan.get(test).and_then(|i| self.rows.get_mut(*i)) {
+                    row.status = Status::Running(iteration);
+                }
+            }
+            Progress::Finished {
+                test,
+                iteration,
+                passed,
+                results,
+                messages,
+            } => {
+                if let Some(row) = self.plan.get(test).and_then(|i| self.rows.get_mut(*i)) {
+                    if !passed {
+                        row.status = Status::Failed;
+                    } else if iteration + 1 >= row.test.iterations && row.status != Status::Failed {
+                        row.status = Status::Passed;
+                    }
+
+                    row.iterations.push(IterationResult {
+                        iteration,
+                        passed,
+                        results,
+                        messages,
+                    });
+                }
+            }
+            Progress::Skipped { test, reason } => {
+                if let Some(row) = self.plan.get(test).and_then(|i| self.rows.get_mut(*i)) {
+                    row.status = Status::Skipped;
+                    row.skip_reason = Some(reason);
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        for index in self.plan.iter() {
+            let row = &mut self.rows[*index];
+            if matches!(row.status, Status::Pending | Status::Running(_)) {
+                row.status = if row.iterations.iter().any(|i| !i.passed) {
+                    Status::Failed
+                } else {
+                    Status::NotRun
+                };
+            }
+        }
+    }
+
+    fn count(&self, status: Status) -> usize {
+        self.plan
+            .iter()
+            .filter(|i| self.rows[**i].status == status)
+            .count()
+    }
+
+    fn select(&mut self, offset: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+
+        let current = self.list.selected().unwrap_or(0) as isize;
+        let next = (current + offset).clamp(0, self.rows.len() as isize - 1);
+        self.list.select(Some(next as usize));
+        self.scroll = 0;
+    }
+}
+
+// the failed tests along with the tests they require, so extracted variables are available
+fn rerun_files(rows: &[Row]) -> Vec<String> {
+    let mut selected: HashSet<&str> = rows
+        .iter()
+        .filter(|r| r.status == Status::Failed)
+        .map(|r| r.test.id.as_str())
+        .collect();
+
+    loop {
+        let requirements: Vec<&str> = rows
+            .iter()
+            .filter(|r| selected.contains(r.test.id.as_str()))
+            .filter_map(|r| r.test.requires.as_deref())
+            .filter(|r| !selected.contains(r))
+            .collect();
+
+        if requirements.is_empty() {
+            break;
+        }
+
+        selected.extend(requirements);
+    }
+
+    rows.iter()
+        .filter(|r| selected.contains(r.test.id.as_str()))
+        .map(|r| r.test.filename.clone())
+        .collect()
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+fn body_diff(expected: &Value, actual: &Value) -> Vec<Line<'static>> {
+    let expected = pretty(expected);
+    let actual = pretty(actual);
+
+    similar::TextDiff::from_lines(&expected, &actual)
+        .iter_all_changes()
+        .map(|change| {
+            let text = change.value().trim_end_matches('\n').to_string();
+            match change.tag() {
+                similar::ChangeTag::Delete => {
+                    Line::styled(format!("    - {}", text), Style::new().fg(Color::Red))
+                }
+                similar::ChangeTag::Insert => {
+                    Line::styled(format!("    + {}", text), Style::new().fg(Color::Green))
+                }
+                similar::ChangeTag::Equal => Line::raw(format!("      {}", text)),
+            }
+        })
+        .collect()
+}
+
+fn heading(text: &str) -> Line<'static> {
+    Line::styled(
+        format!("  {}", text),
+        Style::new().add_modifier(Modifier::BOLD),
+    )
+}
+
+fn status_span(passed: bool) -> Span<'static> {
+    if passed {
+        Span::styled("PASSED", Style::new().fg(Color::Green))
+    } else {
+        Span::styled("FAILED", Style::new().fg(Color::Red))
+    }
+}
+
+fn stage_status_span(status: &TestStatus) -> Span<'static> {
+    match status {
+        TestStatus::Passed => status_span(true),
+        TestStatus::Failed => status_span(false),
+        TestStatus::Skipped => Span::styled("SKIPPED", Style::new().fg(Color::DarkGray)),
+        TestStatus::ExpectedFailure => Span::styled("XFAIL", Style::new().fg(Color::Yellow)),
+        TestStatus::UnexpectedPass => Span::styled("XPASS", Style::new().fg(Color::Yellow)),
+        TestStatus::Warning => Span::styled("WARNING", Style::new().fg(Color::Yellow)),
+    }
+}
+
+fn stage_lines(result: &StageResult, lines: &mut Vec<Line<'static>>) {
+    let details = &result.details;
+
+    lines.push(Line::from(vec![
+        Span::styled(
+            format!("{} ", result.label()),
+            Style::new().add_modifier(Modifier::BOLD),
+        ),
+        stage_status_span(&result.status),
+        Span::raw(format!(" {}ms", result.runtime)),
+    ]));
+
+    lines.push(heading("request"));
+    lines.push(Line::raw(format!(
+        "    {} {}",
+        details.request.method, details.request.url
+    )));
+    for header in details.request.headers.iter() {
+        lines.push(Line::raw(format!(
+            "    {}: {}",
+            header.header, header.value
+        )));
+    }
+    if !details.request.body.is_null() {
+        for line in pretty(&details.request.body).lines() {
+            lines.push(Line::raw(format!("    {}", line)));
+        }
+    }
+
+    let actual = match &details.actual {
+        Some(a) => a,
+        None => {
+            lines.push(Line::styled(
+                "  no response was received",
+                Style::new().fg(Color::Red),
+            ));
+            return;
+        }
+    };
+
+    if details.expected.status > 0 {
+        let style = if details.expected.status == actual.status {
+            Style::new()
+        } else {
+            Style::new().fg(Color::Red)
+        };
+        lines.push(Line::styled(
+            format!(
+                "  status: expected {}, actual {}",
+                details.expected.status, actual.status
+            ),
+            style,
+        ));
+    } else {
+        lines.push(Line::raw(format!("  status: {}", actual.status)));
+    }
+
+    if !details.expected.body.is_null() {
+        lines.push(heading("body (- expected, + actual)"));
+        lines.append(&mut body_diff(&details.expected.body, &actual.body));
+    } else if !actual.body.is_null() {
+        lines.push(heading("body"));
+        for line in pretty(&actual.body).lines() {
+            lines.push(Line::raw(format!("    {}", line)));
+        }
+    }
+
+    if let (Some(request), Some(compare)) = (&details.compare_request, &details.compare_actual) {
+        lines.push(heading("compare request"));
+        lines.push(Line::raw(format!("    {} {}", request.method, request.url)));
+        lines.push(Line::raw(format!(
+            "  compare status: {}, actual {}",
+            compare.status, actual.status
+        )));
+        lines.push(heading("body (- compare, + actual)"));
+        lines.append(&mut body_diff(&compare.body, &actual.body));
+    }
+}
+
+fn detail_lines(row: &Row) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+
+    if row.iterations.is_empty() {
+        let text = match (row.status, &row.skip_reason) {
+            (Status::Skipped, Some(reason)) => format!("This test was skipped: {}.", reason),
+            (Status::NotRun, _) => "This test was not run.".to_string(),
+            _ => "Waiting for results.".to_string(),
+        };
+        lines.push(Line::raw(text));
+        return lines;
+    }
+
+    for iteration in row.iterations.iter() {
+        if row.test.iterations > 1 {
+            lines.push(Line::from(vec![
+                Span::styled(
+                    format!(
+                        "Iteration {}/{}{} ",
+                        iteration.iteration + 1,
+                        row.test.iterations,
+                        row.test
+                            .labels
+                            .get(iteration.iteration as usize)
+                            .map(|l| format!(" [{}]", l))
+                            .unwrap_or_default()
+                    ),
+                    Style::new().add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                ),
+                status_span(iteration.passed),
+            ]));
+        }
+
+        for result in iteration.results.iter() {
+            stage_lines(result, &mut lines);
+            lines.push(Line::raw(""));
+        }
+
+        for message in iteration.messages.iter() {
+            for line in message.lines() {
+                lines.push(Line::styled(line.to_string(), Style::new().fg(Color::Red)));
+            }
+        }
+    }
+
+    lines
+}
+
+fn draw(frame: &mut Frame, app: &mut App, running: bool) {
+    let [top, main, help] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [tests, details] =
+        Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)]).areas(main);
+
+    let total = app.plan.len();
+    let passed = app.count(Status::Passed);
+    let failed = app.count(Status::Failed);
+    let not_run = app.count(Status::NotRun);
+    let skipped = app.count(Status::Skipped);
+    let done = passed + failed + not_run + skipped;
+    let gauge = Gauge::default()
+        .block(Block::default().borders(Borders::ALL).title(if running {
+            " Jikken - running "
+        } else {
+            " Jikken - finished "
+        }))
+        .gauge_style(Style::new().fg(if failed > 0 { Color::Red } else { Color::Green }))
+        .ratio(if total == 0 {
+            0.0
+        } else {
+            done as f64 / total as f64
+        })
+        .label(format!(
+            "{}/{} tests, {} passed, {} failed{}{}",
+            done,
+            total,
+            passed,
+            failed,
+            if skipped > 0 {
+                format!(", {} skipped", skipped)
+            } else {
+                String::new()
+            },
+            if not_run > 0 {
+                format!(", {} not run", not_run)
+            } else {
+                String::new()
+            }
+        ));
+    frame.render_widget(gauge, top);
+
+    let items: Vec<ListItem> = app
+        .rows
+        .iter()
+        .map(|row| {
+            let (symbol, style) = match row.status {
+                Status::Pending => ("  ", Style::new().fg(Color::DarkGray)),
+                Status::Running(_) => ("> ", Style::new().fg(Color::Yellow)),
+                Status::Passed => ("✔ ", Style::new().fg(Color::Green)),
+                Status::Failed => ("✘ ", Style::new().fg(Color::Red)),
+                Status::NotRun => ("- ", Style::new().fg(Color::DarkGray)),
+                Status::Skipped => ("- ", Style::new().fg(Color::Yellow)),
+            };
+            let name = match row.status {
+                Status::Running(i) if row.test.iterations > 1 => {
+                    format!("{} ({}/{})", row.test.name, i + 1, row.test.iterations)
+                }
+                _ => row.test.name.clone(),
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(symbol, style),
+                Span::raw(name),
+            ]))
+        })
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(" Tests "))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, tests, &mut app.list);
+
+    let selected = app.list.selected().and_then(|i| app.rows.get(i));
+    let (title, lines) = match selected {
+        Some(row) => (format!(" {} ", row.test.filename), detail_lines(row)),
+        None => (
+            " Details ".to_string(),
+            app.messages
+                .iter()
+                .map(|m| Line::styled(m.clone(), Style::new().fg(Color::Red)))
+                .collect(),
+        ),
+    };
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .wrap(Wrap { trim: false })
+        .scroll((app.scroll, 0));
+    frame.render_widget(paragraph, details);
+
+    frame.render_widget(
+        Line::styled(
+            " ↑/↓ select  PgUp/PgDn scroll  r re-run failed  q quit",
+            Style::new().fg(Color::DarkGray),
+        ),
+        help,
+    );
+}
+
+enum Command {
+    Rerun(Vec<String>),
+    Quit,
+}
+
+// drives the view on its own thread, the executor's futures can't be moved off the main task
+fn interact(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    progress: &mut mpsc::UnboundedReceiver<Progress>,
+    finished: &std::sync::mpsc::Receiver<Option<String>>,
+    commands: &mpsc::UnboundedSender<Command>,
+) -> io::Result<()> {
+    let mut running = true;
+
+    loop {
+        while let Ok(p) = progress.try_recv() {
+            app.update(p);
+        }
+
+        if running {
+            if let Ok(result) = finished.try_recv() {
+                while let Ok(p) = progress.try_recv() {
+                    app.update(p);
+                }
+
+                if let Some(e) = result {
+                    app.messages.push(e);
+                }
+                app.finish();
+                app.messages.append(&mut logger::take_captured());
+                running = false;
+            }
+        }
+
+        terminal.draw(|frame| draw(frame, app, running))?;
+
+        if !event::poll(POLL)? {
+            continue;
+        }
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+            KeyCode::Up | KeyCode::Char('k') => app.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => app.select(1),
+            KeyCode::PageUp => app.scroll = app.scroll.saturating_sub(10),
+            KeyCode::PageDown => app.scroll = app.scroll.saturating_add(10),
+            KeyCode::Char('r') if !running => {
+                let files = rerun_files(&app.rows);
+                if !files.is_empty() {
+                    app.scroll = 0;
+                    running = true;
+                    _ = commands.send(Command::Rerun(files));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    _ = commands.send(Command::Quit);
+    Ok(())
+}
+
+// runs the tests in a full screen view with a progress bar, a list of tests and the details
+// of the selected test's stages. failed tests can be re-run until the view is closed
+pub async fn run_tui(
+    files: Vec<String>,
+    filter: Filter,
+    cli_args: Box<serde_json::Value>,
+    options: RunOptions,
+) -> Result<Report, Box<dyn Error + Send + Sync>> {
+    let (progress_sender, mut progress) = mpsc::unbounded_channel();
+    let (finished_sender, finished) = std::sync::mpsc::channel();
+    let (command_sender, mut commands) = mpsc::unbounded_channel();
+    let options = RunOptions {
+        progress: Some(progress_sender),
+        ..options
+    };
+
+    logger::start_capture();
+    let view = std::thread::spawn(move || {
+        let mut app = App::new();
+        let mut terminal = ratatui::init();
+        let result = interact(
+            &mut terminal,
+            &mut app,
+            &mut progress,
+            &finished,
+            &command_sender,
+        );
+        ratatui::restore();
+        result.map(|_| app)
+    });
+
+    let mut files = files;
+    let mut invalid = None;
+    loop {
+        let run = crate::run_tests(
+            files.clone(),
+            filter.clone(),
+            false,
+            false,
+            cli_args.clone(),
+            options.clone(),
+        );
+
+        // closing the view while tests are running abandons the run
+        let command = tokio::select! {
+            result = run => {
+                // re-runs only load the failed tests, the first run covers every file
+                if let (None, Ok(report)) = (invalid, &result) {
+                    invalid = Some(report.invalid);
+                }
+                _ = finished_sender.send(result.err().map(|e| e.to_string()));
+                commands.recv().await
+            }
+            command = commands.recv() => command,
+        };
+
+        match command {
+            Some(Command::Rerun(f)) => files = f,
+            Some(Command::Quit) | None => break,
+        }
+    }
+
+    let app = match view.join() {
+        Ok(result) => result?,
+        Err(_) => {
+            logger::stop_capture();
+            return Err(Box::new(GenericError {
+                reason: "the interactive view stopped unexpectedly".to_string(),
+            }));
+        }
+    };
+
+    for message in app.messages.iter().chain(logger::stop_capture().iter()) {
+        error!("{}", message);
+    }
+
+    let count = |status: Status| app.rows.iter().filter(|r| r.status == status).count() as u16;
+    let report = Report {
+        run: app.rows.iter().filter(|r| !r.iterations.is_empty()).count() as u16,
+        passed: count(Status::Passed),
+        failed: count(Status::Failed),
+        skipped: count(Status::NotRun) + count(Status::Skipped),
+        invalid: invalid.unwrap_or(0),
+        ..Report::default()
+    };
+    crate::print_summary(&report);
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn row(id: &str, requires: Option<&str>, status: Status) -> Row {
+        Row {
+            test: PlannedTest {
+                id: id.to_string(),
+                name: id.to_string(),
+                filename: format!("{}.jkt", id),
+                requires: requires.map(|r| r.to_string()),
+                iterations: 1,
+                labels: Vec::new(),
+            },
+            status,
+            iterations: Vec::new(),
+            skip_reason: None,
+        }
+    }
+
+    #[test]
+    fn rerun_includes_required_tests() {
+        let rows = vec![
+            row("login", None, Status::Passed),
+            row("profile", Some("login"), Status::Failed),
+            row("orders", Some("profile"), Status::NotRun),
+            row("status", None, Status::Failed),
+        ];
+
+        assert_eq!(
+            vec!["login.jkt", "profile.jkt", "status.jkt"],
+            rerun_files(&rows)
+        );
+    }
+
+    #[test]
+    fn body_diff_marks_changed_lines() {
+        let lines: Vec<String> = body_diff(
+            &json!({"id": 1, "name": "a"}),
+            &json!({"id": 2, "name": "a"}),
+        )
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+
+        assert_eq!(
+            vec![
+                "      {",
+                "    -   \"id\": 1,",
+                "    +   \"id\": 2,",
+                "        \"name\": \"a\"",
+                "      }"
+            ],
+            lines
+        );
+    }
+}
@@ -21,7 +21,7 @@ async fn update(url: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
     info!("Jikken is updating to the latest version...");
     stdout().flush().unwrap();
 
-    let file_name_opt = url.split('/').last();
+    let file_name_opt = url.split('/').next_back();
 
     if file_name_opt.is_none() {
         error!("error: invalid url");
@@ -79,10 +79,13 @@ fn has_newer_version(new_version: String) -> bool {
         let new_segment_opt = new_version_segments[i].parse::<u32>();
         let my_segment_opt = my_version_segments[i].parse::<u32>();
 
-        if new_segment_opt.is_err() || my_segment_opt.is_err() {
-            return false;
-        } else if new_segment_opt.unwrap() > my_segment_opt.unwrap() {
-            return true;
+        match (new_segment_opt, my_segment_opt) {
+            (Ok(new_segment), Ok(my_segment)) => {
+                if new_segment > my_segment {
+                    return true;
+                }
+            }
+            _ => return false,
         }
     }
 
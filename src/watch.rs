@@ -0,0 +1,310 @@
+use crate::executor::select::Filter;
+use crate::executor::{suite, RunOptions};
+use crate::test;
+use crate::test::{dataset, variable};
+use log::{error, info, trace};
+use notify::{RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const CONFIG_FILE: &str = ".jikken";
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// what a test file depends on, used to work out which tests a change affects
+#[derive(Debug, Clone)]
+struct TestInfo {
+    filename: String,
+    path: PathBuf,
+    id: String,
+    requires: Option<String>,
+    // variable, dataset, graphql query and grpc descriptor files
+    variable_files: Vec<PathBuf>,
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn load_info(filename: &str) -> Option<TestInfo> {
+    let file = match test::file::load(filename) {
+        Ok(f) => f,
+        Err(e) => {
+            trace!("unable to load test file ({}): {}", filename, e);
+            return None;
+        }
+    };
+
+    let source_path = variable::parse_source_path(filename);
+    let dataset_file = file
+        .dataset
+        .as_ref()
+        .map(|d| normalize(Path::new(&dataset::resolve_path(&d.file, filename))));
+    let requests = file.requests();
+    let query_files = requests
+        .iter()
+        .filter_map(|r| r.graphql.as_ref().and_then(|g| g.file.as_ref()));
+    let descriptor_files = requests.iter().filter_map(|r| {
+        r.grpc
+            .as_ref()
+            .and_then(|g| g.proto.as_ref().or(g.descriptor_set.as_ref()))
+    });
+    let variable_files = file
+        .variables
+        .iter()
+        .flatten()
+        .chain(
+            file.stages
+                .iter()
+                .flatten()
+                .flat_map(|s| s.variables.iter().flatten()),
+        )
+        .filter_map(|v| v.file.as_ref())
+        .chain(query_files)
+        .chain(descriptor_files)
+        .map(|f| normalize(Path::new(&variable::resolve_file_path(f, &source_path))))
+        .chain(dataset_file)
+        .collect();
+
+    Some(TestInfo {
+        filename: filename.to_string(),
+        path: normalize(Path::new(filename)),
+        id: file
+            .id
+            .clone()
+            .unwrap_or_else(|| file.generate_id())
+            .to_lowercase(),
+        requires: file.requires.map(|r| r.to_lowercase()),
+        variable_files,
+    })
+}
+
+// tests that changed, or whose variable files changed, along with the tests that require
+// them. the tests they require are included too so extracted variables are available
+fn affected_tests(tests: &[TestInfo], changed: &HashSet<PathBuf>) -> Vec<String> {
+    let mut selected: HashSet<String> = tests
+        .iter()
+        .filter(|t| {
+            changed.contains(&t.path) || t.variable_files.iter().any(|f| changed.contains(f))
+        })
+        .map(|t| t.id.clone())
+        .collect();
+
+    loop {
+        let dependents: Vec<String> = tests
+            .iter()
+            .filter(|t| !selected.contains(&t.id))
+            .filter(|t| {
+                t.requires
+                    .as_ref()
+                    .map(|r| selected.contains(r))
+                    .unwrap_or(false)
+            })
+            .map(|t| t.id.clone())
+            .collect();
+
+        if dependents.is_empty() {
+            break;
+        }
+
+        selected.extend(dependents);
+    }
+
+    loop {
+        let requirements: Vec<String> = tests
+            .iter()
+            .filter(|t| selected.contains(&t.id))
+            .filter_map(|t| t.requires.clone())
+            .filter(|r| !selected.contains(r))
+            .collect();
+
+        if requirements.is_empty() {
+            break;
+        }
+
+        selected.extend(requirements);
+    }
+
+    tests
+        .iter()
+        .filter(|t| selected.contains(&t.id))
+        .map(|t| t.filename.clone())
+        .collect()
+}
+
+// directories are watched rather than files, editors often save by replacing the file
+fn watch_targets(
+    paths: &[String],
+    recursive: bool,
+    tests: &[TestInfo],
+) -> Vec<(PathBuf, RecursiveMode)> {
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+
+    let mut targets = vec![(normalize(Path::new(".")), RecursiveMode::NonRecursive)];
+
+    for path in paths.iter() {
+        let p = Path::new(path);
+        if p.is_dir() {
+            targets.push((normalize(p), mode));
+        } else if p.is_file() {
+            targets.push((
+                normalize(p.parent().unwrap_or(Path::new("."))),
+                RecursiveMode::NonRecursive,
+            ));
+        } else {
+            // glob patterns are matched against the current directory
+            targets.push((normalize(Path::new(".")), mode));
+        }
+    }
+
+    for file in tests.iter().flat_map(|t| t.variable_files.iter()) {
+        if let Some(parent) = file.parent() {
+            targets.push((parent.to_path_buf(), RecursiveMode::NonRecursive));
+        }
+    }
+
+    targets
+}
+
+fn clear_console() {
+    info!("\x1b[2J\x1b[1;1H");
+}
+
+// runs the tests, then re-runs the affected ones each time a test file, a variable file or
+// the config file changes, until interrupted
+pub async fn watch_tests(
+    paths: Vec<String>,
+    filter: Filter,
+    recursive: bool,
+    cli_args: Box<serde_json::Value>,
+    options: RunOptions,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let paths = if paths.is_empty() {
+        vec![".".to_string()]
+    } else {
+        paths
+    };
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+        if let Ok(event) = result {
+            if !event.kind.is_access() {
+                _ = sender.send(event.paths);
+            }
+        }
+    })?;
+
+    let config_path = normalize(Path::new(CONFIG_FILE));
+    let mut watched: HashSet<PathBuf> = HashSet::new();
+    let mut changed: Option<HashSet<PathBuf>> = None;
+
+    loop {
+        let files = crate::get_files(paths.clone(), recursive).await?;
+        let tests: Vec<TestInfo> = files.iter().filter_map(|f| load_info(f)).collect();
+
+        for (target, mode) in watch_targets(&paths, recursive, &tests) {
+            if watched.insert(target.clone()) {
+                if let Err(e) = watcher.watch(&target, mode) {
+                    error!("unable to watch `{}`: {}", target.display(), e);
+                }
+            }
+        }
+
+        let selected = match &changed {
+            Some(c) => affected_tests(&tests, c),
+            None => files,
+        };
+
+        // changes to unrelated files in the watched directories are ignored quietly
+        if changed.is_none() || !selected.is_empty() {
+            clear_console();
+            let report = crate::run_tests(
+                selected,
+                filter.clone(),
+                false,
+                false,
+                cli_args.clone(),
+                options.clone(),
+            )
+            .await?;
+
+            // Ctrl-C during a run stops watching too
+            if report.interrupted {
+                return Ok(());
+            }
+
+            info!("Watching for changes, press Ctrl-C to stop.\n");
+        }
+
+        // wait for a change, then gather the rest of the burst an editor save produces
+        let mut batch: HashSet<PathBuf> = tokio::select! {
+            received = receiver.recv() => match received {
+                Some(p) => p.into_iter().collect(),
+                None => return Ok(()),
+            },
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        };
+
+        while let Ok(Some(p)) = tokio::time::timeout(DEBOUNCE, receiver.recv()).await {
+            batch.extend(p);
+        }
+
+        // config and suite changes can alter variables and settings for every test
+        let suite_changed = batch
+            .iter()
+            .any(|p| suite::is_suite_file(&p.to_string_lossy()));
+        changed = if batch.contains(&config_path) || suite_changed {
+            None
+        } else {
+            Some(batch)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, requires: Option<&str>, variable_file: Option<&str>) -> TestInfo {
+        TestInfo {
+            filename: format!("{}.jkt", name),
+            path: PathBuf::from(format!("/tests/{}.jkt", name)),
+            id: name.to_string(),
+            requires: requires.map(|r| r.to_string()),
+            variable_files: variable_file.map(PathBuf::from).into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn changes_select_dependents_and_their_requirements() {
+        let tests = vec![
+            info("login", None, None),
+            info("profile", Some("login"), None),
+            info("orders", Some("profile"), Some("/data/orders.json")),
+            info("status", None, None),
+        ];
+
+        let changed = HashSet::from([PathBuf::from("/tests/profile.jkt")]);
+        assert_eq!(
+            vec!["login.jkt", "profile.jkt", "orders.jkt"],
+            affected_tests(&tests, &changed)
+        );
+
+        let changed = HashSet::from([PathBuf::from("/data/orders.json")]);
+        assert_eq!(
+            vec!["login.jkt", "profile.jkt", "orders.jkt"],
+            affected_tests(&tests, &changed)
+        );
+
+        let changed = HashSet::from([PathBuf::from("/tests/notes.txt")]);
+        assert!(affected_tests(&tests, &changed).is_empty());
+    }
+}